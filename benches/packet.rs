@@ -0,0 +1,112 @@
+//! Parsing, encoding and reply assembly on packets like those a real client sends.
+//!
+//! Run with `cargo bench`.
+
+use std::hint::black_box;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::Duration;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+
+use dhcp4r::options::{self, DhcpOption, MessageType, OfferConfig};
+use dhcp4r::packet::{Packet, PacketRef};
+use dhcp4r::server::{Handler, MockTransport, Server};
+
+const CHADDR: [u8; 6] = [0x52, 0x54, 0x00, 0x12, 0x34, 0x56];
+
+/// The identifying options a typical Linux client adds to its DISCOVERs and REQUESTs.
+fn client_options(p: &mut Packet) {
+    let mut client_id = vec![1];
+    client_id.extend_from_slice(&CHADDR);
+    p.options.extend([DhcpOption {
+                          code: options::CLIENT_IDENTIFIER,
+                          data: client_id,
+                      },
+                      DhcpOption::string(options::HOST_NAME, "build-worker-07"),
+                      DhcpOption::string(options::VENDOR_CLASS_IDENTIFIER, "dhcpcd-9.4.1:Linux"),
+                      DhcpOption {
+                          code: options::MAXIMUM_DHCP_MESSAGE_SIZE,
+                          data: 1472u16.to_be_bytes().to_vec(),
+                      }]);
+}
+
+fn discover() -> Packet {
+    let mut p = Packet::discover(0x3903f326, CHADDR);
+    client_options(&mut p);
+    p
+}
+
+fn request() -> Packet {
+    let mut p = Packet::request(0x3903f326,
+                                CHADDR,
+                                Ipv4Addr::new(192, 168, 1, 100),
+                                Ipv4Addr::new(192, 168, 1, 1));
+    client_options(&mut p);
+    p
+}
+
+fn offer_config() -> OfferConfig {
+    OfferConfig {
+        subnet: Ipv4Addr::new(255, 255, 255, 0),
+        router: Ipv4Addr::new(192, 168, 1, 1),
+        dns: vec![Ipv4Addr::new(192, 168, 1, 1), Ipv4Addr::new(9, 9, 9, 9)],
+        lease: Duration::from_secs(86400),
+        domain: Some("lan.example.com".to_string()),
+    }
+}
+
+/// Offers and acknowledges one fixed address, as a server with a single free lease would.
+struct Responder(OfferConfig);
+
+impl Handler for Responder {
+    fn handle_discover(&mut self, server: &Server, packet: Packet) {
+        let opts = options::standard_offer(&self.0);
+        let _ = server.reply(MessageType::Offer, opts, [192, 168, 1, 100], packet);
+    }
+
+    fn handle_request_msg(&mut self, server: &Server, packet: Packet) {
+        let opts = options::standard_offer(&self.0);
+        let _ = server.reply(MessageType::Ack, opts, [192, 168, 1, 100], packet);
+    }
+}
+
+fn parse(c: &mut Criterion) {
+    for (name, p) in [("discover", discover()), ("request", request())] {
+        let bytes = p.to_bytes();
+        c.bench_function(&format!("from_bytes/{}", name),
+                         |b| b.iter(|| Packet::from_bytes(black_box(&bytes)).unwrap()));
+        c.bench_function(&format!("packet_ref/{}", name),
+                         |b| b.iter(|| PacketRef::parse(black_box(&bytes)).unwrap().xid()));
+    }
+}
+
+fn serialize(c: &mut Criterion) {
+    for (name, p) in [("discover", discover()), ("request", request())] {
+        c.bench_function(&format!("to_bytes/{}", name), |b| b.iter(|| black_box(&p).to_bytes()));
+        let mut buf = [0; 1500];
+        c.bench_function(&format!("encode/{}", name),
+                         |b| b.iter(|| black_box(&p).encode(&mut buf).len()));
+    }
+}
+
+/// Receiving, parsing and dispatching a request, and assembling and encoding the reply, through
+/// a `MockTransport` so no socket is involved.
+fn reply(c: &mut Criterion) {
+    let client = "0.0.0.0:68".parse::<SocketAddr>().unwrap();
+    for (name, p) in [("offer", discover()), ("ack", request())] {
+        let bytes = p.to_bytes();
+        c.bench_function(&format!("server/{}", name), |b| {
+            b.iter(|| {
+                let mock = MockTransport::new();
+                mock.push_request(&bytes, client);
+                Server::builder()
+                    .server_ip([192, 168, 1, 1])
+                    .serve(mock.clone(), Responder(offer_config()));
+                mock.take_replies()
+            })
+        });
+    }
+}
+
+criterion_group!(benches, parse, serialize, reply);
+criterion_main!(benches);
@@ -1,7 +1,10 @@
+// Kept as originally written rather than rewritten for clippy
+#![allow(clippy::single_match)]
+
 extern crate dhcp4r;
 extern crate time;
 
-use std::net::{UdpSocket,Ipv4Addr};
+use std::net::{UdpSocket,Ipv4Addr,SocketAddr};
 use dhcp4r::{packet, options, server};
 
 fn main() {
@@ -11,21 +14,15 @@ fn main() {
 struct MyServer {}
 
 impl server::Handler for MyServer {
-    fn handle_request(&mut self, _: &server::Server, in_packet: packet::Packet) {
+    fn handle_request_from(&mut self, _: &server::Server, in_packet: packet::Packet, _: SocketAddr) {
         match in_packet.message_type() {
             Ok(options::MessageType::Request) => {
-                let req_ip = match in_packet.option(options::REQUESTED_IP_ADDRESS) {
+                let req_ip = match in_packet.requested_ip() {
+                    Some(ip) => ip.octets(),
                     None => in_packet.ciaddr,
-                    Some(x) => {
-                        if x.len() != 4 {
-                            return;
-                        } else {
-                            [x[0], x[1], x[2], x[3]]
-                        }
-                    }
                 };
                 println!("{}\t{}\t{}\tOnline", time::now().strftime("%Y-%m-%dT%H:%M:%S").unwrap(),
-                 chaddr(&in_packet.chaddr), Ipv4Addr::from(req_ip));
+                 chaddr(&in_packet.chaddr()), Ipv4Addr::from(req_ip));
             }
             _ => {}
         }
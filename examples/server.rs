@@ -1,7 +1,10 @@
+// Kept as originally written rather than rewritten for clippy
+#![allow(clippy::needless_return, clippy::op_ref, clippy::manual_range_contains)]
+
 #[macro_use(u32_bytes, bytes_u32)]
 extern crate dhcp4r;
 
-use std::net::UdpSocket;
+use std::net::{Ipv4Addr, UdpSocket};
 use std::time::{Duration, Instant};
 use std::collections::HashMap;
 use std::ops::Add;
@@ -12,13 +15,13 @@ use dhcp4r::{packet, options, server};
 const SERVER_IP: [u8; 4] = [192, 168, 0, 76];
 const IP_START: [u8; 4] = [192, 168, 0, 180];
 const SUBNET_MASK: [u8; 4] = [255, 255, 255, 0];
-const DNS_IPS: [u8; 8] = [8, 8, 8, 8, 8, 8, 4, 4]; // google dns servers
+// google dns servers
+const DNS_IPS: [Ipv4Addr; 2] = [Ipv4Addr::new(8, 8, 8, 8), Ipv4Addr::new(8, 8, 4, 4)];
 const ROUTER_IP: [u8; 4] = [192, 168, 0, 254];
 const LEASE_DURATION_SECS: u32 = 7200;
 const LEASE_NUM: u32 = 100;
 
 // Derrived constants
-const LEASE_DURATION_BYTES: [u8; 4] = u32_bytes!(LEASE_DURATION_SECS);
 const IP_START_NUM: u32 = bytes_u32!(IP_START);
 
 fn main() {
@@ -41,85 +44,79 @@ struct MyServer {
 }
 
 impl server::Handler for MyServer {
-    fn handle_request(&mut self,
-                      server: &server::Server,
-                      in_packet: packet::Packet) {
-        match in_packet.message_type() {
-            Ok(options::MessageType::Discover) => {
-                // Prefer client's choice if available
-                if let Some(r) = in_packet.option(options::REQUESTED_IP_ADDRESS) {
-                    if r.len() == 4 && self.available(&in_packet.chaddr, bytes_u32!(r)) {
-                        reply(server,
-                              options::MessageType::Offer,
-                              in_packet,
-                              [r[0], r[1], r[2], r[3]]);
-                        return;
-                    }
-                }
-                // Otherwise prefer existing (including expired if available)
-                if let Some(ip) = self.current_lease(&in_packet.chaddr) {
-                    reply(server,
-                          options::MessageType::Offer,
-                          in_packet,
-                          u32_bytes!(ip));
-                    return;
-                }
-                // Otherwise choose a free ip if available
-                for _ in 0..LEASE_NUM {
-                    self.last_lease = (self.last_lease + 1) % LEASE_NUM;
-                    if self.available(&in_packet.chaddr, IP_START_NUM + &self.last_lease) {
-                        reply(server,
-                              options::MessageType::Offer,
-                              in_packet,
-                              u32_bytes!(IP_START_NUM + &self.last_lease));
-                        break;
-                    }
-                }
-            }
-
-            Ok(options::MessageType::Request) => {
-                // Ignore requests to alternative DHCP server
-                if !server.for_this_server(&in_packet) {
-                    return;
-                }
-                let req_ip = match in_packet.option(options::REQUESTED_IP_ADDRESS) {
-                    None => in_packet.ciaddr,
-                    Some(x) => {
-                        if x.len() != 4 {
-                            return;
-                        } else {
-                            [x[0], x[1], x[2], x[3]]
-                        }
-                    }
-                };
-                let req_ip_num = bytes_u32!(req_ip);
-                if !&self.available(&in_packet.chaddr, req_ip_num) {
-                    nak(server, in_packet, b"Requested IP not available");
-                    return;
-                }
-                self.leases.insert(req_ip_num,
-                                   (in_packet.chaddr, Instant::now().add(self.lease_duration)));
-                reply(server, options::MessageType::Ack, in_packet, req_ip);
+    fn handle_discover(&mut self, server: &server::Server, in_packet: packet::Packet) {
+        // Prefer client's choice if available
+        if let Some(r) = in_packet.requested_ip() {
+            if self.available(&in_packet.chaddr(), u32::from(r)) {
+                reply(server, options::MessageType::Offer, in_packet, r.octets());
+                return;
             }
-
-            Ok(options::MessageType::Release) |
-            Ok(options::MessageType::Decline) => {
-                // Ignore requests to alternative DHCP server
-                if !server.for_this_server(&in_packet) {
-                    return;
-                }
-                if let Some(ip) = self.current_lease(&in_packet.chaddr) {
-                    self.leases.remove(&ip);
-                }
+        }
+        // Otherwise prefer existing (including expired if available)
+        if let Some(ip) = self.current_lease(&in_packet.chaddr()) {
+            reply(server,
+                  options::MessageType::Offer,
+                  in_packet,
+                  u32_bytes!(ip));
+            return;
+        }
+        // Otherwise choose a free ip if available
+        for _ in 0..LEASE_NUM {
+            self.last_lease = (self.last_lease + 1) % LEASE_NUM;
+            if self.available(&in_packet.chaddr(), IP_START_NUM + &self.last_lease) {
+                reply(server,
+                      options::MessageType::Offer,
+                      in_packet,
+                      u32_bytes!(IP_START_NUM + &self.last_lease));
+                break;
             }
+        }
+    }
 
-            // TODO - not necessary but support for dhcp4r::INFORM might be nice
-            _ => {}
+    fn handle_request_msg(&mut self, server: &server::Server, in_packet: packet::Packet) {
+        // Ignore requests to alternative DHCP server
+        if !server.for_this_server(&in_packet) {
+            return;
         }
+        let req_ip = match in_packet.requested_ip() {
+            Some(ip) => ip.octets(),
+            None => in_packet.ciaddr,
+        };
+        let req_ip_num = bytes_u32!(req_ip);
+        if !&self.available(&in_packet.chaddr(), req_ip_num) {
+            let _ = server.nak("Requested IP not available", in_packet);
+            return;
+        }
+        self.leases.insert(req_ip_num,
+                           (in_packet.chaddr(), Instant::now().add(self.lease_duration)));
+        reply(server, options::MessageType::Ack, in_packet, req_ip);
+    }
+
+    fn handle_release(&mut self, server: &server::Server, in_packet: packet::Packet) {
+        self.release(server, in_packet);
+    }
+
+    fn handle_decline(&mut self, server: &server::Server, in_packet: packet::Packet) {
+        self.release(server, in_packet);
+    }
+
+    fn handle_inform(&mut self, server: &server::Server, in_packet: packet::Packet) {
+        // The lease time is dropped from replies to an INFORM
+        let _ = server.reply_inform(options::standard_offer(&config()), in_packet);
     }
 }
 
 impl MyServer {
+    fn release(&mut self, server: &server::Server, in_packet: packet::Packet) {
+        // Ignore requests to alternative DHCP server
+        if !server.for_this_server(&in_packet) {
+            return;
+        }
+        if let Some(ip) = self.current_lease(&in_packet.chaddr()) {
+            self.leases.remove(&ip);
+        }
+    }
+
     fn available(&self, chaddr: &[u8; 6], pos: u32) -> bool {
         return pos >= IP_START_NUM && pos < IP_START_NUM + LEASE_NUM &&
                match self.leases.get(&pos) {
@@ -142,33 +139,16 @@ fn reply(s: &server::Server,
          msg_type: options::MessageType,
          req_packet: packet::Packet,
          offer_ip: [u8; 4]) {
-    let _ = s.reply(msg_type,
-                    vec![options::DhcpOption {
-                             code: options::IP_ADDRESS_LEASE_TIME,
-                             data: &LEASE_DURATION_BYTES,
-                         },
-                         options::DhcpOption {
-                             code: options::SUBNET_MASK,
-                             data: &SUBNET_MASK,
-                         },
-                         options::DhcpOption {
-                             code: options::ROUTER,
-                             data: &ROUTER_IP,
-                         },
-                         options::DhcpOption {
-                             code: options::DOMAIN_NAME_SERVER,
-                             data: &DNS_IPS,
-                         }],
-                    offer_ip,
-                    req_packet);
+    let _ = s.reply(msg_type, options::standard_offer(&config()), offer_ip, req_packet);
 }
 
-fn nak(s: &server::Server, req_packet: packet::Packet, message: &[u8]) {
-    let _ = s.reply(options::MessageType::Nak,
-                    vec![options::DhcpOption {
-                             code: options::MESSAGE,
-                             data: message,
-                         }],
-                    [0, 0, 0, 0],
-                    req_packet);
+/// Network configuration handed to every client.
+fn config() -> options::OfferConfig {
+    options::OfferConfig {
+        subnet: Ipv4Addr::from(SUBNET_MASK),
+        router: Ipv4Addr::from(ROUTER_IP),
+        dns: DNS_IPS.to_vec(),
+        lease: Duration::new(LEASE_DURATION_SECS as u64, 0),
+        domain: None,
+    }
 }
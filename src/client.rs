@@ -0,0 +1,325 @@
+//! A minimal DHCP client that acquires a lease by running the DISCOVER/REQUEST exchange
+//! (RFC 2131 section 4.4): INIT -> SELECTING -> REQUESTING -> BOUND.
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::options::{self, MessageType};
+use crate::packet::Packet;
+use crate::server::SERVER_PORT;
+
+/// UDP port DHCP clients listen on.
+pub const CLIENT_PORT: u16 = 68;
+
+/// Retransmission delays start at 4 seconds and double up to 64 seconds (RFC 2131 section 4.1).
+const INITIAL_RETRANSMIT: Duration = Duration::from_secs(4);
+const MAX_RETRANSMIT: Duration = Duration::from_secs(64);
+
+/// Address configuration acquired from a server. See `Lease::from_ack`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lease {
+    pub ip: Ipv4Addr,
+    pub lease_time: Option<Duration>,
+    /// The SERVER_IDENTIFIER, or 0.0.0.0 if the server sent none.
+    pub server: Ipv4Addr,
+    /// The next server in the boot process, from siaddr, or 0.0.0.0.
+    pub next_server: Ipv4Addr,
+    pub subnet_mask: Option<Ipv4Addr>,
+    /// Routers in order of preference, empty if none were sent.
+    pub routers: Vec<Ipv4Addr>,
+    /// DNS servers in order of preference, empty if none were sent.
+    pub dns: Vec<Ipv4Addr>,
+}
+
+impl Lease {
+    /// Collects the configuration a client needs from a DHCPACK: the address assigned in yiaddr,
+    /// the next server in siaddr, and the lease time, server identifier, subnet mask, routers
+    /// and DNS servers from the options. Options that are malformed are left out as if absent.
+    /// `None` if `ack` is not an ACK.
+    pub fn from_ack(ack: &Packet) -> Option<Lease> {
+        if ack.message_type() != Ok(MessageType::Ack) {
+            return None;
+        }
+        let ips = |code| {
+            ack.option(code).and_then(|d| options::decode_ips(d).ok()).unwrap_or_default()
+        };
+        Some(Lease {
+            ip: Ipv4Addr::from(ack.yiaddr),
+            lease_time: match ack.option(options::IP_ADDRESS_LEASE_TIME) {
+                Some(d) if d.len() == 4 => Some(Duration::from_secs(bytes_u32!(d) as u64)),
+                _ => None,
+            },
+            server: ack.server_identifier().unwrap_or(Ipv4Addr::UNSPECIFIED),
+            next_server: Ipv4Addr::from(ack.siaddr),
+            subnet_mask: ack.option(options::SUBNET_MASK).and_then(options::bytes_to_ip),
+            routers: ips(options::ROUTER),
+            dns: ips(options::DOMAIN_NAME_SERVER),
+        })
+    }
+}
+
+pub enum ClientError {
+    Io(io::Error),
+    /// No acceptable reply arrived before the timeout.
+    Timeout,
+    /// The server refused the request with a DHCPNAK.
+    Nak,
+}
+
+impl fmt::Debug for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ClientError::Io(ref e) => write!(f, "Io({:?})", e),
+            ClientError::Timeout => write!(f, "Timeout"),
+            ClientError::Nak => write!(f, "Nak"),
+        }
+    }
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ClientError::Io(ref e) => write!(f, "{}", e),
+            ClientError::Timeout => write!(f, "Timed out waiting for server"),
+            ClientError::Nak => write!(f, "Server sent DHCPNAK"),
+        }
+    }
+}
+
+impl Error for ClientError {}
+
+impl From<io::Error> for ClientError {
+    fn from(e: io::Error) -> ClientError {
+        ClientError::Io(e)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Init,
+    Selecting,
+    Requesting,
+    Bound,
+}
+
+pub struct Client {
+    socket: UdpSocket,
+    mac: [u8; 6],
+    server: SocketAddr,
+    state: State,
+    xid: u32,
+}
+
+impl Client {
+    /// Creates a client for the given hardware address.
+    /// The socket should be bound to port 68 and have broadcast enabled.
+    pub fn new(socket: UdpSocket, mac: [u8; 6]) -> Client {
+        Client {
+            socket,
+            mac,
+            server: SocketAddr::new(IpAddr::V4(Ipv4Addr::BROADCAST), SERVER_PORT),
+            state: State::Init,
+            xid: 0,
+        }
+    }
+
+    /// Overrides where requests are sent, by default the broadcast address on port 67.
+    pub fn set_server_addr(&mut self, addr: SocketAddr) {
+        self.server = addr;
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    /// Runs the full exchange, accepting the first DHCPOFFER received and requesting it.
+    /// Each message is retransmitted with exponential backoff until a reply arrives or `timeout`
+    /// (covering the whole exchange) expires.
+    pub fn acquire(&mut self, timeout: Duration) -> Result<Lease, ClientError> {
+        let deadline = Instant::now() + timeout;
+        self.xid = new_xid(&self.mac);
+
+        self.state = State::Selecting;
+        let discover = Packet::discover(self.xid, self.mac);
+        let offer = match self.exchange(&discover, deadline, |t| matches!(t, MessageType::Offer)) {
+            Ok(p) => p,
+            Err(e) => {
+                self.state = State::Init;
+                return Err(e);
+            }
+        };
+        let server = offer.server_identifier();
+
+        self.state = State::Requesting;
+        let mut request = Packet::request(self.xid,
+                                          self.mac,
+                                          Ipv4Addr::from(offer.yiaddr),
+                                          server.unwrap_or(Ipv4Addr::UNSPECIFIED));
+        if server.is_none() {
+            request.remove_option(options::SERVER_IDENTIFIER);
+        }
+        let reply = self.exchange(&request,
+                                  deadline,
+                                  |t| matches!(t, MessageType::Ack | MessageType::Nak));
+        let ack = match reply {
+            Ok(p) => p,
+            Err(e) => {
+                self.state = State::Init;
+                return Err(e);
+            }
+        };
+        if let Ok(MessageType::Nak) = ack.message_type() {
+            self.state = State::Init;
+            return Err(ClientError::Nak);
+        }
+
+        let mut lease = Lease::from_ack(&ack).expect("accepted as an ACK");
+        if lease.server.is_unspecified() {
+            lease.server = server.unwrap_or(Ipv4Addr::UNSPECIFIED);
+        }
+        self.state = State::Bound;
+        Ok(lease)
+    }
+
+    /// Sends `p` and waits for a reply to it whose message type satisfies `accept`,
+    /// retransmitting with backoff until `deadline`.
+    fn exchange<F>(&self, p: &Packet, deadline: Instant, accept: F) -> Result<Packet, ClientError>
+        where F: Fn(MessageType) -> bool
+    {
+        let bytes = p.to_bytes();
+        let mut in_buf = [0; 1500];
+        let mut delay = INITIAL_RETRANSMIT;
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(ClientError::Timeout);
+            }
+            self.socket.send_to(&bytes, self.server)?;
+            let retransmit = (now + delay).min(deadline);
+            delay = (delay * 2).min(MAX_RETRANSMIT);
+
+            loop {
+                let now = Instant::now();
+                if now >= retransmit {
+                    break;
+                }
+                self.socket.set_read_timeout(Some(retransmit - now))?;
+                let l = match self.socket.recv_from(&mut in_buf) {
+                    Ok((l, _)) => l,
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock ||
+                                  e.kind() == io::ErrorKind::TimedOut => break,
+                    Err(e) => return Err(e.into()),
+                };
+                if let Ok(reply) = Packet::from_bytes(&in_buf[..l]) {
+                    if reply.reply && reply.xid == p.xid && reply.chaddr() == p.chaddr() {
+                        if let Ok(t) = reply.message_type() {
+                            if accept(t) {
+                                return Ok(reply);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Derives a transaction id from the clock and hardware address.
+fn new_xid(mac: &[u8; 6]) -> u32 {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.subsec_nanos()).unwrap_or(0);
+    nanos ^ (bytes_u32!(mac[2..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::DhcpOption;
+    use crate::packet::PacketBuilder;
+    use std::thread;
+
+    #[test]
+    fn acquire_lease() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server_addr = server.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let mut buf = [0; 1500];
+            for reply_type in [MessageType::Offer, MessageType::Ack] {
+                let (l, src) = server.recv_from(&mut buf).unwrap();
+                let req = Packet::from_bytes(&buf[..l]).unwrap();
+                let reply = PacketBuilder::new()
+                    .reply(true)
+                    .xid(bytes_u32!(req.xid))
+                    .chaddr(req.chaddr())
+                    .yiaddr(Ipv4Addr::new(192, 168, 0, 50))
+                    .message_type(reply_type)
+                    .option(DhcpOption {
+                        code: options::SERVER_IDENTIFIER,
+                        data: vec![192, 168, 0, 1],
+                    })
+                    .option(DhcpOption::from_duration(options::IP_ADDRESS_LEASE_TIME,
+                                                      Duration::from_secs(3600)))
+                    .build();
+                server.send_to(&reply.to_bytes(), src).unwrap();
+            }
+        });
+
+        let mut c = Client::new(UdpSocket::bind("127.0.0.1:0").unwrap(), [1, 2, 3, 4, 5, 6]);
+        c.set_server_addr(server_addr);
+        let lease = c.acquire(Duration::from_secs(10)).unwrap();
+        handle.join().unwrap();
+
+        assert_eq!(c.state(), State::Bound);
+        assert_eq!(lease.ip, Ipv4Addr::new(192, 168, 0, 50));
+        assert_eq!(lease.server, Ipv4Addr::new(192, 168, 0, 1));
+        assert_eq!(lease.lease_time, Some(Duration::from_secs(3600)));
+    }
+
+    #[test]
+    fn lease_from_ack() {
+        let req = Packet::request(5,
+                                  [1, 2, 3, 4, 5, 6],
+                                  Ipv4Addr::new(10, 0, 0, 5),
+                                  Ipv4Addr::new(10, 0, 0, 1));
+        let mut ack = req.clone_for_reply(MessageType::Ack);
+        ack.yiaddr = [10, 0, 0, 5];
+        ack.siaddr = [10, 0, 0, 2];
+        ack.options.extend(options::standard_offer(&options::OfferConfig {
+            subnet: Ipv4Addr::new(255, 255, 255, 0),
+            router: Ipv4Addr::new(10, 0, 0, 1),
+            dns: vec![Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(9, 9, 9, 9)],
+            lease: Duration::from_secs(3600),
+            domain: None,
+        }));
+        ack.options.push(DhcpOption {
+            code: options::SERVER_IDENTIFIER,
+            data: vec![10, 0, 0, 1],
+        });
+        let lease = Lease::from_ack(&ack).unwrap();
+        assert_eq!(lease,
+                   Lease {
+                       ip: Ipv4Addr::new(10, 0, 0, 5),
+                       lease_time: Some(Duration::from_secs(3600)),
+                       server: Ipv4Addr::new(10, 0, 0, 1),
+                       next_server: Ipv4Addr::new(10, 0, 0, 2),
+                       subnet_mask: Some(Ipv4Addr::new(255, 255, 255, 0)),
+                       routers: vec![Ipv4Addr::new(10, 0, 0, 1)],
+                       dns: vec![Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(9, 9, 9, 9)],
+                   });
+
+        // Malformed options read as absent
+        ack.options.retain(|o| o.code != options::ROUTER && o.code != options::SERVER_IDENTIFIER);
+        ack.options.push(DhcpOption {
+            code: options::ROUTER,
+            data: vec![10, 0, 0],
+        });
+        let lease = Lease::from_ack(&ack).unwrap();
+        assert!(lease.routers.is_empty());
+        assert_eq!(lease.server, Ipv4Addr::UNSPECIFIED);
+
+        assert!(Lease::from_ack(&req.clone_for_reply(MessageType::Offer)).is_none());
+        assert!(Lease::from_ack(&req).is_none());
+    }
+}
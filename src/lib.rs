@@ -1,10 +1,11 @@
-#[macro_use]
-extern crate enum_primitive_derive;
-extern crate num_traits;
+//! DHCPv4 packet and option handling, with a server framework and a minimal client.
+//!
+//! The `packet` and `options` modules only need `alloc`. Disabling the default `std` feature
+//! builds the crate as `no_std`, without the `client` and `server` modules.
 
-pub mod options;
-pub mod packet;
-pub mod server;
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
 
 /// Converts a u32 to 4 bytes (Big endian)
 #[macro_export]
@@ -22,8 +23,62 @@ macro_rules! bytes_u32 {
     };
 }
 
+/// Converts a u16 to 2 bytes (Big endian)
+#[macro_export]
+macro_rules! u16_bytes {
+    ( $x:expr ) => {
+        [($x >> 8) as u8, $x as u8]
+    };
+}
+
+/// Converts 2 bytes to a u16 (Big endian)
+#[macro_export]
+macro_rules! bytes_u16 {
+    ( $x:expr ) => {
+        ($x[0] as u16) * (1 << 8) + ($x[1] as u16)
+    };
+}
+
+/// Logs through the `log` crate when the `log` feature is enabled. Otherwise the arguments are
+/// type checked but never evaluated, so disabled logging costs nothing. Only the std modules
+/// log.
+#[cfg(all(feature = "std", feature = "log"))]
+macro_rules! log_at {
+    ( $level:ident, $($arg:tt)* ) => {
+        log::$level!($($arg)*)
+    };
+}
+
+#[cfg(all(feature = "std", not(feature = "log")))]
+macro_rules! log_at {
+    ( $level:ident, $($arg:tt)* ) => {
+        if false {
+            let _ = format_args!($($arg)*);
+        }
+    };
+}
+
+#[cfg(feature = "std")]
+pub mod client;
+pub mod options;
+pub mod packet;
+#[cfg(feature = "std")]
+pub mod server;
+
+#[cfg(feature = "serde")]
+mod serde_fmt;
+
 #[cfg(test)]
 mod tests {
     #[test]
     fn it_works() {}
+
+    #[test]
+    fn byte_macros() {
+        assert_eq!(u16_bytes!(1500u16), [5, 220]);
+        assert_eq!(bytes_u16!([5, 220]), 1500);
+        assert_eq!(bytes_u16!(u16_bytes!(65535u16)), 65535);
+        assert_eq!(u32_bytes!(0x01020304u32), [1, 2, 3, 4]);
+        assert_eq!(bytes_u32!([1, 2, 3, 4]), 0x01020304);
+    }
 }
@@ -1,21 +1,1014 @@
-use num_traits::FromPrimitive;
+use alloc::borrow::Cow;
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::error::Error;
+use core::fmt;
+use core::net::Ipv4Addr;
+use core::time::Duration;
 
-pub struct DhcpOption<'a> {
+pub mod pxe;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DhcpOption {
     pub code: u8,
-    pub data: &'a [u8],
+    pub data: Vec<u8>,
+}
+
+/// Decoded option payload, shaped by the encoding RFC 2132 defines for the option code.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OptionValue {
+    Ip(Ipv4Addr),
+    Ips(Vec<Ipv4Addr>),
+    /// Unsigned 32 bit value. TIME_OFFSET is signed; reinterpret with `as i32`.
+    U32(u32),
+    U16(u16),
+    U8(u8),
+    Str(String),
+    /// Options of unknown or structured encoding, or data whose length doesn't fit the encoding.
+    Raw(Vec<u8>),
+}
+
+impl OptionValue {
+    /// Encodes the value as option data.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match *self {
+            OptionValue::Ip(ip) => ip.octets().to_vec(),
+            OptionValue::Ips(ref ips) => encode_ips(ips),
+            OptionValue::U32(v) => u32_bytes!(v).to_vec(),
+            OptionValue::U16(v) => vec![(v >> 8) as u8, v as u8],
+            OptionValue::U8(v) => vec![v],
+            OptionValue::Str(ref s) => s.as_bytes().to_vec(),
+            OptionValue::Raw(ref d) => d.clone(),
+        }
+    }
 }
 
-impl<'a> DhcpOption<'a> {
+impl DhcpOption {
+    /// Builds an option from a typed value.
+    pub fn from_value(code: u8, value: &OptionValue) -> DhcpOption {
+        DhcpOption {
+            code,
+            data: value.to_bytes(),
+        }
+    }
+
+    /// Builds a u32 seconds option such as IP_ADDRESS_LEASE_TIME, RENEWAL_TIME_VALUE or
+    /// REBINDING_TIME_VALUE. Durations longer than u32::MAX seconds are clamped.
+    pub fn from_duration(code: u8, d: Duration) -> DhcpOption {
+        let secs = if d.as_secs() > u32::MAX as u64 {
+            u32::MAX
+        } else {
+            d.as_secs() as u32
+        };
+        DhcpOption {
+            code,
+            data: u32_bytes!(secs).to_vec(),
+        }
+    }
+
+    /// Builds a lease time, renewal (T1) or rebinding (T2) option, which unlike
+    /// `from_duration` can say the lease never expires.
+    pub fn from_lease_time(code: u8, t: LeaseTime) -> DhcpOption {
+        DhcpOption {
+            code,
+            data: t.encode().to_vec(),
+        }
+    }
+
+    /// Creates an INTERFACE_MTU option (26).
+    pub fn mtu(mtu: u16) -> DhcpOption {
+        DhcpOption {
+            code: INTERFACE_MTU,
+            data: u16_bytes!(mtu).to_vec(),
+        }
+    }
+
+    /// Creates a BROADCAST_ADDRESS option (28).
+    pub fn broadcast_address(ip: Ipv4Addr) -> DhcpOption {
+        DhcpOption {
+            code: BROADCAST_ADDRESS,
+            data: ip.octets().to_vec(),
+        }
+    }
+
+    /// Creates an option carrying a string, such as HOST_NAME, DOMAIN_NAME,
+    /// NETWORK_INFORMATION_SERVICE_DOMAIN or a WPAD URL. DHCP strings are not null terminated
+    /// on the wire, so none is added.
+    pub fn string(code: u8, value: &str) -> DhcpOption {
+        DhcpOption {
+            code,
+            data: value.as_bytes().to_vec(),
+        }
+    }
+
+    /// Returns the data as a string, replacing invalid UTF-8 sequences. A trailing null, which
+    /// some clients send regardless, is dropped; `None` if there are nulls elsewhere, as the data
+    /// is then binary rather than a string.
+    pub fn as_str(&self) -> Option<Cow<'_, str>> {
+        data_str(&self.data)
+    }
+
+    /// Creates a TZ_POSIX_STRING option (100, RFC 4833) carrying a POSIX TZ string such as
+    /// "EST5EDT,M3.2.0/02:00,M11.1.0/02:00".
+    pub fn tz_posix(tz: &str) -> DhcpOption {
+        DhcpOption::string(TZ_POSIX_STRING, tz)
+    }
+
+    /// Creates a TZ_DATABASE_STRING option (101, RFC 4833) carrying the name of a tz database
+    /// zone such as "Europe/Zurich".
+    pub fn tz_database(name: &str) -> DhcpOption {
+        DhcpOption::string(TZ_DATABASE_STRING, name)
+    }
+
+    /// Creates a MESSAGE option (56), such as the reason given in a DHCPNAK. Text over 255
+    /// bytes is truncated at a character boundary rather than split across instances, which
+    /// clients merely displaying it may not join back together.
+    pub fn message(text: &str) -> DhcpOption {
+        let mut len = text.len().min(255);
+        while !text.is_char_boundary(len) {
+            len -= 1;
+        }
+        DhcpOption {
+            code: MESSAGE,
+            data: text.as_bytes()[..len].to_vec(),
+        }
+    }
+
+    /// Creates a TFTP server name option (66). Names over 255 bytes are split across
+    /// instances when written (RFC 3396).
+    pub fn tftp_server_name(name: &str) -> DhcpOption {
+        DhcpOption::string(TFTP_SERVER_NAME, name)
+    }
+
+    /// Creates a bootfile name option (67), which may be a long URL for HTTP boot. Names over
+    /// 255 bytes are split across instances when written (RFC 3396).
+    pub fn bootfile_name(name: &str) -> DhcpOption {
+        DhcpOption::string(BOOTFILE_NAME, name)
+    }
+
+    /// Returns the duration carried by a lease time, renewal (T1) or rebinding (T2) option.
+    /// An infinite lease reads as u32::MAX seconds; use `as_lease_time` to tell it apart.
+    pub fn lease_time(&self) -> Option<Duration> {
+        match self.code {
+            IP_ADDRESS_LEASE_TIME | RENEWAL_TIME_VALUE | REBINDING_TIME_VALUE
+                if self.data.len() == 4 => {
+                Some(Duration::from_secs(bytes_u32!(self.data) as u64))
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the lease time, renewal (T1) or rebinding (T2) time carried by the option.
+    pub fn as_lease_time(&self) -> Option<LeaseTime> {
+        match self.code {
+            IP_ADDRESS_LEASE_TIME | RENEWAL_TIME_VALUE | REBINDING_TIME_VALUE => {
+                LeaseTime::decode(&self.data).ok()
+            }
+            _ => None,
+        }
+    }
+
+    /// Decodes the data as a list of addresses, as carried by ROUTER, DOMAIN_NAME_SERVER,
+    /// NETWORK_TIME_PROTOCOL_SERVERS and similar options.
+    pub fn ip_list(&self) -> Result<Vec<Ipv4Addr>, DecodeError> {
+        decode_ips(&self.data)
+    }
+
     /// Returns name of DHCP Option code
-    pub fn title(&'a self) -> String {
+    pub fn title(&self) -> String {
         match title(self.code) {
             Some(t) => t.to_string(),
             None => "Unknown (".to_string() + &self.code.to_string() + ")",
         }
     }
+
+    /// Decodes the option data according to the encoding of its code.
+    /// Falls back to `OptionValue::Raw` for unknown codes and malformed data.
+    pub fn value(&self) -> OptionValue {
+        let d = &self.data;
+        let raw = || OptionValue::Raw(d.clone());
+        match self.code {
+            SUBNET_MASK | SWAP_SERVER | BROADCAST_ADDRESS | ROUTER_SOLICITATION_ADDRESS |
+            REQUESTED_IP_ADDRESS | SERVER_IDENTIFIER => {
+                match bytes_to_ip(d) {
+                    Some(ip) => OptionValue::Ip(ip),
+                    None => raw(),
+                }
+            }
+
+            ROUTER | TIME_SERVER | NAME_SERVER | DOMAIN_NAME_SERVER | LOG_SERVER |
+            COOKIE_SERVER | LPR_SERVER | IMPRESS_SERVER | RESOURCE_LOCATION_SERVER |
+            NETWORK_INFORMATION_SERVERS | NETWORK_TIME_PROTOCOL_SERVERS |
+            NETBIOS_OVER_TCPIP_NAME_SERVER | NETBIOS_OVER_TCPIP_DATAGRAM_DISTRIBUTION_SERVER |
+            XWINDOW_SYSTEM_FONT_SERVER | XWINDOW_SYSTEM_DISPLAY_MANAGER |
+            NETWORK_INFORMATION_SERVICEPLUS_SERVERS | MOBILE_IP_HOME_AGENT |
+            SIMPLE_MAIL_TRANSPORT_PROTOCOL | POST_OFFICE_PROTOCOL_SERVER |
+            NETWORK_NEWS_TRANSPORT_PROTOCOL | DEFAULT_WORLD_WIDE_WEB_SERVER |
+            DEFAULT_FINGER_SERVER | DEFAULT_INTERNET_RELAY_CHAT_SERVER | STREETTALK_SERVER |
+            STREETTALK_DIRECTORY_ASSISTANCE | ASSOCIATED_IP => {
+                match decode_ips(d) {
+                    Ok(ips) if !ips.is_empty() => OptionValue::Ips(ips),
+                    _ => raw(),
+                }
+            }
+
+            TIME_OFFSET | PATH_MTU_AGING_TIMEOUT | ARP_CACHE_TIMEOUT | TCP_KEEPALIVE_INTERVAL |
+            IP_ADDRESS_LEASE_TIME | RENEWAL_TIME_VALUE | REBINDING_TIME_VALUE |
+            CLIENT_LAST_TRANSACTION_TIME => {
+                if d.len() == 4 {
+                    OptionValue::U32(bytes_u32!(d))
+                } else {
+                    raw()
+                }
+            }
+
+            BOOT_FILE_SIZE | MAXIMUM_DATAGRAM_REASSEMBLY_SIZE | INTERFACE_MTU |
+            MAXIMUM_DHCP_MESSAGE_SIZE => {
+                if d.len() == 2 {
+                    OptionValue::U16(((d[0] as u16) << 8) + d[1] as u16)
+                } else {
+                    raw()
+                }
+            }
+
+            IP_FORWARDING_ENABLE_DISABLE | NON_LOCAL_SOURCE_ROUTING_ENABLE_DISABLE |
+            DEFAULT_IP_TIME_TO_LIVE | ALL_SUBNETS_ARE_LOCAL | PERFORM_MASK_DISCOVERY |
+            MASK_SUPPLIER | PERFORM_ROUTER_DISCOVERY | TRAILER_ENCAPSULATION |
+            ETHERNET_ENCAPSULATION | TCP_DEFAULT_TTL | TCP_KEEPALIVE_GARBAGE |
+            NETBIOS_OVER_TCPIP_NODE_TYPE | OVERLOAD | DHCP_MESSAGE_TYPE => {
+                if d.len() == 1 {
+                    OptionValue::U8(d[0])
+                } else {
+                    raw()
+                }
+            }
+
+            HOST_NAME | MERIT_DUMP_FILE | DOMAIN_NAME | ROOT_PATH | EXTENSIONS_PATH |
+            NETWORK_INFORMATION_SERVICE_DOMAIN | NETBIOS_OVER_TCPIP_SCOPE | MESSAGE |
+            NETWORK_INFORMATION_SERVICEPLUS_DOMAIN | TFTP_SERVER_NAME | BOOTFILE_NAME |
+            TZ_POSIX_STRING | TZ_DATABASE_STRING | WPAD => {
+                match String::from_utf8(d.clone()) {
+                    Ok(s) => OptionValue::Str(s),
+                    Err(_) => raw(),
+                }
+            }
+
+            _ => raw(),
+        }
+    }
+}
+
+/// Options keyed by code, with typed getters for the commonly used ones.
+///
+/// Iteration is in ascending code order. When built from a list containing several options with
+/// the same code, the first one is kept, matching `Packet::option`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DhcpOptions {
+    options: BTreeMap<u8, Vec<u8>>,
+}
+
+impl DhcpOptions {
+    pub fn new() -> DhcpOptions {
+        DhcpOptions::default()
+    }
+
+    pub fn get(&self, code: u8) -> Option<&[u8]> {
+        self.options.get(&code).map(|d| &d[..])
+    }
+
+    /// Inserts an option, returning the one it replaced.
+    pub fn insert(&mut self, option: DhcpOption) -> Option<DhcpOption> {
+        let code = option.code;
+        self.options.insert(code, option.data).map(|data| DhcpOption { code, data })
+    }
+
+    pub fn remove(&mut self, code: u8) -> Option<DhcpOption> {
+        self.options.remove(&code).map(|data| DhcpOption { code, data })
+    }
+
+    pub fn len(&self) -> usize {
+        self.options.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.options.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u8, &[u8])> {
+        self.options.iter().map(|(&c, d)| (c, &d[..]))
+    }
+
+    /// Converts back to a list of options, in code order.
+    pub fn into_vec(self) -> Vec<DhcpOption> {
+        self.options.into_iter().map(|(code, data)| DhcpOption { code, data }).collect()
+    }
+
+    /// `None` where `Packet::message_type` would give an error.
+    pub fn message_type(&self) -> Option<MessageType> {
+        match self.get(DHCP_MESSAGE_TYPE) {
+            Some(d) if d.len() == 1 => Some(d[0].into()),
+            _ => None,
+        }
+    }
+
+    pub fn lease_time(&self) -> Option<Duration> {
+        self.duration(IP_ADDRESS_LEASE_TIME)
+    }
+
+    pub fn renewal_time(&self) -> Option<Duration> {
+        self.duration(RENEWAL_TIME_VALUE)
+    }
+
+    pub fn rebinding_time(&self) -> Option<Duration> {
+        self.duration(REBINDING_TIME_VALUE)
+    }
+
+    pub fn subnet_mask(&self) -> Option<Ipv4Addr> {
+        self.ip(SUBNET_MASK)
+    }
+
+    /// Returns the first, preferred, router. `None` where `routers` is.
+    pub fn router(&self) -> Option<Ipv4Addr> {
+        self.routers().map(|r| r[0])
+    }
+
+    /// Returns the routers in order of preference, or `None` if the option is missing or
+    /// malformed: RFC 2132 has it hold one or more addresses, so it must be a non-zero multiple
+    /// of 4 bytes long.
+    pub fn routers(&self) -> Option<Vec<Ipv4Addr>> {
+        self.ips(ROUTER)
+    }
+
+    /// Returns the DOMAIN_NAME_SERVER addresses in order of preference, validated as
+    /// `routers` is.
+    pub fn dns(&self) -> Option<Vec<Ipv4Addr>> {
+        self.ips(DOMAIN_NAME_SERVER)
+    }
+
+    pub fn server_identifier(&self) -> Option<Ipv4Addr> {
+        self.ip(SERVER_IDENTIFIER)
+    }
+
+    pub fn mtu(&self) -> Option<u16> {
+        match self.get(INTERFACE_MTU) {
+            Some(d) if d.len() == 2 => Some(bytes_u16!(d)),
+            _ => None,
+        }
+    }
+
+    pub fn broadcast_address(&self) -> Option<Ipv4Addr> {
+        self.ip(BROADCAST_ADDRESS)
+    }
+
+    pub fn requested_ip(&self) -> Option<Ipv4Addr> {
+        self.ip(REQUESTED_IP_ADDRESS)
+    }
+
+    /// See `DhcpOption::tz_posix`.
+    pub fn tz_posix(&self) -> Option<Cow<'_, str>> {
+        self.get(TZ_POSIX_STRING).and_then(data_str)
+    }
+
+    /// See `DhcpOption::tz_database`.
+    pub fn tz_database(&self) -> Option<Cow<'_, str>> {
+        self.get(TZ_DATABASE_STRING).and_then(data_str)
+    }
+
+    fn ip(&self, code: u8) -> Option<Ipv4Addr> {
+        self.get(code).and_then(bytes_to_ip)
+    }
+
+    fn ips(&self, code: u8) -> Option<Vec<Ipv4Addr>> {
+        match self.get(code).map(decode_ips) {
+            Some(Ok(ips)) if !ips.is_empty() => Some(ips),
+            _ => None,
+        }
+    }
+
+    fn duration(&self, code: u8) -> Option<Duration> {
+        match self.get(code) {
+            Some(d) if d.len() == 4 => Some(Duration::from_secs(bytes_u32!(d) as u64)),
+            _ => None,
+        }
+    }
+}
+
+impl core::iter::FromIterator<DhcpOption> for DhcpOptions {
+    fn from_iter<I: IntoIterator<Item = DhcpOption>>(iter: I) -> DhcpOptions {
+        let mut options = BTreeMap::new();
+        for o in iter {
+            options.entry(o.code).or_insert(o.data);
+        }
+        DhcpOptions { options }
+    }
+}
+
+impl From<Vec<DhcpOption>> for DhcpOptions {
+    fn from(options: Vec<DhcpOption>) -> DhcpOptions {
+        options.into_iter().collect()
+    }
+}
+
+/// Error decoding the structured payload of an option.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The data ends before a length or value it announces.
+    Truncated,
+    /// A classless route announces a prefix longer than 32 bits.
+    InvalidPrefixLength(u8),
+    /// A domain name has a malformed label or is not valid UTF-8.
+    InvalidName,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DecodeError::Truncated => write!(f, "Option data truncated"),
+            DecodeError::InvalidPrefixLength(l) => write!(f, "Invalid prefix length {}", l),
+            DecodeError::InvalidName => write!(f, "Invalid domain name"),
+        }
+    }
+}
+
+impl Error for DecodeError {}
+
+/// Reads option data as a string, as `DhcpOption::as_str` describes.
+fn data_str(d: &[u8]) -> Option<Cow<'_, str>> {
+    let d = d.strip_suffix(&[0]).unwrap_or(d);
+    if d.contains(&0) {
+        return None;
+    }
+    Some(String::from_utf8_lossy(d))
+}
+
+/// Splits code/length/value encoded sub-options, as used by options 43 and 82.
+fn parse_sub_options(d: &[u8]) -> Result<Vec<(u8, Vec<u8>)>, DecodeError> {
+    let mut subs = Vec::new();
+    let mut i = 0;
+    while i < d.len() {
+        if i + 2 > d.len() {
+            return Err(DecodeError::Truncated);
+        }
+        let end = i + 2 + d[i + 1] as usize;
+        if end > d.len() {
+            return Err(DecodeError::Truncated);
+        }
+        subs.push((d[i], d[i + 2..end].to_vec()));
+        i = end;
+    }
+    Ok(subs)
+}
+
+/// Joins sub-options into code/length/value form. Values over 255 bytes are truncated.
+fn encode_sub_options(subs: &[(u8, Vec<u8>)]) -> Vec<u8> {
+    let mut d = Vec::new();
+    for &(code, ref data) in subs {
+        let data = &data[..data.len().min(255)];
+        d.push(code);
+        d.push(data.len() as u8);
+        d.extend_from_slice(data);
+    }
+    d
+}
+
+/// Relay Agent Information (option 82) sub-option codes from RFC 3046.
+pub const AGENT_CIRCUIT_ID: u8 = 1;
+pub const AGENT_REMOTE_ID: u8 = 2;
+
+/// Relay Agent Information (option 82) inserted by relays, as described in RFC 3046.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelayAgentInfo {
+    /// Sub-options in wire order, including codes not known to this crate.
+    pub sub_options: Vec<(u8, Vec<u8>)>,
+}
+
+impl RelayAgentInfo {
+    pub fn parse(data: &[u8]) -> Result<RelayAgentInfo, DecodeError> {
+        Ok(RelayAgentInfo { sub_options: parse_sub_options(data)? })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        encode_sub_options(&self.sub_options)
+    }
+
+    /// Returns the data of the first sub-option with the given code.
+    pub fn sub_option(&self, code: u8) -> Option<&[u8]> {
+        self.sub_options.iter().find(|s| s.0 == code).map(|s| &s.1[..])
+    }
+
+    /// Agent Circuit ID, typically identifying the switch port the request arrived on.
+    pub fn circuit_id(&self) -> Option<&[u8]> {
+        self.sub_option(AGENT_CIRCUIT_ID)
+    }
+
+    /// Agent Remote ID, typically identifying the remote host end of the circuit.
+    pub fn remote_id(&self) -> Option<&[u8]> {
+        self.sub_option(AGENT_REMOTE_ID)
+    }
+}
+
+/// PXE sub-option codes carried in Vendor-Specific Information (option 43), from the PXE
+/// specification.
+pub const PXE_DISCOVERY_CONTROL: u8 = 6;
+pub const PXE_BOOT_SERVERS: u8 = 8;
+pub const PXE_BOOT_MENU: u8 = 9;
+pub const PXE_MENU_PROMPT: u8 = 10;
+pub const PXE_BOOT_ITEM: u8 = 71;
+
+/// Vendor-Specific Information (option 43) in the common code/length/value form, as used by
+/// PXE.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VendorOptions {
+    /// Sub-options in wire order.
+    pub sub_options: Vec<(u8, Vec<u8>)>,
+}
+
+impl VendorOptions {
+    /// Parses sub-options leniently, since vendor encodings differ: PAD bytes are skipped,
+    /// an END byte stops parsing but is not required, and a truncated trailing sub-option is
+    /// dropped.
+    pub fn parse(data: &[u8]) -> VendorOptions {
+        let mut subs = Vec::new();
+        let mut i = 0;
+        while i < data.len() {
+            match data[i] {
+                PAD => i += 1,
+                END => break,
+                code => {
+                    if i + 2 > data.len() {
+                        break;
+                    }
+                    let end = i + 2 + data[i + 1] as usize;
+                    if end > data.len() {
+                        break;
+                    }
+                    subs.push((code, data[i + 2..end].to_vec()));
+                    i = end;
+                }
+            }
+        }
+        VendorOptions { sub_options: subs }
+    }
+
+    /// Encodes the sub-options followed by an END byte, as PXE clients expect.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut d = encode_sub_options(&self.sub_options);
+        d.push(END);
+        d
+    }
+
+    /// Returns the data of the first sub-option with the given code.
+    pub fn sub_option(&self, code: u8) -> Option<&[u8]> {
+        self.sub_options.iter().find(|s| s.0 == code).map(|s| &s.1[..])
+    }
+}
+
+/// A route carried in the Classless Static Route option (121), as described in RFC 3442.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClasslessRoute {
+    pub dest: Ipv4Addr,
+    pub prefix: u8,
+    pub gateway: Ipv4Addr,
+}
+
+impl ClasslessRoute {
+    /// Encodes routes as option 121 data. Each destination only takes as many octets as its
+    /// prefix needs, so 0.0.0.0/0 is a single zero byte followed by the gateway.
+    /// Prefixes over 32 are treated as 32.
+    pub fn encode(routes: &[ClasslessRoute]) -> Vec<u8> {
+        let mut d = Vec::new();
+        for r in routes {
+            let prefix = r.prefix.min(32);
+            d.push(prefix);
+            d.extend_from_slice(&r.dest.octets()[..significant_octets(prefix)]);
+            d.extend_from_slice(&r.gateway.octets());
+        }
+        d
+    }
+
+    pub fn decode(d: &[u8]) -> Result<Vec<ClasslessRoute>, DecodeError> {
+        let mut routes = Vec::new();
+        let mut i = 0;
+        while i < d.len() {
+            let prefix = d[i];
+            if prefix > 32 {
+                return Err(DecodeError::InvalidPrefixLength(prefix));
+            }
+            let width = significant_octets(prefix);
+            let end = i + 1 + width + 4;
+            if end > d.len() {
+                return Err(DecodeError::Truncated);
+            }
+            let mut dest = [0; 4];
+            dest[..width].copy_from_slice(&d[i + 1..i + 1 + width]);
+            let g = &d[i + 1 + width..end];
+            routes.push(ClasslessRoute {
+                dest: Ipv4Addr::from(dest),
+                prefix,
+                gateway: Ipv4Addr::new(g[0], g[1], g[2], g[3]),
+            });
+            i = end;
+        }
+        Ok(routes)
+    }
+}
+
+/// Domain Search (option 119) list encoding, as described in RFC 3397: DNS wire format names
+/// where a name ending in a suffix already in the list points back to it (RFC 1035 section
+/// 4.1.4), so the domains of one organisation take little more space than one.
+pub struct DomainSearch;
+
+impl DomainSearch {
+    /// Encodes `domains`, given in dotted form, compressing repeated suffixes. Labels over 63
+    /// bytes are truncated, and names without any, such as "" or ".", are left out, as `decode`
+    /// would reject the bare root.
+    pub fn encode(domains: &[&str]) -> Vec<u8> {
+        let mut d = Vec::new();
+        let mut suffixes: Vec<(Vec<&[u8]>, usize)> = Vec::new();
+        for domain in domains {
+            let labels: Vec<&[u8]> = domain.split('.')
+                .filter(|l| !l.is_empty())
+                .map(|l| &l.as_bytes()[..l.len().min(63)])
+                .collect();
+            if labels.is_empty() {
+                continue;
+            }
+            let mut pointer = None;
+            for j in 0..labels.len() {
+                if let Some(&(_, at)) = suffixes.iter().find(|(s, _)| s[..] == labels[j..]) {
+                    pointer = Some(at);
+                    break;
+                }
+                // Pointers have 14 bits
+                if d.len() < 0x4000 {
+                    suffixes.push((labels[j..].to_vec(), d.len()));
+                }
+                d.push(labels[j].len() as u8);
+                d.extend_from_slice(labels[j]);
+            }
+            match pointer {
+                Some(at) => d.extend_from_slice(&[0xc0 | (at >> 8) as u8, at as u8]),
+                None => d.push(0),
+            }
+        }
+        d
+    }
+
+    /// Decodes the list into dotted names. Compression pointers may only point back to an
+    /// earlier part of the list than the one being read, so a malicious list cannot make
+    /// decoding loop.
+    pub fn decode(d: &[u8]) -> Result<Vec<String>, DecodeError> {
+        let mut domains = Vec::new();
+        let mut i = 0;
+        while i < d.len() {
+            let (name, next) = decode_compressed_name(d, i)?;
+            domains.push(name);
+            i = next;
+        }
+        Ok(domains)
+    }
+}
+
+/// Decodes the possibly compressed name starting at `start`, returning it with the position
+/// just past it.
+fn decode_compressed_name(d: &[u8], start: usize) -> Result<(String, usize), DecodeError> {
+    let mut labels = Vec::new();
+    let mut i = start;
+    // Each pointer must go further back than the last, which bounds the jumps
+    let mut limit = start;
+    let mut next = None;
+    loop {
+        let l = *d.get(i).ok_or(DecodeError::Truncated)? as usize;
+        match l {
+            0 => break,
+            0xc0..=0xff => {
+                let low = *d.get(i + 1).ok_or(DecodeError::Truncated)? as usize;
+                let target = (l & 0x3f) << 8 | low;
+                if target >= limit {
+                    return Err(DecodeError::InvalidName);
+                }
+                next.get_or_insert(i + 2);
+                limit = target;
+                i = target;
+            }
+            1..=63 => {
+                let label = d.get(i + 1..i + 1 + l).ok_or(DecodeError::Truncated)?;
+                labels.push(core::str::from_utf8(label).map_err(|_| DecodeError::InvalidName)?);
+                i += 1 + l;
+            }
+            _ => return Err(DecodeError::InvalidName),
+        }
+    }
+    if labels.is_empty() {
+        return Err(DecodeError::InvalidName);
+    }
+    Ok((labels.join("."), next.unwrap_or(i + 1)))
+}
+
+/// Client FQDN (option 81) flag bits from RFC 4702.
+/// S: the server should perform the A RR update.
+pub const FQDN_FLAG_S: u8 = 0x01;
+/// O: the server overrode the client's preference for S.
+pub const FQDN_FLAG_O: u8 = 0x02;
+/// E: the name uses the canonical wire encoding instead of the deprecated ASCII one.
+pub const FQDN_FLAG_E: u8 = 0x04;
+/// N: the server should perform no DNS updates.
+pub const FQDN_FLAG_N: u8 = 0x08;
+
+/// Client Fully Qualified Domain Name (option 81), as described in RFC 4702.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientFqdn {
+    pub flags: u8,
+    /// RCODE1, the A-RR result. Deprecated, sent as 0 by clients and 255 by servers.
+    pub rcode1: u8,
+    /// RCODE2, the PTR-RR result. Deprecated like `rcode1`.
+    pub rcode2: u8,
+    /// Domain name in dotted form, without a trailing dot.
+    pub name: String,
+    /// Whether the name is partial, leaving the server to append a domain to it.
+    pub partial: bool,
+}
+
+impl ClientFqdn {
+    /// Parses option 81 data, decoding the name according to the E flag. In the ASCII
+    /// encoding a name is fully qualified if it ends with a dot; in the wire encoding if
+    /// it ends with the root label.
+    pub fn parse(d: &[u8]) -> Result<ClientFqdn, DecodeError> {
+        if d.len() < 3 {
+            return Err(DecodeError::Truncated);
+        }
+        let (name, partial) = if d[0] & FQDN_FLAG_E != 0 {
+            decode_domain_name(&d[3..])?
+        } else {
+            let s = core::str::from_utf8(&d[3..]).map_err(|_| DecodeError::InvalidName)?;
+            let s = s.trim_end_matches('\0');
+            match s.strip_suffix('.') {
+                Some(n) => (n.to_string(), false),
+                None => (s.to_string(), true),
+            }
+        };
+        Ok(ClientFqdn {
+            flags: d[0],
+            rcode1: d[1],
+            rcode2: d[2],
+            name,
+            partial,
+        })
+    }
+
+    /// Encodes the option data, using the encoding selected by the E flag.
+    /// Labels over 63 bytes are truncated.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut d = vec![self.flags, self.rcode1, self.rcode2];
+        if self.flags & FQDN_FLAG_E != 0 {
+            for label in self.name.split('.').filter(|l| !l.is_empty()) {
+                let label = &label.as_bytes()[..label.len().min(63)];
+                d.push(label.len() as u8);
+                d.extend_from_slice(label);
+            }
+            if !self.partial {
+                d.push(0);
+            }
+        } else {
+            d.extend_from_slice(self.name.as_bytes());
+            if !self.partial && !self.name.is_empty() {
+                d.push(b'.');
+            }
+        }
+        d
+    }
+}
+
+/// Decodes an uncompressed DNS wire format name, returning it in dotted form and whether
+/// it is partial (missing the terminating root label).
+fn decode_domain_name(d: &[u8]) -> Result<(String, bool), DecodeError> {
+    let mut labels = Vec::new();
+    let mut i = 0;
+    while i < d.len() {
+        let l = d[i] as usize;
+        if l == 0 {
+            if i + 1 != d.len() {
+                return Err(DecodeError::InvalidName);
+            }
+            return Ok((labels.join("."), false));
+        }
+        if l > 63 {
+            return Err(DecodeError::InvalidName);
+        }
+        if i + 1 + l > d.len() {
+            return Err(DecodeError::Truncated);
+        }
+        let label = core::str::from_utf8(&d[i + 1..i + 1 + l]).map_err(|_| DecodeError::InvalidName)?;
+        labels.push(label);
+        i += 1 + l;
+    }
+    Ok((labels.join("."), true))
+}
+
+/// User Class (option 77) names sent by the client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserClasses {
+    pub classes: Vec<Vec<u8>>,
+}
+
+impl UserClasses {
+    /// Parses option 77 data. RFC 3004 encodes it as a list of non-empty length prefixed
+    /// names, but Windows clients send a single unprefixed name instead. The data is taken to
+    /// be a list if it divides exactly into one, and as a single name otherwise.
+    pub fn parse(d: &[u8]) -> UserClasses {
+        let mut classes = Vec::new();
+        let mut i = 0;
+        while i < d.len() {
+            let l = d[i] as usize;
+            if l == 0 || i + 1 + l > d.len() {
+                return UserClasses { classes: vec![d.to_vec()] };
+            }
+            classes.push(d[i + 1..i + 1 + l].to_vec());
+            i += 1 + l;
+        }
+        UserClasses { classes }
+    }
+
+    /// Encodes the names in the RFC 3004 form. Names over 255 bytes are truncated.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut d = Vec::new();
+        for c in &self.classes {
+            let c = &c[..c.len().min(255)];
+            d.push(c.len() as u8);
+            d.extend_from_slice(c);
+        }
+        d
+    }
+}
+
+/// Authentication (option 90) protocols from RFC 3118.
+pub const AUTH_PROTOCOL_CONFIGURATION_TOKEN: u8 = 0;
+pub const AUTH_PROTOCOL_DELAYED: u8 = 1;
+/// HMAC-MD5, the only algorithm defined for delayed authentication.
+pub const AUTH_ALGORITHM_HMAC_MD5: u8 = 1;
+/// Replay detection method: a monotonically increasing counter, such as a timestamp.
+pub const AUTH_RDM_MONOTONIC: u8 = 0;
+
+/// Authentication (option 90), as described in RFC 3118. Fields are exposed as received so
+/// that a handler can verify them; no cryptography is done here, and protocols or algorithms
+/// this crate has no constant for are kept like any other.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Authentication {
+    pub protocol: u8,
+    pub algorithm: u8,
+    /// Replay detection method, selecting how `replay_detection` is interpreted.
+    pub rdm: u8,
+    pub replay_detection: u64,
+    /// Protocol specific information. For delayed authentication this is the secret ID
+    /// followed by the HMAC-MD5 digest.
+    pub auth_info: Vec<u8>,
+}
+
+impl Authentication {
+    pub fn parse(d: &[u8]) -> Result<Authentication, DecodeError> {
+        if d.len() < 11 {
+            return Err(DecodeError::Truncated);
+        }
+        let mut replay = [0; 8];
+        replay.copy_from_slice(&d[3..11]);
+        Ok(Authentication {
+            protocol: d[0],
+            algorithm: d[1],
+            rdm: d[2],
+            replay_detection: u64::from_be_bytes(replay),
+            auth_info: d[11..].to_vec(),
+        })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut d = vec![self.protocol, self.algorithm, self.rdm];
+        d.extend_from_slice(&self.replay_detection.to_be_bytes());
+        d.extend_from_slice(&self.auth_info);
+        d
+    }
+}
+
+/// Encodes addresses back to back, as in options carrying a list of servers.
+pub fn encode_ips(ips: &[Ipv4Addr]) -> Vec<u8> {
+    ips.iter().flat_map(|ip| ip.octets()).collect()
+}
+
+/// Decodes back to back addresses. Fails if the length is not a multiple of 4.
+pub fn decode_ips(d: &[u8]) -> Result<Vec<Ipv4Addr>, DecodeError> {
+    if !d.len().is_multiple_of(4) {
+        return Err(DecodeError::Truncated);
+    }
+    Ok(d.chunks(4).map(|c| Ipv4Addr::new(c[0], c[1], c[2], c[3])).collect())
+}
+
+/// How long a lease lasts. RFC 2131 section 3.3 reserves 0xffffffff seconds for a lease that
+/// never expires, as for a static reservation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LeaseTime {
+    Finite(Duration),
+    Infinite,
+}
+
+impl LeaseTime {
+    /// Encodes the seconds as carried by IP_ADDRESS_LEASE_TIME. Finite durations are clamped
+    /// below 0xffffffff seconds, so that none is mistaken for an infinite lease.
+    pub fn encode(&self) -> [u8; 4] {
+        let secs = match *self {
+            LeaseTime::Finite(d) => d.as_secs().min(u32::MAX as u64 - 1) as u32,
+            LeaseTime::Infinite => u32::MAX,
+        };
+        u32_bytes!(secs)
+    }
+
+    /// Decodes the four bytes of seconds of a lease time option.
+    pub fn decode(d: &[u8]) -> Result<LeaseTime, DecodeError> {
+        if d.len() != 4 {
+            return Err(DecodeError::Truncated);
+        }
+        Ok(match bytes_u32!(d) {
+            u32::MAX => LeaseTime::Infinite,
+            secs => LeaseTime::Finite(Duration::from_secs(secs as u64)),
+        })
+    }
+}
+
+/// The network settings nearly every server offers, for `standard_offer`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OfferConfig {
+    pub subnet: Ipv4Addr,
+    pub router: Ipv4Addr,
+    /// Name servers in order of preference. No DOMAIN_NAME_SERVER option is sent if empty.
+    pub dns: Vec<Ipv4Addr>,
+    pub lease: Duration,
+    /// Sent as DOMAIN_NAME if set. Names over 255 bytes are truncated.
+    pub domain: Option<String>,
+}
+
+/// Builds the SUBNET_MASK, ROUTER, DOMAIN_NAME_SERVER, DOMAIN_NAME and IP_ADDRESS_LEASE_TIME
+/// options for `config`, in that order, ascending by code as RFC 2132 lists them.
+///
+/// ```
+/// use std::net::Ipv4Addr;
+/// use std::time::Duration;
+/// use dhcp4r::options::{self, OfferConfig};
+///
+/// let opts = options::standard_offer(&OfferConfig {
+///     subnet: Ipv4Addr::new(255, 255, 255, 0),
+///     router: Ipv4Addr::new(192, 168, 0, 1),
+///     dns: vec![Ipv4Addr::new(192, 168, 0, 1)],
+///     lease: Duration::from_secs(3600),
+///     domain: None,
+/// });
+/// let codes: Vec<u8> = opts.iter().map(|o| o.code).collect();
+/// assert_eq!(codes, [options::SUBNET_MASK, options::ROUTER, options::DOMAIN_NAME_SERVER,
+///                    options::IP_ADDRESS_LEASE_TIME]);
+/// ```
+pub fn standard_offer(config: &OfferConfig) -> Vec<DhcpOption> {
+    let mut opts = vec![DhcpOption {
+                            code: SUBNET_MASK,
+                            data: config.subnet.octets().to_vec(),
+                        },
+                        DhcpOption {
+                            code: ROUTER,
+                            data: config.router.octets().to_vec(),
+                        }];
+    if !config.dns.is_empty() {
+        opts.push(DhcpOption {
+            code: DOMAIN_NAME_SERVER,
+            data: encode_ips(&config.dns),
+        });
+    }
+    if let Some(ref domain) = config.domain {
+        opts.push(DhcpOption {
+            code: DOMAIN_NAME,
+            data: domain.as_bytes()[..domain.len().min(255)].to_vec(),
+        });
+    }
+    opts.push(DhcpOption::from_duration(IP_ADDRESS_LEASE_TIME, config.lease));
+    opts
+}
+
+/// The four bytes of an address, as they appear in option data and the packet header.
+pub fn ip_to_bytes(ip: Ipv4Addr) -> [u8; 4] {
+    ip.octets()
+}
+
+/// Reads an address from option data, which must be exactly 4 bytes long.
+pub fn bytes_to_ip(d: &[u8]) -> Option<Ipv4Addr> {
+    <[u8; 4]>::try_from(d).ok().map(Ipv4Addr::from)
+}
+
+/// Number of destination octets a prefix of the given length occupies.
+fn significant_octets(prefix: u8) -> usize {
+    (prefix as usize).div_ceil(8)
 }
 
 // DHCP Options;
+pub const PAD: u8 = 0;
 pub const SUBNET_MASK: u8 = 1;
 pub const TIME_OFFSET: u8 = 2;
 pub const ROUTER: u8 = 3;
@@ -103,21 +1096,87 @@ pub const REBINDING_TIME_VALUE: u8 = 59;
 pub const VENDOR_CLASS_IDENTIFIER: u8 = 60;
 pub const CLIENT_IDENTIFIER: u8 = 61;
 
+pub const NETWARE_IP_DOMAIN: u8 = 62;
+pub const NETWARE_IP_OPTION: u8 = 63;
+
 pub const TFTP_SERVER_NAME: u8 = 66;
 pub const BOOTFILE_NAME: u8 = 67;
 
 pub const USER_CLASS: u8 = 77;
+pub const DIRECTORY_AGENT: u8 = 78;
+pub const SERVICE_SCOPE: u8 = 79;
+pub const RAPID_COMMIT: u8 = 80;
+pub const CLIENT_FQDN: u8 = 81;
+pub const INTERNET_STORAGE_NAME_SERVICE: u8 = 83;
+pub const NDS_SERVERS: u8 = 85;
+pub const NDS_TREE_NAME: u8 = 86;
+pub const NDS_CONTEXT: u8 = 87;
+pub const BCMCS_CONTROLLER_DOMAIN_NAME_LIST: u8 = 88;
+pub const BCMCS_CONTROLLER_IPV4_ADDRESS: u8 = 89;
+pub const AUTHENTICATION: u8 = 90;
+pub const CLIENT_LAST_TRANSACTION_TIME: u8 = 91;
+pub const ASSOCIATED_IP: u8 = 92;
 
 pub const CLIENT_ARCHITECTURE: u8 = 93;
+pub const CLIENT_NETWORK_INTERFACE_IDENTIFIER: u8 = 94;
+pub const CLIENT_MACHINE_IDENTIFIER: u8 = 97;
+pub const USER_AUTHENTICATION_PROTOCOL: u8 = 98;
+pub const GEOCONF_CIVIC: u8 = 99;
 
 pub const TZ_POSIX_STRING: u8 = 100;
 pub const TZ_DATABASE_STRING: u8 = 101;
 
+pub const NETINFO_PARENT_SERVER_ADDRESS: u8 = 112;
+pub const NETINFO_PARENT_SERVER_TAG: u8 = 113;
+pub const CAPTIVE_PORTAL: u8 = 114;
+pub const AUTO_CONFIGURE: u8 = 116;
+pub const NAME_SERVICE_SEARCH: u8 = 117;
+pub const SUBNET_SELECTION: u8 = 118;
+pub const DOMAIN_SEARCH: u8 = 119;
+pub const SIP_SERVERS: u8 = 120;
+
 pub const CLASSLESS_ROUTE_FORMAT: u8 = 121;
+/// RFC 3442 name for CLASSLESS_ROUTE_FORMAT.
+pub const CLASSLESS_STATIC_ROUTE: u8 = CLASSLESS_ROUTE_FORMAT;
+
+pub const VENDOR_IDENTIFYING_VENDOR_CLASS: u8 = 124;
+pub const VENDOR_IDENTIFYING_VENDOR_SPECIFIC_INFORMATION: u8 = 125;
+pub const TFTP_SERVER_ADDRESS: u8 = 150;
+
+// PXELINUX (RFC 5071)
+pub const PXELINUX_MAGIC: u8 = 208;
+pub const PXELINUX_CONFIG_FILE: u8 = 209;
+pub const PXELINUX_PATH_PREFIX: u8 = 210;
+pub const PXELINUX_REBOOT_TIME: u8 = 211;
+
+/// Web Proxy Auto-Discovery: the URL of a proxy auto-config file. A site-specific code, but
+/// the one browsers look for.
+pub const WPAD: u8 = 252;
+
+pub const END: u8 = 255;
+
+/// Whether repeated instances of an option are fragments of one value to be concatenated, as
+/// RFC 3396 describes. False for options with a fixed length, such as DHCP_MESSAGE_TYPE or
+/// IP_ADDRESS_LEASE_TIME, where a repeat can only be a conflicting duplicate.
+pub fn is_concatenable(code: u8) -> bool {
+    !matches!(code,
+              SUBNET_MASK | SWAP_SERVER | BROADCAST_ADDRESS | ROUTER_SOLICITATION_ADDRESS |
+              REQUESTED_IP_ADDRESS | SERVER_IDENTIFIER | TIME_OFFSET | PATH_MTU_AGING_TIMEOUT |
+              ARP_CACHE_TIMEOUT | TCP_KEEPALIVE_INTERVAL | IP_ADDRESS_LEASE_TIME |
+              RENEWAL_TIME_VALUE | REBINDING_TIME_VALUE | BOOT_FILE_SIZE |
+              MAXIMUM_DATAGRAM_REASSEMBLY_SIZE | INTERFACE_MTU | MAXIMUM_DHCP_MESSAGE_SIZE |
+              IP_FORWARDING_ENABLE_DISABLE | NON_LOCAL_SOURCE_ROUTING_ENABLE_DISABLE |
+              DEFAULT_IP_TIME_TO_LIVE | ALL_SUBNETS_ARE_LOCAL | PERFORM_MASK_DISCOVERY |
+              MASK_SUPPLIER | PERFORM_ROUTER_DISCOVERY | TRAILER_ENCAPSULATION |
+              ETHERNET_ENCAPSULATION | TCP_DEFAULT_TTL | TCP_KEEPALIVE_GARBAGE |
+              NETBIOS_OVER_TCPIP_NODE_TYPE | OVERLOAD | DHCP_MESSAGE_TYPE |
+              CLIENT_LAST_TRANSACTION_TIME)
+}
 
 /// Returns title of DHCP Option code, if known.
 pub fn title(code: u8) -> Option<&'static str> {
     Some(match code {
+        PAD => "Pad",
         SUBNET_MASK => "Subnet Mask",
 
         TIME_OFFSET => "Time Offset",
@@ -208,22 +1267,72 @@ pub fn title(code: u8) -> Option<&'static str> {
         VENDOR_CLASS_IDENTIFIER => "Vendor class identifier",
         CLIENT_IDENTIFIER => "Client-identifier",
 
+        NETWARE_IP_DOMAIN => "NetWare/IP Domain Name",
+        NETWARE_IP_OPTION => "NetWare/IP sub Options",
+
         // Find below
         TFTP_SERVER_NAME => "TFTP server name",
         BOOTFILE_NAME => "Bootfile name",
 
         USER_CLASS => "User Class",
+        DIRECTORY_AGENT => "Directory Agent Information",
+        SERVICE_SCOPE => "Service Location Agent Scope",
+        RAPID_COMMIT => "Rapid Commit",
+        CLIENT_FQDN => "Client Fully Qualified Domain Name",
+        INTERNET_STORAGE_NAME_SERVICE => "Internet Storage Name Service",
+        NDS_SERVERS => "Novell Directory Services Servers",
+        NDS_TREE_NAME => "Novell Directory Services Tree Name",
+        NDS_CONTEXT => "Novell Directory Services Context",
+        BCMCS_CONTROLLER_DOMAIN_NAME_LIST => "BCMCS Controller Domain Name List",
+        BCMCS_CONTROLLER_IPV4_ADDRESS => "BCMCS Controller IPv4 Address",
+        AUTHENTICATION => "Authentication",
+        CLIENT_LAST_TRANSACTION_TIME => "Client Last Transaction Time",
+        ASSOCIATED_IP => "Associated IP",
 
         CLIENT_ARCHITECTURE => "Client Architecture",
+        CLIENT_NETWORK_INTERFACE_IDENTIFIER => "Client Network Interface Identifier",
+        CLIENT_MACHINE_IDENTIFIER => "Client Machine Identifier",
+        USER_AUTHENTICATION_PROTOCOL => "User Authentication Protocol",
+        GEOCONF_CIVIC => "GeoConf Civic",
 
         TZ_POSIX_STRING => "TZ-POSIX String",
         TZ_DATABASE_STRING => "TZ-Database String",
+
+        NETINFO_PARENT_SERVER_ADDRESS => "NetInfo Parent Server Address",
+        NETINFO_PARENT_SERVER_TAG => "NetInfo Parent Server Tag",
+        CAPTIVE_PORTAL => "Captive Portal",
+        AUTO_CONFIGURE => "Auto-Configure",
+        NAME_SERVICE_SEARCH => "Name Service Search",
+        SUBNET_SELECTION => "Subnet Selection",
+        DOMAIN_SEARCH => "Domain Search",
+        SIP_SERVERS => "SIP Servers",
         CLASSLESS_ROUTE_FORMAT => "Classless Route Format",
 
+        VENDOR_IDENTIFYING_VENDOR_CLASS => "Vendor-Identifying Vendor Class",
+        VENDOR_IDENTIFYING_VENDOR_SPECIFIC_INFORMATION => "Vendor-Identifying Vendor-Specific Information",
+        TFTP_SERVER_ADDRESS => "TFTP Server Address",
+
+        PXELINUX_MAGIC => "PXELINUX Magic",
+        PXELINUX_CONFIG_FILE => "PXELINUX Configuration File",
+        PXELINUX_PATH_PREFIX => "PXELINUX Path Prefix",
+        PXELINUX_REBOOT_TIME => "PXELINUX Reboot Time",
+
+        WPAD => "Web Proxy Auto-Discovery",
+
+        END => "End",
+
         _ => return None,
     })
 }
 
+/// Whether the packet's SERVER_IDENTIFIER names `server_ip`. Packets without one, such as
+/// DISCOVERs and renewals, are not considered addressed to any particular server.
+/// An unspecified `server_ip` (0.0.0.0) stands for a server answering regardless, so every
+/// packet is for it, whatever server identifier it carries.
+pub fn is_for_server(packet: &crate::packet::Packet, server_ip: Ipv4Addr) -> bool {
+    server_ip.is_unspecified() || packet.server_identifier() == Some(server_ip)
+}
+
 ///
 /// DHCP Message Type.
 ///
@@ -235,40 +1344,535 @@ pub fn title(code: u8) -> Option<&'static str> {
 /// > This option is used to convey the type of the DHCP message.  The code for this option is 53,
 /// > and its length is 1.
 ///
-#[derive(Primitive)]
+/// Later RFCs (3203, 4388, 6926 and 7724) assign values 9 through 18.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum MessageType {
     /// Client broadcast to locate available servers.
-    Discover = 1,
+    Discover,
 
     /// Server to client in response to DHCPDISCOVER with offer of configuration parameters.
-    Offer = 2,
+    Offer,
 
     /// Client message to servers either (a) requesting offered parameters from one server and
     /// implicitly declining offers from all others, (b) confirming correctness of previously
     /// allocated address after, e.g., system reboot, or (c) extending the lease on a particular
     /// network address.
-    Request = 3,
+    Request,
 
     /// Client to server indicating network address is already in use.
-    Decline = 4,
+    Decline,
 
     /// Server to client with configuration parameters, including committed network address.
-    Ack = 5,
+    Ack,
 
     /// Server to client indicating client's notion of network address is incorrect (e.g., client
     /// has moved to new subnet) or client's lease as expired.
-    Nak = 6,
+    Nak,
 
     /// Client to server relinquishing network address and cancelling remaining lease.
-    Release = 7,
+    Release,
 
     /// Client to server, asking only for local configuration parameters; client already has
     /// externally configured network address.
-    Inform = 8,
+    Inform,
+
+    /// Server to client, forcing the client into the RENEWING state (RFC 3203).
+    ForceRenew,
+
+    /// Relay agent or access concentrator to server, asking about a lease (RFC 4388).
+    LeaseQuery,
+
+    /// Server to querier: the server is authoritative for the address but it is not leased.
+    LeaseUnassigned,
+
+    /// Server to querier: the server has no information about the queried client or address.
+    LeaseUnknown,
+
+    /// Server to querier: the queried address is actively leased.
+    LeaseActive,
+
+    /// Bulk leasequery over TCP (RFC 6926).
+    BulkLeaseQuery,
+
+    /// Server to querier: end of a bulk leasequery response (RFC 6926).
+    LeaseQueryDone,
+
+    /// Active leasequery over TCP (RFC 7724).
+    ActiveLeaseQuery,
+
+    /// Server to querier: status of an active leasequery connection (RFC 7724).
+    LeaseQueryStatus,
+
+    /// Negotiation of TLS on an active leasequery connection (RFC 7724).
+    Tls,
+
+    /// A message type value not defined by any of the above RFCs.
+    Unknown(u8),
 }
 
 impl MessageType {
+    /// Maps the option 53 value to a message type.
+    /// Values not assigned by an RFC are preserved as `Unknown` rather than rejected.
+    #[deprecated(note = "never fails; use `From<u8>` or `TryFrom<u8>` instead")]
     pub fn from(val: u8) -> Result<MessageType, String> {
-        MessageType::from_u8(val).ok_or_else(|| format!["Invalid DHCP Message Type: {:?}", val])
+        Ok(MessageType::from_u8(val))
+    }
+
+    fn from_u8(val: u8) -> MessageType {
+        match val {
+            1 => MessageType::Discover,
+            2 => MessageType::Offer,
+            3 => MessageType::Request,
+            4 => MessageType::Decline,
+            5 => MessageType::Ack,
+            6 => MessageType::Nak,
+            7 => MessageType::Release,
+            8 => MessageType::Inform,
+            9 => MessageType::ForceRenew,
+            10 => MessageType::LeaseQuery,
+            11 => MessageType::LeaseUnassigned,
+            12 => MessageType::LeaseUnknown,
+            13 => MessageType::LeaseActive,
+            14 => MessageType::BulkLeaseQuery,
+            15 => MessageType::LeaseQueryDone,
+            16 => MessageType::ActiveLeaseQuery,
+            17 => MessageType::LeaseQueryStatus,
+            18 => MessageType::Tls,
+            _ => MessageType::Unknown(val),
+        }
+    }
+
+    /// Returns the option 53 value for this message type.
+    pub fn to_u8(&self) -> u8 {
+        match *self {
+            MessageType::Discover => 1,
+            MessageType::Offer => 2,
+            MessageType::Request => 3,
+            MessageType::Decline => 4,
+            MessageType::Ack => 5,
+            MessageType::Nak => 6,
+            MessageType::Release => 7,
+            MessageType::Inform => 8,
+            MessageType::ForceRenew => 9,
+            MessageType::LeaseQuery => 10,
+            MessageType::LeaseUnassigned => 11,
+            MessageType::LeaseUnknown => 12,
+            MessageType::LeaseActive => 13,
+            MessageType::BulkLeaseQuery => 14,
+            MessageType::LeaseQueryDone => 15,
+            MessageType::ActiveLeaseQuery => 16,
+            MessageType::LeaseQueryStatus => 17,
+            MessageType::Tls => 18,
+            MessageType::Unknown(val) => val,
+        }
+    }
+}
+
+/// Maps the option 53 value to a message type, preserving values not assigned by an RFC as
+/// `Unknown`.
+impl From<u8> for MessageType {
+    fn from(val: u8) -> MessageType {
+        MessageType::from_u8(val)
+    }
+}
+
+impl From<MessageType> for u8 {
+    fn from(t: MessageType) -> u8 {
+        t.to_u8()
+    }
+}
+
+impl fmt::Display for MessageType {
+    /// Writes the RFC name, such as DHCPDISCOVER.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            MessageType::Discover => "DHCPDISCOVER",
+            MessageType::Offer => "DHCPOFFER",
+            MessageType::Request => "DHCPREQUEST",
+            MessageType::Decline => "DHCPDECLINE",
+            MessageType::Ack => "DHCPACK",
+            MessageType::Nak => "DHCPNAK",
+            MessageType::Release => "DHCPRELEASE",
+            MessageType::Inform => "DHCPINFORM",
+            MessageType::ForceRenew => "DHCPFORCERENEW",
+            MessageType::LeaseQuery => "DHCPLEASEQUERY",
+            MessageType::LeaseUnassigned => "DHCPLEASEUNASSIGNED",
+            MessageType::LeaseUnknown => "DHCPLEASEUNKNOWN",
+            MessageType::LeaseActive => "DHCPLEASEACTIVE",
+            MessageType::BulkLeaseQuery => "DHCPBULKLEASEQUERY",
+            MessageType::LeaseQueryDone => "DHCPLEASEQUERYDONE",
+            MessageType::ActiveLeaseQuery => "DHCPACTIVELEASEQUERY",
+            MessageType::LeaseQueryStatus => "DHCPLEASEQUERYSTATUS",
+            MessageType::Tls => "DHCPTLS",
+            MessageType::Unknown(val) => return write!(f, "Unknown message type {}", val),
+        };
+        f.write_str(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn opt(code: u8, data: &[u8]) -> DhcpOption {
+        DhcpOption {
+            code,
+            data: data.to_vec(),
+        }
+    }
+
+    #[test]
+    fn option_values() {
+        assert_eq!(opt(ROUTER, &[192, 168, 0, 1]).value(),
+                   OptionValue::Ips(vec![Ipv4Addr::new(192, 168, 0, 1)]));
+        assert_eq!(opt(SUBNET_MASK, &[255, 255, 255, 0]).value(),
+                   OptionValue::Ip(Ipv4Addr::new(255, 255, 255, 0)));
+        assert_eq!(opt(IP_ADDRESS_LEASE_TIME, &[0, 0, 28, 32]).value(), OptionValue::U32(7200));
+        assert_eq!(opt(INTERFACE_MTU, &[5, 220]).value(), OptionValue::U16(1500));
+        assert_eq!(opt(DHCP_MESSAGE_TYPE, &[1]).value(), OptionValue::U8(1));
+        assert_eq!(opt(HOST_NAME, b"foo").value(), OptionValue::Str("foo".to_string()));
+        assert_eq!(opt(ROUTER, &[1, 2, 3]).value(), OptionValue::Raw(vec![1, 2, 3]));
+        assert_eq!(opt(250, &[1, 2]).value(), OptionValue::Raw(vec![1, 2]));
+
+        let v = OptionValue::Ips(vec![Ipv4Addr::new(8, 8, 8, 8), Ipv4Addr::new(8, 8, 4, 4)]);
+        assert_eq!(DhcpOption::from_value(DOMAIN_NAME_SERVER, &v).value(), v);
+    }
+
+    #[test]
+    fn relay_agent_info() {
+        let d = [AGENT_CIRCUIT_ID, 2, 0, 7, 9, 1, 0xff, AGENT_REMOTE_ID, 3, b'a', b'b', b'c'];
+        let r = RelayAgentInfo::parse(&d).unwrap();
+        assert_eq!(r.circuit_id(), Some(&[0, 7][..]));
+        assert_eq!(r.remote_id(), Some(&b"abc"[..]));
+        assert_eq!(r.sub_option(9), Some(&[0xff][..]));
+        assert_eq!(r.sub_options.len(), 3);
+        assert_eq!(r.encode(), d.to_vec());
+
+        assert_eq!(RelayAgentInfo::parse(&[AGENT_CIRCUIT_ID, 4, 0]), Err(DecodeError::Truncated));
+        assert_eq!(RelayAgentInfo::parse(&[AGENT_CIRCUIT_ID]), Err(DecodeError::Truncated));
+    }
+
+    #[test]
+    fn for_server() {
+        use crate::packet::PacketBuilder;
+
+        let p = PacketBuilder::new()
+            .option(opt(SERVER_IDENTIFIER, &[10, 0, 0, 1]))
+            .build();
+        assert!(is_for_server(&p, Ipv4Addr::new(10, 0, 0, 1)));
+        assert!(!is_for_server(&p, Ipv4Addr::new(10, 0, 0, 2)));
+        assert!(!is_for_server(&PacketBuilder::new().build(), Ipv4Addr::new(10, 0, 0, 1)));
+        assert!(is_for_server(&p, Ipv4Addr::UNSPECIFIED));
+        assert!(is_for_server(&PacketBuilder::new().build(), Ipv4Addr::UNSPECIFIED));
+    }
+
+    #[test]
+    fn options_map() {
+        let mut m: DhcpOptions = vec![opt(ROUTER, &[10, 0, 0, 1, 10, 0, 0, 2]),
+                                      opt(DHCP_MESSAGE_TYPE, &[2]),
+                                      opt(SUBNET_MASK, &[255, 255, 0, 0]),
+                                      opt(ROUTER, &[10, 0, 0, 9])]
+            .into();
+        assert_eq!(m.len(), 3);
+        assert_eq!(m.router(), Some(Ipv4Addr::new(10, 0, 0, 1)));
+        assert_eq!(m.subnet_mask(), Some(Ipv4Addr::new(255, 255, 0, 0)));
+        assert!(matches!(m.message_type(), Some(MessageType::Offer)));
+        assert_eq!(m.lease_time(), None);
+        assert_eq!(m.mtu(), None);
+
+        m.insert(DhcpOption::mtu(1500));
+        assert_eq!(m.get(INTERFACE_MTU), Some(&[5, 220][..]));
+        assert_eq!(m.mtu(), Some(1500));
+        m.insert(DhcpOption::broadcast_address(Ipv4Addr::new(10, 0, 255, 255)));
+        assert_eq!(m.broadcast_address(), Some(Ipv4Addr::new(10, 0, 255, 255)));
+        m.remove(INTERFACE_MTU);
+        m.remove(BROADCAST_ADDRESS);
+        m.insert(DhcpOption::from_duration(IP_ADDRESS_LEASE_TIME, Duration::from_secs(60)));
+        assert_eq!(m.lease_time(), Some(Duration::from_secs(60)));
+        assert_eq!(m.remove(SUBNET_MASK).map(|o| o.data), Some(vec![255, 255, 0, 0]));
+        assert_eq!(m.iter().map(|(c, _)| c).collect::<Vec<_>>(),
+                   vec![ROUTER, IP_ADDRESS_LEASE_TIME, DHCP_MESSAGE_TYPE]);
+        assert_eq!(m.into_vec().len(), 3);
+    }
+
+    #[test]
+    fn address_lists() {
+        let m: DhcpOptions = vec![opt(ROUTER, &[10, 0, 0, 1, 10, 0, 0, 2]),
+                                  opt(DOMAIN_NAME_SERVER, &[9, 9, 9, 9])]
+            .into();
+        assert_eq!(m.routers(), Some(vec![Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 2)]));
+        assert_eq!(m.dns(), Some(vec![Ipv4Addr::new(9, 9, 9, 9)]));
+
+        // Empty and partial addresses are rejected rather than read as a garbage address
+        for bad in [&[][..], &[10, 0, 0, 1, 10][..], &[10, 0, 0][..]].iter() {
+            let m: DhcpOptions = vec![opt(ROUTER, bad), opt(DOMAIN_NAME_SERVER, bad)].into();
+            assert_eq!(m.routers(), None, "{:?}", bad);
+            assert_eq!(m.router(), None, "{:?}", bad);
+            assert_eq!(m.dns(), None, "{:?}", bad);
+        }
+        assert_eq!(DhcpOptions::default().router(), None);
+    }
+
+    #[test]
+    fn vendor_options() {
+        let d = [PXE_DISCOVERY_CONTROL, 1, 8, PAD, PXE_BOOT_MENU, 2, 0, 1, END];
+        let v = VendorOptions::parse(&d);
+        assert_eq!(v.sub_option(PXE_DISCOVERY_CONTROL), Some(&[8][..]));
+        assert_eq!(v.sub_option(PXE_BOOT_MENU), Some(&[0, 1][..]));
+        assert_eq!(v.encode(), vec![PXE_DISCOVERY_CONTROL, 1, 8, PXE_BOOT_MENU, 2, 0, 1, END]);
+
+        // No END, and a truncated trailer
+        let v = VendorOptions::parse(&[PXE_DISCOVERY_CONTROL, 1, 3, PXE_BOOT_MENU, 9, 0]);
+        assert_eq!(v.sub_options, vec![(PXE_DISCOVERY_CONTROL, vec![3])]);
+    }
+
+    #[test]
+    fn classless_routes() {
+        let routes = vec![ClasslessRoute {
+                              dest: Ipv4Addr::new(0, 0, 0, 0),
+                              prefix: 0,
+                              gateway: Ipv4Addr::new(192, 168, 0, 1),
+                          },
+                          ClasslessRoute {
+                              dest: Ipv4Addr::new(10, 0, 0, 0),
+                              prefix: 8,
+                              gateway: Ipv4Addr::new(192, 168, 0, 2),
+                          }];
+        let d = ClasslessRoute::encode(&routes);
+        assert_eq!(d, vec![0, 192, 168, 0, 1, 8, 10, 192, 168, 0, 2]);
+        assert_eq!(ClasslessRoute::decode(&d), Ok(routes));
+
+        let r = ClasslessRoute::decode(&[20, 172, 16, 32, 10, 0, 0, 1]).unwrap();
+        assert_eq!(r[0].dest, Ipv4Addr::new(172, 16, 32, 0));
+        assert_eq!(ClasslessRoute::decode(&[8, 10, 192, 168]), Err(DecodeError::Truncated));
+        assert_eq!(ClasslessRoute::decode(&[33, 0, 0, 0, 0, 0, 0, 0, 0]),
+                   Err(DecodeError::InvalidPrefixLength(33)));
+    }
+
+    #[test]
+    fn client_fqdn() {
+        let d = [FQDN_FLAG_E | FQDN_FLAG_S, 0, 0, 4, b'h', b'o', b's', b't', 7, b'e', b'x', b'a',
+                 b'm', b'p', b'l', b'e', 3, b'c', b'o', b'm', 0];
+        let f = ClientFqdn::parse(&d).unwrap();
+        assert_eq!(f.name, "host.example.com");
+        assert!(!f.partial);
+        assert_eq!(f.encode(), d.to_vec());
+
+        let f = ClientFqdn::parse(&[FQDN_FLAG_E, 0, 0, 4, b'h', b'o', b's', b't']).unwrap();
+        assert_eq!(f.name, "host");
+        assert!(f.partial);
+
+        let f = ClientFqdn::parse(b"\0\xff\xffhost.example.com.").unwrap();
+        assert_eq!((f.rcode1, f.rcode2), (255, 255));
+        assert_eq!(f.name, "host.example.com");
+        assert!(!f.partial);
+        assert_eq!(f.encode(), b"\0\xff\xffhost.example.com.".to_vec());
+        assert!(ClientFqdn::parse(b"\0\0\0host").unwrap().partial);
+
+        assert_eq!(ClientFqdn::parse(&[0, 0]), Err(DecodeError::Truncated));
+        assert_eq!(ClientFqdn::parse(&[FQDN_FLAG_E, 0, 0, 5, b'h']), Err(DecodeError::Truncated));
+        assert_eq!(ClientFqdn::parse(&[FQDN_FLAG_E, 0, 0, 0xc0, 12]),
+                   Err(DecodeError::InvalidName));
+    }
+
+    #[test]
+    fn ip_lists() {
+        let ips = [Ipv4Addr::new(8, 8, 8, 8), Ipv4Addr::new(8, 8, 4, 4)];
+        let d = encode_ips(&ips);
+        assert_eq!(d, vec![8, 8, 8, 8, 8, 8, 4, 4]);
+        assert_eq!(decode_ips(&d), Ok(ips.to_vec()));
+        assert_eq!(decode_ips(&[]), Ok(vec![]));
+        assert_eq!(decode_ips(&d[..7]), Err(DecodeError::Truncated));
+
+        let o = DhcpOption { code: DOMAIN_NAME_SERVER, data: d };
+        assert_eq!(o.ip_list(), Ok(ips.to_vec()));
+        assert_eq!(o.value(), OptionValue::Ips(ips.to_vec()));
+    }
+
+    #[test]
+    fn standard_offers() {
+        let mut config = OfferConfig {
+            subnet: Ipv4Addr::new(255, 255, 255, 0),
+            router: Ipv4Addr::new(10, 0, 0, 1),
+            dns: vec![Ipv4Addr::new(10, 0, 0, 2), Ipv4Addr::new(10, 0, 0, 3)],
+            lease: Duration::from_secs(7200),
+            domain: Some("example.org".to_string()),
+        };
+        let opts = standard_offer(&config);
+        let codes: Vec<u8> = opts.iter().map(|o| o.code).collect();
+        assert_eq!(codes,
+                   vec![SUBNET_MASK, ROUTER, DOMAIN_NAME_SERVER, DOMAIN_NAME, IP_ADDRESS_LEASE_TIME]);
+        assert_eq!(opts[1].data, vec![10, 0, 0, 1]);
+        assert_eq!(opts[2].ip_list(), Ok(config.dns.clone()));
+        assert_eq!(opts[3].as_str().as_deref(), Some("example.org"));
+        assert_eq!(opts[4].lease_time(), Some(Duration::from_secs(7200)));
+
+        config.dns.clear();
+        config.domain = None;
+        let codes: Vec<u8> = standard_offer(&config).iter().map(|o| o.code).collect();
+        assert_eq!(codes, vec![SUBNET_MASK, ROUTER, IP_ADDRESS_LEASE_TIME]);
+    }
+
+    #[test]
+    fn single_ips() {
+        let ip = Ipv4Addr::new(192, 168, 0, 1);
+        assert_eq!(ip_to_bytes(ip), [192, 168, 0, 1]);
+        assert_eq!(bytes_to_ip(&ip_to_bytes(ip)), Some(ip));
+        assert_eq!(bytes_to_ip(&[192, 168, 0]), None);
+        assert_eq!(bytes_to_ip(&[192, 168, 0, 1, 0]), None);
+    }
+
+    #[test]
+    fn user_classes() {
+        let u = UserClasses::parse(b"\x04RRAS\x06kiosks");
+        assert_eq!(u.classes, vec![b"RRAS".to_vec(), b"kiosks".to_vec()]);
+        assert_eq!(u.encode(), b"\x04RRAS\x06kiosks".to_vec());
+
+        // Windows style single name
+        assert_eq!(UserClasses::parse(b"RRAS.Microsoft").classes, vec![b"RRAS.Microsoft".to_vec()]);
+        assert_eq!(UserClasses::parse(b"\x04RRAS\x00").classes, vec![b"\x04RRAS\x00".to_vec()]);
+        assert!(UserClasses::parse(b"").classes.is_empty());
+    }
+
+    #[test]
+    fn authentication() {
+        let d = [AUTH_PROTOCOL_DELAYED, AUTH_ALGORITHM_HMAC_MD5, AUTH_RDM_MONOTONIC, 0, 0, 0, 0, 0,
+                 0, 1, 2, 0, 0, 0, 7, 0xaa, 0xbb];
+        let a = Authentication::parse(&d).unwrap();
+        assert_eq!(a.protocol, AUTH_PROTOCOL_DELAYED);
+        assert_eq!(a.replay_detection, 0x102);
+        assert_eq!(a.auth_info, vec![0, 0, 0, 7, 0xaa, 0xbb]);
+        assert_eq!(a.encode(), d.to_vec());
+
+        // Unknown protocols are kept for the handler to reject
+        let a = Authentication::parse(&[42, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]).unwrap();
+        assert_eq!(a.protocol, 42);
+        assert!(a.auth_info.is_empty());
+        assert_eq!(Authentication::parse(&d[..10]), Err(DecodeError::Truncated));
+    }
+
+    #[test]
+    fn string_options() {
+        let o = DhcpOption::string(WPAD, "http://proxy/wpad.dat");
+        assert_eq!(o.data, b"http://proxy/wpad.dat");
+        assert_eq!(o.as_str().as_deref(), Some("http://proxy/wpad.dat"));
+        assert_eq!(o.value(), OptionValue::Str("http://proxy/wpad.dat".to_string()));
+        assert_eq!(opt(HOST_NAME, b"pc\0").as_str().as_deref(), Some("pc"));
+        assert_eq!(opt(HOST_NAME, b"caf\xe9").as_str().as_deref(), Some("caf\u{fffd}"));
+        assert_eq!(opt(CLIENT_IDENTIFIER, &[1, 0, 2, 3]).as_str(), None);
+
+        let name = "é".repeat(200);
+        assert_eq!(DhcpOption::tftp_server_name(&name).as_str().as_deref(), Some(&*name));
+
+        let m = DhcpOption::message(&"a".repeat(300));
+        assert_eq!((m.code, m.data.len()), (MESSAGE, 255));
+        assert_eq!(DhcpOption::message(&"é".repeat(300)).data.len(), 254);
+        assert_eq!(DhcpOption::message("No lease").data, b"No lease");
+    }
+
+    #[test]
+    fn equality_and_hashing() {
+        let p = crate::packet::PacketBuilder::new()
+            .message_type(MessageType::Request)
+            .option(DhcpOption::tz_database("UTC"))
+            .build();
+        assert_eq!(p.options().nth(1), Some(&DhcpOption::tz_database("UTC")));
+        assert_eq!(p.message_type(), Ok(MessageType::Request));
+        assert_ne!(DhcpOption::mtu(1500), DhcpOption::mtu(9000));
+
+        let mut seen = std::collections::HashSet::new();
+        assert!(seen.insert(DhcpOption::mtu(1500)));
+        assert!(!seen.insert(DhcpOption::mtu(1500)));
+        let types: std::collections::HashSet<MessageType> =
+            [MessageType::Offer, MessageType::Ack, MessageType::Offer].iter().copied().collect();
+        assert_eq!(types.len(), 2);
+    }
+
+    #[test]
+    fn timezones() {
+        let posix = DhcpOption::tz_posix("CET-1CEST,M3.5.0,M10.5.0/3");
+        assert_eq!((posix.code, &posix.data[..]), (100, &b"CET-1CEST,M3.5.0,M10.5.0/3"[..]));
+        let m: DhcpOptions = vec![posix, DhcpOption::tz_database("Europe/Zurich")].into();
+        assert_eq!(m.tz_posix().as_deref(), Some("CET-1CEST,M3.5.0,M10.5.0/3"));
+        assert_eq!(m.tz_database().as_deref(), Some("Europe/Zurich"));
+        assert_eq!(DhcpOptions::from(vec![opt(TZ_DATABASE_STRING, b"UTC\0")]).tz_database(),
+                   Some(Cow::Borrowed("UTC")));
+        assert_eq!(DhcpOptions::default().tz_posix(), None);
+    }
+
+    #[test]
+    fn domain_search() {
+        let d = DomainSearch::encode(&["eng.example.com", "example.com", "sales.example.com."]);
+        let mut expected = b"\x03eng\x07example\x03com\x00".to_vec();
+        expected.extend_from_slice(&[0xc0, 4]);
+        expected.extend_from_slice(b"\x05sales\xc0\x04");
+        assert_eq!(d, expected);
+        assert_eq!(DomainSearch::decode(&d).unwrap(),
+                   vec!["eng.example.com", "example.com", "sales.example.com"]);
+        let d = DomainSearch::encode(&["", "example.com", ".", ".."]);
+        assert_eq!(d, b"\x07example\x03com\x00");
+        assert_eq!(DomainSearch::decode(&d).unwrap(), vec!["example.com"]);
+
+        // Pointers to themselves or forwards would loop
+        assert_eq!(DomainSearch::decode(&[0xc0, 0]), Err(DecodeError::InvalidName));
+        assert_eq!(DomainSearch::decode(&[1, b'a', 0xc0, 4, 0]), Err(DecodeError::InvalidName));
+        assert_eq!(DomainSearch::decode(&[1, b'a', 0xc0, 0]), Err(DecodeError::InvalidName));
+        let d = [1, b'a', 0, 1, b'b', 0xc0, 0, 1, b'c', 0xc0, 3];
+        assert_eq!(DomainSearch::decode(&d).unwrap(), vec!["a", "b.a", "c.b.a"]);
+        assert_eq!(DomainSearch::decode(&[3, b'c', b'o']), Err(DecodeError::Truncated));
+        assert_eq!(DomainSearch::decode(&[3, b'c', b'o', b'm']), Err(DecodeError::Truncated));
+        assert_eq!(DomainSearch::decode(&[]), Ok(vec![]));
+    }
+
+    #[test]
+    fn infinite_leases() {
+        let o = DhcpOption::from_lease_time(IP_ADDRESS_LEASE_TIME, LeaseTime::Infinite);
+        assert_eq!(o.data, vec![255, 255, 255, 255]);
+        assert_eq!(o.as_lease_time(), Some(LeaseTime::Infinite));
+
+        let hour = LeaseTime::Finite(Duration::from_secs(3600));
+        let o = DhcpOption::from_lease_time(IP_ADDRESS_LEASE_TIME, hour);
+        assert_eq!(o.data, vec![0, 0, 14, 16]);
+        assert_eq!(o.as_lease_time(), Some(hour));
+
+        let long = LeaseTime::Finite(Duration::from_secs(1 << 40));
+        assert_eq!(long.encode(), [255, 255, 255, 254]);
+        assert_eq!(LeaseTime::decode(&[0, 0, 1]), Err(DecodeError::Truncated));
+        assert_eq!(opt(INTERFACE_MTU, &[255, 255, 255, 255]).as_lease_time(), None);
+    }
+
+    #[test]
+    fn lease_time_durations() {
+        let o = DhcpOption::from_duration(IP_ADDRESS_LEASE_TIME, Duration::from_secs(7200));
+        assert_eq!(o.data, vec![0, 0, 28, 32]);
+        assert_eq!(o.lease_time(), Some(Duration::from_secs(7200)));
+
+        let o = DhcpOption::from_duration(RENEWAL_TIME_VALUE, Duration::from_secs(1 << 40));
+        assert_eq!(o.data, vec![255, 255, 255, 255]);
+        assert_eq!(o.lease_time(), Some(Duration::from_secs(u32::MAX as u64)));
+
+        assert_eq!(opt(IP_ADDRESS_LEASE_TIME, &[1, 2]).lease_time(), None);
+        assert_eq!(opt(INTERFACE_MTU, &[0, 0, 5, 220]).lease_time(), None);
+    }
+
+    #[test]
+    fn message_type_round_trip() {
+        for val in 0..=255u8 {
+            assert_eq!(MessageType::from_u8(val).to_u8(), val);
+        }
+        let t: MessageType = 5.into();
+        assert_eq!(t, MessageType::Ack);
+        assert_eq!(MessageType::from_u8(8), MessageType::Inform);
+        assert_eq!(MessageType::from_u8(13), MessageType::LeaseActive);
+        assert_eq!(MessageType::from_u8(200), MessageType::Unknown(200));
+        #[allow(deprecated)]
+        let old = MessageType::from(1);
+        assert_eq!(old, Ok(MessageType::Discover));
+        assert_eq!(u8::from(MessageType::Release), 7);
+        assert_eq!(MessageType::Discover.to_string(), "DHCPDISCOVER");
+        assert_eq!(MessageType::LeaseQueryDone.to_string(), "DHCPLEASEQUERYDONE");
+        assert_eq!(MessageType::Unknown(99).to_string(), "Unknown message type 99");
     }
 }
@@ -0,0 +1,264 @@
+//! Typed PXE sub-options of Vendor-Specific Information (option 43), as defined by the PXE
+//! specification (version 2.1), for presenting a boot menu to PXE clients.
+
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::net::Ipv4Addr;
+
+use super::{decode_ips, encode_ips, DecodeError, DhcpOption, VendorOptions,
+            PXE_BOOT_MENU, PXE_BOOT_SERVERS, PXE_DISCOVERY_CONTROL, PXE_MENU_PROMPT,
+            VENDOR_SPECIFIC_INFORMATION};
+
+/// PXE_DISCOVERY_CONTROL bit: do not discover boot servers by broadcast.
+pub const DISABLE_BROADCAST: u8 = 0x01;
+/// PXE_DISCOVERY_CONTROL bit: do not discover boot servers by multicast.
+pub const DISABLE_MULTICAST: u8 = 0x02;
+/// PXE_DISCOVERY_CONTROL bit: only accept boot servers listed in PXE_BOOT_SERVERS.
+pub const SERVER_LIST_ONLY: u8 = 0x04;
+/// PXE_DISCOVERY_CONTROL bit: skip discovery and download the bootfile named in the offer.
+pub const USE_BOOTFILE: u8 = 0x08;
+
+/// The boot server type of the menu entry booting from the local disk.
+pub const LOCAL_BOOT: u16 = 0;
+
+/// The most data a sub-option's one byte length can describe.
+const MAX_SUB_OPTION_LEN: usize = 255;
+
+/// A boot server type and the addresses of the servers of that type, for PXE_BOOT_SERVERS.
+/// Addresses past the 255 bytes the sub-option can hold are left out when encoding.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BootServer {
+    pub server_type: u16,
+    pub addresses: Vec<Ipv4Addr>,
+}
+
+/// An entry of the boot menu (PXE_BOOT_MENU), booting from servers of `server_type`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MenuItem {
+    pub server_type: u16,
+    /// Shown to the user. Descriptions over 255 bytes are truncated, and entries past the 255
+    /// bytes PXE_BOOT_MENU can hold are left out.
+    pub description: String,
+}
+
+/// The prompt shown before the boot menu (PXE_MENU_PROMPT).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MenuPrompt {
+    /// Seconds to wait for a key before booting the first menu entry. 255 waits forever,
+    /// and 0 boots the first entry straight away.
+    pub timeout: u8,
+    /// Shown with the remaining seconds, e.g. "Press F8 for the boot menu". Prompts over 254
+    /// bytes are truncated.
+    pub prompt: String,
+}
+
+/// The PXE sub-options understood by this module. Encoded in ascending code order, with any
+/// left unset omitted.
+///
+/// ```
+/// use dhcp4r::options::pxe::{MenuItem, MenuPrompt, PxeOptions, LOCAL_BOOT};
+///
+/// let pxe = PxeOptions {
+///     menu: vec![MenuItem { server_type: LOCAL_BOOT, description: "Local disk".into() }],
+///     prompt: Some(MenuPrompt { timeout: 10, prompt: "Press F8".into() }),
+///     ..PxeOptions::default()
+/// };
+/// let option = pxe.to_option();
+/// assert_eq!(PxeOptions::parse(&option.data), Ok(pxe));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PxeOptions {
+    /// PXE_DISCOVERY_CONTROL bits, such as `DISABLE_MULTICAST`.
+    pub discovery_control: Option<u8>,
+    pub boot_servers: Vec<BootServer>,
+    pub menu: Vec<MenuItem>,
+    pub prompt: Option<MenuPrompt>,
+}
+
+impl PxeOptions {
+    /// Decodes the sub-options this module knows from option 43 data, ignoring any others.
+    /// Fails if one of them is malformed.
+    pub fn parse(data: &[u8]) -> Result<PxeOptions, DecodeError> {
+        let mut pxe = PxeOptions::default();
+        for (code, d) in VendorOptions::parse(data).sub_options {
+            match code {
+                PXE_DISCOVERY_CONTROL => {
+                    pxe.discovery_control = Some(*d.first().ok_or(DecodeError::Truncated)?);
+                }
+                PXE_BOOT_SERVERS => pxe.boot_servers = parse_boot_servers(&d)?,
+                PXE_BOOT_MENU => pxe.menu = parse_menu(&d)?,
+                PXE_MENU_PROMPT => {
+                    let (&timeout, prompt) = d.split_first().ok_or(DecodeError::Truncated)?;
+                    pxe.prompt = Some(MenuPrompt {
+                        timeout,
+                        prompt: String::from_utf8_lossy(prompt).into_owned(),
+                    });
+                }
+                _ => {}
+            }
+        }
+        Ok(pxe)
+    }
+
+    /// Encodes the sub-options as option 43 data, ending with END.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut subs = Vec::new();
+        if let Some(bits) = self.discovery_control {
+            subs.push((PXE_DISCOVERY_CONTROL, vec![bits]));
+        }
+        if !self.boot_servers.is_empty() {
+            let mut d = Vec::new();
+            for s in &self.boot_servers {
+                // Addresses that would not fit in the 255 byte sub-option are left out
+                let room = (MAX_SUB_OPTION_LEN.saturating_sub(d.len() + 3)) / 4;
+                let addresses = &s.addresses[..s.addresses.len().min(room)];
+                if addresses.is_empty() {
+                    break;
+                }
+                d.extend_from_slice(&s.server_type.to_be_bytes());
+                d.push(addresses.len() as u8);
+                d.extend(encode_ips(addresses));
+            }
+            subs.push((PXE_BOOT_SERVERS, d));
+        }
+        if !self.menu.is_empty() {
+            let mut d = Vec::new();
+            for item in &self.menu {
+                let description = truncated(&item.description, 255);
+                // Entries that would not fit in the 255 byte sub-option are left out
+                if d.len() + 3 + description.len() > MAX_SUB_OPTION_LEN {
+                    break;
+                }
+                d.extend_from_slice(&item.server_type.to_be_bytes());
+                d.push(description.len() as u8);
+                d.extend_from_slice(description);
+            }
+            subs.push((PXE_BOOT_MENU, d));
+        }
+        if let Some(ref p) = self.prompt {
+            let mut d = vec![p.timeout];
+            d.extend_from_slice(truncated(&p.prompt, 254));
+            subs.push((PXE_MENU_PROMPT, d));
+        }
+        VendorOptions { sub_options: subs }.encode()
+    }
+
+    /// Builds the VENDOR_SPECIFIC_INFORMATION option carrying the sub-options.
+    pub fn to_option(&self) -> DhcpOption {
+        DhcpOption {
+            code: VENDOR_SPECIFIC_INFORMATION,
+            data: self.encode(),
+        }
+    }
+}
+
+fn truncated(s: &str, max: usize) -> &[u8] {
+    &s.as_bytes()[..s.len().min(max)]
+}
+
+fn parse_boot_servers(mut d: &[u8]) -> Result<Vec<BootServer>, DecodeError> {
+    let mut servers = Vec::new();
+    while !d.is_empty() {
+        if d.len() < 3 {
+            return Err(DecodeError::Truncated);
+        }
+        let end = 3 + 4 * d[2] as usize;
+        if d.len() < end {
+            return Err(DecodeError::Truncated);
+        }
+        servers.push(BootServer {
+            server_type: bytes_u16!(d),
+            addresses: decode_ips(&d[3..end])?,
+        });
+        d = &d[end..];
+    }
+    Ok(servers)
+}
+
+fn parse_menu(mut d: &[u8]) -> Result<Vec<MenuItem>, DecodeError> {
+    let mut menu = Vec::new();
+    while !d.is_empty() {
+        if d.len() < 3 {
+            return Err(DecodeError::Truncated);
+        }
+        let end = 3 + d[2] as usize;
+        if d.len() < end {
+            return Err(DecodeError::Truncated);
+        }
+        menu.push(MenuItem {
+            server_type: bytes_u16!(d),
+            description: String::from_utf8_lossy(&d[3..end]).into_owned(),
+        });
+        d = &d[end..];
+    }
+    Ok(menu)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::END;
+    use alloc::string::ToString;
+
+    #[test]
+    fn one_entry_menu() {
+        let pxe = PxeOptions {
+            discovery_control: Some(DISABLE_MULTICAST | SERVER_LIST_ONLY),
+            boot_servers: vec![BootServer {
+                                   server_type: 0x8000,
+                                   addresses: vec![Ipv4Addr::new(10, 0, 0, 5)],
+                               }],
+            menu: vec![MenuItem {
+                           server_type: 0x8000,
+                           description: "Installer".to_string(),
+                       }],
+            prompt: Some(MenuPrompt {
+                timeout: 5,
+                prompt: "Boot".to_string(),
+            }),
+        };
+        let d = pxe.encode();
+        assert_eq!(d,
+                   vec![PXE_DISCOVERY_CONTROL, 1, 0x06,
+                        PXE_BOOT_SERVERS, 7, 0x80, 0, 1, 10, 0, 0, 5,
+                        PXE_BOOT_MENU, 12, 0x80, 0, 9, b'I', b'n', b's', b't', b'a', b'l', b'l',
+                        b'e', b'r',
+                        PXE_MENU_PROMPT, 5, 5, b'B', b'o', b'o', b't',
+                        END]);
+        assert_eq!(PxeOptions::parse(&d), Ok(pxe));
+        assert_eq!(PxeOptions::default().encode(), vec![END]);
+    }
+
+    #[test]
+    fn oversized_menu() {
+        let item = |c: char| MenuItem {
+            server_type: 0x8000,
+            description: c.to_string().repeat(100),
+        };
+        let server = |n| BootServer {
+            server_type: 0x8000,
+            addresses: vec![Ipv4Addr::new(10, 0, 0, 5); n],
+        };
+        let pxe = PxeOptions {
+            boot_servers: vec![server(40), server(40)],
+            menu: vec![item('a'), item('b'), item('c')],
+            ..PxeOptions::default()
+        };
+        // Whole entries are dropped from the end until each sub-option fits
+        let parsed = PxeOptions::parse(&pxe.encode()).unwrap();
+        assert_eq!(parsed.menu, pxe.menu[..2]);
+        assert_eq!(parsed.boot_servers, vec![server(40), server(22)]);
+    }
+
+    #[test]
+    fn malformed() {
+        assert_eq!(PxeOptions::parse(&[PXE_BOOT_MENU, 4, 0x80, 0, 9, b'I']),
+                   Err(DecodeError::Truncated));
+        assert_eq!(PxeOptions::parse(&[PXE_BOOT_SERVERS, 7, 0x80, 0, 2, 10, 0, 0, 5]),
+                   Err(DecodeError::Truncated));
+        assert_eq!(PxeOptions::parse(&[PXE_MENU_PROMPT, 0]), Err(DecodeError::Truncated));
+        // Other sub-options are left alone
+        assert_eq!(PxeOptions::parse(&[1, 2, 0, 0, END]), Ok(PxeOptions::default()));
+    }
+}
@@ -1,73 +1,514 @@
-use options::*;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::error::Error;
+use core::fmt;
+use core::net::Ipv4Addr;
+
+use crate::options::*;
+
+mod borrowed;
+pub use self::borrowed::{PacketRef, RawOptions};
 
 /// DHCP Packet Structure
-pub struct Packet<'a> {
+///
+/// With the `serde` feature, addresses serialize as dotted strings, the chaddr field as colon
+/// separated hex bytes and sname/file as byte lists without their trailing zeros.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Packet {
     pub reply: bool, // false = request, true = reply
     pub hops: u8,
     pub xid: [u8; 4], // Random identifier
     pub secs: u16,
-    pub broadcast: bool,
+    pub flags: Flags,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_fmt::ip"))]
     pub ciaddr: [u8; 4],
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_fmt::ip"))]
     pub yiaddr: [u8; 4],
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_fmt::ip"))]
     pub siaddr: [u8; 4],
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_fmt::ip"))]
     pub giaddr: [u8; 4],
-    pub chaddr: [u8; 6],
-    pub options: Vec<DhcpOption<'a>>,
+    /// Hardware type, 1 for Ethernet; see `hardware_type`.
+    pub htype: u8,
+    /// Length of the hardware address in `hardware`.
+    pub hlen: u8,
+    /// The whole chaddr field; see `hardware_address`, and `chaddr` for an Ethernet address.
+    #[cfg_attr(feature = "serde", serde(rename = "chaddr", with = "crate::serde_fmt::mac"))]
+    pub hardware: [u8; 16],
+    /// Optional server host name, null terminated.
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_fmt::field"))]
+    pub sname: [u8; 64],
+    /// Boot file name, null terminated.
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_fmt::field"))]
+    pub file: [u8; 128],
+    pub options: Vec<DhcpOption>,
 }
 
-/// Parses Packet from byte array
-pub fn decode(p: &[u8]) -> Result<Packet, &'static str> {
-    if p[236..240] != COOKIE {
-        return Err("Invalid Cookie");
+/// The BOOTP flags field. Only the top bit, BROADCAST, is defined (RFC 2131 section 2). The
+/// reserved bits are kept as received, for relays that check them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
+pub struct Flags(u16);
+
+impl Flags {
+    pub const BROADCAST: u16 = 0x8000;
+
+    pub fn broadcast(self) -> bool {
+        self.0 & Flags::BROADCAST != 0
     }
 
+    pub fn set_broadcast(&mut self, broadcast: bool) {
+        if broadcast {
+            self.0 |= Flags::BROADCAST;
+        } else {
+            self.0 &= !Flags::BROADCAST;
+        }
+    }
+}
+
+impl From<u16> for Flags {
+    fn from(v: u16) -> Flags {
+        Flags(v)
+    }
+}
+
+impl From<Flags> for u16 {
+    fn from(f: Flags) -> u16 {
+        f.0
+    }
+}
+
+/// The htype field, from the ARP hardware types of RFC 1700 (now kept by IANA), naming the
+/// kind of hardware address the client has.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HardwareType {
+    Ethernet,
+    Ieee802,
+    Arcnet,
+    Other(u8),
+}
+
+impl HardwareType {
+    /// The length of this type's addresses, for the types with a fixed one: 6 for Ethernet and
+    /// IEEE 802 networks, 1 for ARCNET.
+    pub fn address_len(self) -> Option<usize> {
+        match self {
+            HardwareType::Ethernet | HardwareType::Ieee802 => Some(6),
+            HardwareType::Arcnet => Some(1),
+            HardwareType::Other(_) => None,
+        }
+    }
+}
+
+impl From<u8> for HardwareType {
+    fn from(v: u8) -> HardwareType {
+        match v {
+            1 => HardwareType::Ethernet,
+            6 => HardwareType::Ieee802,
+            7 => HardwareType::Arcnet,
+            _ => HardwareType::Other(v),
+        }
+    }
+}
+
+impl From<HardwareType> for u8 {
+    fn from(t: HardwareType) -> u8 {
+        match t {
+            HardwareType::Ethernet => 1,
+            HardwareType::Ieee802 => 6,
+            HardwareType::Arcnet => 7,
+            HardwareType::Other(v) => v,
+        }
+    }
+}
+
+/// Reasons a byte array could not be parsed as a DHCP packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseError {
+    /// Buffer is too short to hold the BOOTP header and magic cookie.
+    TooShort,
+    /// Bytes 236..240 do not contain the DHCP magic cookie.
+    BadMagicCookie,
+    /// The op field is neither BOOTREQUEST nor BOOTREPLY.
+    InvalidOpCode(u8),
+    /// The options section ends without an END option.
+    UnterminatedOptions,
+    /// An option claims more data than remains in the buffer.
+    InvalidOptionLength,
+    /// hlen exceeds the 16 bytes of the chaddr field.
+    InvalidHardwareLength(u8),
+    /// An option that cannot be split across instances appears more than once. Only reported
+    /// by `Packet::from_bytes_strict`.
+    DuplicateOption(u8),
+    /// The text is not pairs of hex digits, apart from whitespace. Only reported by
+    /// `Packet::from_hex`.
+    InvalidHex,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseError::TooShort => write!(f, "Packet too short"),
+            ParseError::BadMagicCookie => write!(f, "Invalid Cookie"),
+            ParseError::InvalidOpCode(op) => write!(f, "Invalid OpCode: {}", op),
+            ParseError::UnterminatedOptions => write!(f, "Options not terminated"),
+            ParseError::InvalidOptionLength => write!(f, "Invalid option length"),
+            ParseError::InvalidHardwareLength(l) => {
+                write!(f, "Invalid hardware address length: {}", l)
+            }
+            ParseError::DuplicateOption(code) => write!(f, "Duplicate option: {}", code),
+            ParseError::InvalidHex => write!(f, "Invalid hex dump"),
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+/// Reasons `Packet::validate` finds a packet would be malformed on the wire.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValidationError {
+    /// hlen exceeds the 16 bytes of the chaddr field, or is not the address length of the
+    /// hardware type, as for an Ethernet htype without a 6 byte address.
+    InvalidHardwareLength(u8),
+    /// An option has the code of PAD or END, which would end or corrupt the options field.
+    ReservedOptionCode(u8),
+    /// OVERLOAD says options continue in sname or file, but the field does not hold
+    /// options terminated by END.
+    InvalidOverloadedField,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ValidationError::InvalidHardwareLength(l) => {
+                write!(f, "Invalid hardware address length: {}", l)
+            }
+            ValidationError::ReservedOptionCode(c) => write!(f, "Reserved option code: {}", c),
+            ValidationError::InvalidOverloadedField => {
+                write!(f, "Overloaded field does not hold valid options")
+            }
+        }
+    }
+}
+
+impl Error for ValidationError {}
+
+/// Parses a packet received from the network, as `Packet::from_bytes` does. This is the entry
+/// point the server uses, and never panics, whatever the input, so it is also the function to
+/// fuzz.
+pub fn parse(p: &[u8]) -> Result<Packet, ParseError> {
+    Packet::from_bytes(p)
+}
+
+/// Parses Packet from byte array. Same as `parse`.
+pub fn decode(p: &[u8]) -> Result<Packet, ParseError> {
+    parse(p)
+}
+
+/// Checks the fixed fields and magic cookie, returning whether the packet is a BOOTREPLY.
+fn check_header(p: &[u8]) -> Result<bool, ParseError> {
+    if p.len() < 240 {
+        return Err(ParseError::TooShort);
+    }
+    if p[236..240] != COOKIE {
+        return Err(ParseError::BadMagicCookie);
+    }
     let reply = match p[0] {
         BOOT_REPLY => true,
         BOOT_REQUEST => false,
-        _ => return Err("Invalid OpCode"),
+        op => return Err(ParseError::InvalidOpCode(op)),
     };
-    // TODO hlen check
+    if p[2] > 16 {
+        return Err(ParseError::InvalidHardwareLength(p[2]));
+    }
+    Ok(reply)
+}
+
+/// Parses a sequence of options terminated by END.
+/// Every code, length and data byte is bounds-checked before being read so that malformed
+/// input results in an error rather than a panic.
+fn parse_options(p: &[u8]) -> Result<Vec<DhcpOption>, ParseError> {
     let mut options = Vec::new();
-    let mut i: usize = 240;
+    let mut i = 0;
+    while let Some((code, data)) = next_option(p, &mut i)? {
+        options.push(DhcpOption {
+            code,
+            data: data.to_vec(),
+        });
+    }
+    Ok(options)
+}
+
+/// Reads the option starting at `*i`, skipping any PAD before it, and advances `*i` past it.
+/// Returns `None` at END.
+fn next_option<'a>(p: &'a [u8], i: &mut usize) -> Result<Option<(u8, &'a [u8])>, ParseError> {
     loop {
-        let l = p.len();
-        if i < l {
-            let code = p[i];
-            if code == END {
-                break;
-            }
-            if i + 2 < l {
-                let opt_end = (p[i + 1]) as usize + i + 2;
-                if opt_end < l {
-                    options.push(DhcpOption {
-                        code: code,
-                        data: &p[i + 2..opt_end],
-                    });
-                    i = opt_end;
-                    continue;
-                }
+        let code = match p.get(*i) {
+            Some(&c) => c,
+            None => return Err(ParseError::UnterminatedOptions),
+        };
+        match code {
+            END => return Ok(None),
+            PAD => {
+                *i += 1;
+                continue;
             }
+            _ => {}
         }
-        return Err("Options Problem");
+        let len = match p.get(*i + 1) {
+            Some(&l) => l as usize,
+            None => return Err(ParseError::UnterminatedOptions),
+        };
+        let data_start = *i + 2;
+        let data_end = data_start + len;
+        if data_end > p.len() {
+            return Err(ParseError::InvalidOptionLength);
+        }
+        *i = data_end;
+        return Ok(Some((code, &p[data_start..data_end])));
+    }
+}
+
+/// Concatenates repeated instances of concatenable options into the first, in the order
+/// received (RFC 3396). Repeats of other options are an error if `strict`, and kept as they are
+/// otherwise.
+fn merge_options(options: Vec<DhcpOption>, strict: bool) -> Result<Vec<DhcpOption>, ParseError> {
+    let mut merged: Vec<DhcpOption> = Vec::with_capacity(options.len());
+    for o in options {
+        match merged.iter_mut().find(|m| m.code == o.code) {
+            Some(m) if is_concatenable(o.code) => m.data.extend(o.data),
+            Some(_) if strict => return Err(ParseError::DuplicateOption(o.code)),
+            _ => merged.push(o),
+        }
+    }
+    Ok(merged)
+}
+
+/// Encoded length of an option with `len` bytes of data, including the headers of the extra
+/// instances a value over 255 bytes is split into.
+pub(crate) fn encoded_option_len(len: usize) -> usize {
+    len + 2 * len.div_ceil(255).max(1)
+}
+
+/// Values of the OVERLOAD option (52), which may be combined.
+pub const OVERLOAD_FILE: u8 = 1;
+pub const OVERLOAD_SNAME: u8 = 2;
+
+fn overload_flags(options: &[DhcpOption]) -> u8 {
+    match options.iter().find(|o| o.code == OVERLOAD) {
+        Some(o) if o.data.len() == 1 => o.data[0] & (OVERLOAD_FILE | OVERLOAD_SNAME),
+        _ => 0,
+    }
+}
+
+/// Copies a fixed size header field out of the packet.
+fn fixed_field<const N: usize>(p: &[u8]) -> [u8; N] {
+    let mut f = [0; N];
+    f.copy_from_slice(p);
+    f
+}
+
+/// Copies `s` into a null-terminated field, truncating it at a character boundary to fit.
+fn set_field(field: &mut [u8], s: &str) {
+    let mut len = s.len().min(field.len() - 1);
+    while !s.is_char_boundary(len) {
+        len -= 1;
+    }
+    field[..len].copy_from_slice(&s.as_bytes()[..len]);
+    for b in &mut field[len..] {
+        *b = 0;
+    }
+}
+
+/// Reads a null-terminated field, returning None if it is empty or not UTF-8.
+fn field_str(field: &[u8]) -> Option<&str> {
+    let len = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    match core::str::from_utf8(&field[..len]) {
+        Ok(s) if !s.is_empty() => Some(s),
+        _ => None,
     }
-    Ok(Packet {
-        reply: reply,
-        hops: p[3],
-        secs: ((p[8] as u16) << 8) + p[9] as u16,
-        broadcast: p[10] & 128 == 128,
-        ciaddr: [p[12], p[13], p[14], p[15]],
-        yiaddr: [p[16], p[17], p[18], p[19]],
-        siaddr: [p[20], p[21], p[22], p[23]],
-        giaddr: [p[24], p[25], p[26], p[27]],
-        options: options,
-        chaddr: [p[28], p[29], p[30], p[31], p[32], p[33]],
-        xid: [p[4], p[5], p[6], p[7]],
-    })
 }
 
-impl<'a> Packet<'a> {
+/// Parameters requested by `Packet::discover` and `Packet::request`.
+pub const DEFAULT_PARAMETER_REQUEST_LIST: [u8; 7] = [SUBNET_MASK,
+                                                     ROUTER,
+                                                     DOMAIN_NAME_SERVER,
+                                                     DOMAIN_NAME,
+                                                     BROADCAST_ADDRESS,
+                                                     RENEWAL_TIME_VALUE,
+                                                     REBINDING_TIME_VALUE];
+
+impl Default for Packet {
+    fn default() -> Packet {
+        Packet::new()
+    }
+}
+
+impl Packet {
+    /// Creates an empty BOOTREQUEST for an Ethernet client, with every other field zero and no
+    /// options. It encodes as a valid packet, the magic cookie and END option being added then.
+    pub fn new() -> Packet {
+        Packet {
+            reply: false,
+            hops: 0,
+            xid: [0; 4],
+            secs: 0,
+            flags: Flags::default(),
+            ciaddr: [0; 4],
+            yiaddr: [0; 4],
+            siaddr: [0; 4],
+            giaddr: [0; 4],
+            htype: 1,
+            hlen: 6,
+            hardware: [0; 16],
+            sname: [0; 64],
+            file: [0; 128],
+            options: Vec::new(),
+        }
+    }
+
+    /// Creates a broadcast DHCPDISCOVER for an Ethernet client, requesting the default
+    /// parameter list.
+    pub fn discover(xid: u32, chaddr: [u8; 6]) -> Packet {
+        PacketBuilder::new()
+            .xid(xid)
+            .chaddr(chaddr)
+            .broadcast(true)
+            .message_type(MessageType::Discover)
+            .option(DhcpOption {
+                code: PARAMETER_REQUEST_LIST,
+                data: DEFAULT_PARAMETER_REQUEST_LIST.to_vec(),
+            })
+            .build()
+    }
+
+    /// Creates a broadcast DHCPREQUEST selecting `requested_ip` as offered by `server`.
+    pub fn request(xid: u32, chaddr: [u8; 6], requested_ip: Ipv4Addr, server: Ipv4Addr) -> Packet {
+        PacketBuilder::new()
+            .xid(xid)
+            .chaddr(chaddr)
+            .broadcast(true)
+            .message_type(MessageType::Request)
+            .option(DhcpOption {
+                code: REQUESTED_IP_ADDRESS,
+                data: requested_ip.octets().to_vec(),
+            })
+            .option(DhcpOption {
+                code: SERVER_IDENTIFIER,
+                data: server.octets().to_vec(),
+            })
+            .option(DhcpOption {
+                code: PARAMETER_REQUEST_LIST,
+                data: DEFAULT_PARAMETER_REQUEST_LIST.to_vec(),
+            })
+            .build()
+    }
+
+    /// Starts a reply of type `msg_type` to this request: a BOOTREPLY with the request's
+    /// htype, hlen, xid, flags, giaddr and hardware address, zeroed addresses otherwise, and
+    /// DHCP_MESSAGE_TYPE as its only option.
+    pub fn clone_for_reply(&self, msg_type: MessageType) -> Packet {
+        Packet {
+            reply: true,
+            hops: 0,
+            xid: self.xid,
+            secs: 0,
+            flags: self.flags,
+            ciaddr: [0; 4],
+            yiaddr: [0; 4],
+            siaddr: [0; 4],
+            giaddr: self.giaddr,
+            htype: self.htype,
+            hlen: self.hlen,
+            hardware: self.hardware,
+            sname: [0; 64],
+            file: [0; 128],
+            options: vec![DhcpOption {
+                              code: DHCP_MESSAGE_TYPE,
+                              data: vec![msg_type.to_u8()],
+                          }],
+        }
+    }
+
+    /// Parses Packet from byte array, copying out the option data.
+    /// Long options split across several instances (RFC 3396) are concatenated, so `option`
+    /// returns the whole value. Repeats of options that cannot be split are kept as they are.
+    /// Options overloaded into sname or file (OVERLOAD) are moved into `options`, leaving those
+    /// fields empty and dropping OVERLOAD, so `to_bytes` writes each option once.
+    pub fn from_bytes(p: &[u8]) -> Result<Packet, ParseError> {
+        Packet::parse(p, false)
+    }
+
+    /// Like `from_bytes`, but rejects packets repeating an option that cannot be split, such as
+    /// two conflicting DHCP_MESSAGE_TYPEs. Repeats of other options are concatenated into one.
+    pub fn from_bytes_strict(p: &[u8]) -> Result<Packet, ParseError> {
+        Packet::parse(p, true)
+    }
+
+    /// Parses a packet from a hex dump of its bytes, such as one pasted into a bug report,
+    /// as `from_bytes` does. Whitespace is ignored, so both `01 01 06 00` and `01010600` are
+    /// accepted, spread over as many lines as needed. The dump must start at the BOOTP header,
+    /// without IP or UDP headers before it.
+    pub fn from_hex(s: &str) -> Result<Packet, ParseError> {
+        let digits: Vec<u32> = s.chars()
+            .filter(|c| !c.is_whitespace())
+            .map(|c| c.to_digit(16).ok_or(ParseError::InvalidHex))
+            .collect::<Result<_, _>>()?;
+        if !digits.len().is_multiple_of(2) {
+            return Err(ParseError::InvalidHex);
+        }
+        let bytes: Vec<u8> = digits.chunks(2).map(|d| (d[0] << 4 | d[1]) as u8).collect();
+        Packet::from_bytes(&bytes)
+    }
+
+    fn parse(p: &[u8], strict: bool) -> Result<Packet, ParseError> {
+        let reply = check_header(p)?;
+        let mut options = parse_options(&p[240..])?;
+        // Option Overload (RFC 2131 section 4.1): the file field is read before sname
+        let overload = overload_flags(&options);
+        if overload & OVERLOAD_FILE != 0 {
+            options.extend(parse_options(&p[108..236])?);
+        }
+        if overload & OVERLOAD_SNAME != 0 {
+            options.extend(parse_options(&p[44..108])?);
+        }
+        if overload != 0 {
+            options.retain(|o| o.code != OVERLOAD);
+        }
+        let options = merge_options(options, strict)?;
+        let mut sname = fixed_field(&p[44..108]);
+        if overload & OVERLOAD_SNAME != 0 {
+            sname = [0; 64];
+        }
+        let mut file = fixed_field(&p[108..236]);
+        if overload & OVERLOAD_FILE != 0 {
+            file = [0; 128];
+        }
+        Ok(Packet {
+            reply,
+            hops: p[3],
+            secs: bytes_u16!(p[8..]),
+            flags: Flags(bytes_u16!(p[10..])),
+            ciaddr: [p[12], p[13], p[14], p[15]],
+            yiaddr: [p[16], p[17], p[18], p[19]],
+            siaddr: [p[20], p[21], p[22], p[23]],
+            giaddr: [p[24], p[25], p[26], p[27]],
+            options,
+            htype: p[1],
+            hlen: p[2],
+            hardware: fixed_field(&p[28..44]),
+            sname,
+            file,
+            xid: [p[4], p[5], p[6], p[7]],
+        })
+    }
+
     /// Extracts requested option payload from packet if available
-    pub fn option(&self, code: u8) -> Option<&'a [u8]> {
+    pub fn option(&self, code: u8) -> Option<&[u8]> {
         for option in &self.options {
             if option.code == code {
                 return Some(&option.data);
@@ -76,28 +517,257 @@ impl<'a> Packet<'a> {
         None
     }
 
+    /// Iterates over every option in the packet in wire order, including unknown codes.
+    /// PAD and END are consumed by the parser and never appear.
+    pub fn options(&self) -> impl Iterator<Item = &DhcpOption> {
+        self.options.iter()
+    }
+
+    /// Transaction ID chosen by the client, for matching replies to requests.
+    pub fn xid(&self) -> u32 {
+        bytes_u32!(self.xid)
+    }
+
+    /// Seconds elapsed since the client began acquiring or renewing its address. Clients that
+    /// keep retrying with growing values are going unanswered, whether from slow replies or an
+    /// exhausted pool.
+    pub fn secs(&self) -> u16 {
+        self.secs
+    }
+
+    /// Whether the broadcast bit, the top bit of the flags field, is set. Clients set it when
+    /// they cannot receive unicast datagrams before their address is configured.
+    pub fn is_broadcast(&self) -> bool {
+        self.flags.broadcast()
+    }
+
+    pub fn set_broadcast(&mut self, broadcast: bool) {
+        self.flags.set_broadcast(broadcast);
+    }
+
+    /// Returns the client hardware address, the first hlen bytes of the chaddr field.
+    pub fn hardware_address(&self) -> &[u8] {
+        &self.hardware[..(self.hlen as usize).min(16)]
+    }
+
+    /// Returns the first six bytes of the chaddr field, the client's MAC address on Ethernet.
+    pub fn chaddr(&self) -> [u8; 6] {
+        let h = &self.hardware;
+        [h[0], h[1], h[2], h[3], h[4], h[5]]
+    }
+
+    /// Returns the htype field.
+    pub fn hardware_type(&self) -> HardwareType {
+        HardwareType::from(self.htype)
+    }
+
+    /// Sets the hardware type and address, the chaddr field and hlen. hlen is set
+    /// to the type's address length where it has one, truncating or zero padding `addr` to it,
+    /// so that the two cannot disagree. Addresses over 16 bytes are truncated.
+    pub fn set_hardware_address(&mut self, htype: HardwareType, addr: &[u8]) {
+        let len = htype.address_len().unwrap_or(addr.len()).min(16);
+        let n = addr.len().min(len);
+        self.htype = htype.into();
+        self.hlen = len as u8;
+        self.hardware = [0; 16];
+        self.hardware[..n].copy_from_slice(&addr[..n]);
+    }
+
+    /// Sets the BOOTP `file` field, truncating names over 127 bytes.
+    pub fn set_bootfile(&mut self, name: &str) {
+        set_field(&mut self.file, name);
+    }
+
+    /// Returns the BOOTP `file` field, if set and not overloaded with options.
+    pub fn bootfile(&self) -> Option<&str> {
+        if overload_flags(&self.options) & OVERLOAD_FILE != 0 {
+            return None;
+        }
+        field_str(&self.file)
+    }
+
+    /// Sets the BOOTP `sname` field, truncating names over 63 bytes.
+    pub fn set_server_name(&mut self, name: &str) {
+        set_field(&mut self.sname, name);
+    }
+
+    /// Returns the BOOTP `sname` field, if set and not overloaded with options.
+    pub fn server_name(&self) -> Option<&str> {
+        if overload_flags(&self.options) & OVERLOAD_SNAME != 0 {
+            return None;
+        }
+        field_str(&self.sname)
+    }
+
+    /// Collects the options into a map keyed by code, for typed access.
+    pub fn option_map(&self) -> DhcpOptions {
+        self.options.iter().cloned().collect()
+    }
+
+    /// Replaces the first option with the same code, or appends it if none exists.
+    pub fn set_option(&mut self, option: DhcpOption) {
+        match self.options.iter().position(|o| o.code == option.code) {
+            Some(i) => self.options[i] = option,
+            None => self.options.push(option),
+        }
+    }
+
+    /// Removes and returns the first option with the given code.
+    pub fn remove_option(&mut self, code: u8) -> Option<DhcpOption> {
+        self.options
+            .iter()
+            .position(|o| o.code == code)
+            .map(|i| self.options.remove(i))
+    }
+
+    /// Returns the option codes the client asked for in its PARAMETER_REQUEST_LIST, in its
+    /// order of preference.
+    pub fn requested_parameters(&self) -> Option<Vec<u8>> {
+        self.option(PARAMETER_REQUEST_LIST).map(|d| d.to_vec())
+    }
+
+    /// Returns the SERVER_IDENTIFIER (option 54), if present and 4 bytes long.
+    pub fn server_identifier(&self) -> Option<Ipv4Addr> {
+        self.option(SERVER_IDENTIFIER).and_then(bytes_to_ip)
+    }
+
+    /// Returns the REQUESTED_IP_ADDRESS (option 50), if present and 4 bytes long.
+    pub fn requested_ip(&self) -> Option<Ipv4Addr> {
+        self.option(REQUESTED_IP_ADDRESS).and_then(bytes_to_ip)
+    }
+
+    /// Returns the MAXIMUM_DHCP_MESSAGE_SIZE (option 57) the client will accept, if present
+    /// and 2 bytes long. The size includes the IP and UDP headers.
+    pub fn max_message_size(&self) -> Option<u16> {
+        match self.option(MAXIMUM_DHCP_MESSAGE_SIZE) {
+            Some(d) if d.len() == 2 => Some(bytes_u16!(d)),
+            _ => None,
+        }
+    }
+
+    /// Parses the Relay Agent Information (option 82), if present and well formed.
+    pub fn relay_agent_info(&self) -> Option<RelayAgentInfo> {
+        self.option(RELAY_AGENT_INFORMATION).and_then(|d| RelayAgentInfo::parse(d).ok())
+    }
+
+    /// Parses Vendor-Specific Information (option 43) sub-options, if present.
+    pub fn vendor_options(&self) -> Option<VendorOptions> {
+        self.option(VENDOR_SPECIFIC_INFORMATION).map(VendorOptions::parse)
+    }
+
+    /// Returns the Client Identifier (option 61), if present: a hardware type byte followed by
+    /// an identifier, used in place of chaddr to tell clients apart.
+    pub fn client_identifier(&self) -> Option<&[u8]> {
+        self.option(CLIENT_IDENTIFIER)
+    }
+
+    /// Whether this is a DISCOVER carrying the Rapid Commit option (80), asking for an immediate
+    /// ACK in place of the OFFER and REQUEST exchange (RFC 4039).
+    pub fn wants_rapid_commit(&self) -> bool {
+        matches!(self.message_type(), Ok(MessageType::Discover)) &&
+        self.option(RAPID_COMMIT).is_some()
+    }
+
+    /// Returns the Vendor Class Identifier (option 60), if present.
+    pub fn vendor_class_identifier(&self) -> Option<&[u8]> {
+        self.option(VENDOR_CLASS_IDENTIFIER)
+    }
+
+    /// Whether the client is a PXE boot ROM, identified by a vendor class starting with
+    /// "PXEClient".
+    pub fn is_pxe_client(&self) -> bool {
+        self.vendor_class_identifier().is_some_and(|v| v.starts_with(b"PXEClient"))
+    }
+
+    /// Parses the User Class option (77), if present. See `UserClasses::parse` for the
+    /// encodings accepted.
+    pub fn user_classes(&self) -> Option<UserClasses> {
+        self.option(USER_CLASS).map(UserClasses::parse)
+    }
+
+    /// Parses the Authentication option (90), if present and well formed.
+    pub fn authentication(&self) -> Option<Authentication> {
+        self.option(AUTHENTICATION).and_then(|d| Authentication::parse(d).ok())
+    }
+
+    /// Parses the Client FQDN (option 81), if present and well formed.
+    pub fn client_fqdn(&self) -> Option<ClientFqdn> {
+        self.option(CLIENT_FQDN).and_then(|d| ClientFqdn::parse(d).ok())
+    }
+
     /// Convenience function for extracting a packet's message type.
+    ///
+    /// A DHCP_MESSAGE_TYPE (option 53) holding a single byte always gives `Ok`, with values no
+    /// RFC assigns, such as 0 or 255, as `MessageType::Unknown` so they never pass for a known
+    /// type. An `Err` describing the problem is returned if the option is missing, as in a
+    /// plain BOOTP packet, or its length is not 1, including an empty option. The default
+    /// `Handler::handle_request` ignores packets with either.
     pub fn message_type(&self) -> Result<MessageType, String> {
         if let Some(x) = self.option(DHCP_MESSAGE_TYPE) {
             if x.len() != 1 {
                 Err(format!["Invalid length for DHCP MessageType: {}", x.len()])
             } else {
-                MessageType::from(x[0])
+                Ok(x[0].into())
             }
         } else {
-            Err(format!["Packet does not have MessageType option"])
+            Err("Packet does not have MessageType option".to_string())
         }
     }
 
+    /// Checks that the packet encodes to one `parse` accepts, without encoding it. Meant for
+    /// catching mistakes in constructed packets, e.g. in tests or debug assertions.
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        let fixed_len = self.hardware_type().address_len();
+        if self.hlen > 16 || fixed_len.is_some_and(|l| l != self.hlen as usize) {
+            return Err(ValidationError::InvalidHardwareLength(self.hlen));
+        }
+        if let Some(o) = self.options.iter().find(|o| o.code == PAD || o.code == END) {
+            return Err(ValidationError::ReservedOptionCode(o.code));
+        }
+        let overload = overload_flags(&self.options);
+        if overload & OVERLOAD_FILE != 0 && parse_options(&self.file).is_err() ||
+           overload & OVERLOAD_SNAME != 0 && parse_options(&self.sname).is_err() {
+            return Err(ValidationError::InvalidOverloadedField);
+        }
+        Ok(())
+    }
+
+    /// Returns the number of bytes the encoded packet will occupy, including END and padding.
+    pub fn encoded_len(&self) -> usize {
+        let options: usize = self.options.iter().map(|o| encoded_option_len(o.data.len())).sum();
+        core::cmp::max(240 + options + 1, MIN_PACKET_SIZE)
+    }
+
     /// Creates byte array DHCP packet
-    pub fn encode<'c>(&'c self, p: &'c mut [u8]) -> &[u8] {
+    pub fn encode<'c>(&'c self, p: &'c mut [u8]) -> &'c [u8] {
+        let length = self.write(p);
+        &p[..length]
+    }
+
+    /// Encodes the packet into the supplied buffer, returning the number of bytes written.
+    /// Unlike `encode`, fails instead of panicking when the buffer is too small.
+    pub fn encode_into(&self, p: &mut [u8]) -> Result<usize, &'static str> {
+        if p.len() < self.encoded_len() {
+            return Err("Buffer too small");
+        }
+        Ok(self.write(p))
+    }
+
+    /// Serializes the packet into a newly allocated byte vector, ready to be put on the wire.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut p = vec![0; self.encoded_len()];
+        self.write(&mut p);
+        p
+    }
+
+    fn write(&self, p: &mut [u8]) -> usize {
         p[..12].clone_from_slice(&[(if self.reply {
                                        BOOT_REPLY
                                    } else {
                                        BOOT_REQUEST
                                    }),
-                                   1,
-                                   6,
+                                   self.htype,
+                                   self.hlen,
                                    self.hops,
                                    self.xid[0],
                                    self.xid[1],
@@ -105,35 +775,162 @@ impl<'a> Packet<'a> {
                                    self.xid[3],
                                    (self.secs >> 8) as u8,
                                    (self.secs & 255) as u8,
-                                   (if self.broadcast {
-                                       128
-                                   } else {
-                                       0
-                                   }),
-                                   0]);
+                                   (u16::from(self.flags) >> 8) as u8,
+                                   u16::from(self.flags) as u8]);
         p[12..16].clone_from_slice(&self.ciaddr);
         p[16..20].clone_from_slice(&self.yiaddr);
         p[20..24].clone_from_slice(&self.siaddr);
         p[24..28].clone_from_slice(&self.giaddr);
-        p[28..34].clone_from_slice(&self.chaddr);
-        p[34..236].clone_from_slice(&[0; 202]);
+        p[28..44].clone_from_slice(&self.hardware);
+        p[44..108].clone_from_slice(&self.sname);
+        p[108..236].clone_from_slice(&self.file);
         p[236..240].clone_from_slice(&COOKIE);
 
         let mut length: usize = 240;
         for option in &self.options {
-            p[length] = option.code;
-            p[length + 1] = option.data.len() as u8;
-            p[length + 2..length + 2 + option.data.len()].clone_from_slice(option.data);
-            length += 2 + option.data.len();
+            // Values over 255 bytes are split into several instances (RFC 3396)
+            let mut chunks = option.data.chunks(255);
+            let first = chunks.next().unwrap_or(&[]);
+            for chunk in core::iter::once(first).chain(chunks) {
+                p[length] = option.code;
+                p[length + 1] = chunk.len() as u8;
+                p[length + 2..length + 2 + chunk.len()].clone_from_slice(chunk);
+                length += 2 + chunk.len();
+            }
         }
         p[length] = END;
         length += 1;
-        if length < 272 {
+        if length < MIN_PACKET_SIZE {
             // Pad to min size
-            p[length..272].clone_from_slice(&[PAD; 32][..272 - length]);
-            length = 272
+            p[length..MIN_PACKET_SIZE].clone_from_slice(&[PAD; 32][..MIN_PACKET_SIZE - length]);
+            length = MIN_PACKET_SIZE
         }
-        &p[..length]
+        length
+    }
+}
+
+impl TryFrom<&[u8]> for Packet {
+    type Error = ParseError;
+
+    /// See `Packet::from_bytes`.
+    fn try_from(p: &[u8]) -> Result<Packet, ParseError> {
+        Packet::from_bytes(p)
+    }
+}
+
+impl From<&Packet> for Vec<u8> {
+    /// See `Packet::to_bytes`.
+    fn from(p: &Packet) -> Vec<u8> {
+        p.to_bytes()
+    }
+}
+
+/// Fluent constructor for packets.
+///
+/// Defaults to an empty BOOTREQUEST for an Ethernet client; the magic cookie and END option are
+/// added when the packet is encoded.
+pub struct PacketBuilder {
+    packet: Packet,
+}
+
+impl Default for PacketBuilder {
+    fn default() -> PacketBuilder {
+        PacketBuilder::new()
+    }
+}
+
+impl PacketBuilder {
+    pub fn new() -> PacketBuilder {
+        PacketBuilder { packet: Packet::new() }
+    }
+
+    /// Sets op to BOOTREPLY (true) or BOOTREQUEST (false).
+    pub fn reply(mut self, reply: bool) -> PacketBuilder {
+        self.packet.reply = reply;
+        self
+    }
+
+    pub fn hops(mut self, hops: u8) -> PacketBuilder {
+        self.packet.hops = hops;
+        self
+    }
+
+    pub fn xid(mut self, xid: u32) -> PacketBuilder {
+        self.packet.xid = u32_bytes!(xid);
+        self
+    }
+
+    pub fn secs(mut self, secs: u16) -> PacketBuilder {
+        self.packet.secs = secs;
+        self
+    }
+
+    pub fn broadcast(mut self, broadcast: bool) -> PacketBuilder {
+        self.packet.flags.set_broadcast(broadcast);
+        self
+    }
+
+    pub fn ciaddr(mut self, ip: Ipv4Addr) -> PacketBuilder {
+        self.packet.ciaddr = ip.octets();
+        self
+    }
+
+    pub fn yiaddr(mut self, ip: Ipv4Addr) -> PacketBuilder {
+        self.packet.yiaddr = ip.octets();
+        self
+    }
+
+    pub fn siaddr(mut self, ip: Ipv4Addr) -> PacketBuilder {
+        self.packet.siaddr = ip.octets();
+        self
+    }
+
+    pub fn giaddr(mut self, ip: Ipv4Addr) -> PacketBuilder {
+        self.packet.giaddr = ip.octets();
+        self
+    }
+
+    /// Sets an Ethernet hardware address.
+    pub fn chaddr(mut self, chaddr: [u8; 6]) -> PacketBuilder {
+        self.packet.set_hardware_address(HardwareType::Ethernet, &chaddr);
+        self
+    }
+
+    /// Sets the hardware type and an address of up to 16 bytes, see
+    /// `Packet::set_hardware_address`.
+    pub fn hardware_address(mut self, htype: HardwareType, addr: &[u8]) -> PacketBuilder {
+        self.packet.set_hardware_address(htype, addr);
+        self
+    }
+
+    /// Sets the BOOTP `file` field.
+    pub fn bootfile(mut self, name: &str) -> PacketBuilder {
+        self.packet.set_bootfile(name);
+        self
+    }
+
+    /// Sets the BOOTP `sname` field.
+    pub fn server_name(mut self, name: &str) -> PacketBuilder {
+        self.packet.set_server_name(name);
+        self
+    }
+
+    /// Sets the DHCP_MESSAGE_TYPE option.
+    pub fn message_type(self, msg_type: MessageType) -> PacketBuilder {
+        self.option(DhcpOption {
+            code: DHCP_MESSAGE_TYPE,
+            data: vec![msg_type.to_u8()],
+        })
+    }
+
+    /// Adds an option, replacing any previously added option with the same code.
+    pub fn option(mut self, option: DhcpOption) -> PacketBuilder {
+        self.packet.set_option(option);
+        self
+    }
+
+    pub fn build(self) -> Packet {
+        self.packet
     }
 }
 
@@ -142,5 +939,571 @@ const COOKIE: [u8; 4] = [99, 130, 83, 99];
 const BOOT_REQUEST: u8 = 1; // From Client;
 const BOOT_REPLY: u8 = 2; // From Server;
 
-const END: u8 = 255;
-const PAD: u8 = 0;
+const MIN_PACKET_SIZE: usize = 272;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a raw BOOTREQUEST with the given (END terminated) options section.
+    fn raw_request(options: &[u8]) -> Vec<u8> {
+        let mut b = vec![0; 236];
+        b[0] = BOOT_REQUEST;
+        b[1] = 1;
+        b[2] = 6;
+        b.extend_from_slice(&COOKIE);
+        b.extend_from_slice(options);
+        b
+    }
+
+    #[test]
+    fn to_bytes_round_trip() {
+        let p = Packet {
+            reply: false,
+            hops: 0,
+            xid: [1, 2, 3, 4],
+            secs: 5,
+            flags: Flags::from(Flags::BROADCAST),
+            ciaddr: [0, 0, 0, 0],
+            yiaddr: [0, 0, 0, 0],
+            siaddr: [0, 0, 0, 0],
+            giaddr: [0, 0, 0, 0],
+            htype: 1,
+            hlen: 6,
+            hardware: [0xa, 0xb, 0xc, 0xd, 0xe, 0xf, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+            sname: [0; 64],
+            file: [0; 128],
+            options: vec![DhcpOption {
+                              code: DHCP_MESSAGE_TYPE,
+                              data: vec![1],
+                          }],
+        };
+        let b = p.to_bytes();
+        assert_eq!(b.len(), p.encoded_len());
+        assert_eq!(&b[28..44], &[0xa, 0xb, 0xc, 0xd, 0xe, 0xf, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(&b[236..240], &COOKIE);
+        assert_eq!(&b[240..244], &[DHCP_MESSAGE_TYPE, 1, 1, END]);
+
+        let d = decode(&b).unwrap();
+        assert_eq!(d.xid, p.xid);
+        assert_eq!(d.secs, 5);
+        assert!(d.is_broadcast());
+        assert_eq!(d.chaddr(), p.chaddr());
+        assert_eq!(d.option(DHCP_MESSAGE_TYPE), Some(&[1][..]));
+
+        assert_eq!(Vec::from(&p), b);
+        let t = Packet::try_from(&b[..]).unwrap();
+        assert_eq!(t.xid, p.xid);
+        assert_eq!(Packet::try_from(&b[..239]).err(), Some(ParseError::TooShort));
+    }
+
+    #[test]
+    fn encode_into_small_buffer() {
+        let p = Packet {
+            reply: true,
+            hops: 0,
+            xid: [0; 4],
+            secs: 0,
+            flags: Flags::default(),
+            ciaddr: [0; 4],
+            yiaddr: [0; 4],
+            siaddr: [0; 4],
+            giaddr: [0; 4],
+            htype: 1,
+            hlen: 6,
+            hardware: [0; 16],
+            sname: [0; 64],
+            file: [0; 128],
+            options: Vec::new(),
+        };
+        assert!(p.encode_into(&mut [0; 100]).is_err());
+        assert_eq!(p.encode_into(&mut [0; 1500]), Ok(MIN_PACKET_SIZE));
+    }
+
+    #[test]
+    fn from_bytes_errors() {
+        let mut b = vec![0; 240];
+        assert_eq!(Packet::from_bytes(&b[..100]).err(), Some(ParseError::TooShort));
+        assert_eq!(Packet::from_bytes(&b).err(), Some(ParseError::BadMagicCookie));
+        b[236..240].clone_from_slice(&COOKIE);
+        assert_eq!(Packet::from_bytes(&b).err(), Some(ParseError::InvalidOpCode(0)));
+        b[0] = BOOT_REQUEST;
+        assert_eq!(Packet::from_bytes(&b).err(), Some(ParseError::UnterminatedOptions));
+        b.push(END);
+        assert!(Packet::from_bytes(&b).is_ok());
+    }
+
+    #[test]
+    fn option_overrun() {
+        let opts = [0x63, 0x82, 0x53, 0x63, REQUESTED_IP_ADDRESS, 10, 0, 0, 0];
+        assert_eq!(Packet::from_bytes(&opts).err(), Some(ParseError::TooShort));
+
+        let b = raw_request(&opts[4..]);
+        assert_eq!(Packet::from_bytes(&b).err(), Some(ParseError::InvalidOptionLength));
+    }
+
+    #[test]
+    fn parse_options_guards() {
+        assert_eq!(parse_options(&[]).err(), Some(ParseError::UnterminatedOptions));
+        assert_eq!(parse_options(&[HOST_NAME]).err(), Some(ParseError::UnterminatedOptions));
+        assert_eq!(parse_options(&[HOST_NAME, 1, b'a']).err(),
+                   Some(ParseError::UnterminatedOptions));
+        assert_eq!(parse_options(&[HOST_NAME, 2, b'a']).err(),
+                   Some(ParseError::InvalidOptionLength));
+
+        let o = parse_options(&[PAD, HOST_NAME, 1, b'a', PAD, END]).unwrap();
+        assert_eq!(o.len(), 1);
+        assert_eq!(o[0].data, b"a");
+    }
+
+    #[test]
+    fn options_iterator() {
+        let p = Packet::from_bytes(&raw_request(&[DHCP_MESSAGE_TYPE, 1, 1,
+                                                  CLIENT_IDENTIFIER, 2, 1, 2,
+                                                  PAD,
+                                                  HOST_NAME, 3, b'f', b'o', b'o',
+                                                  200, 1, 7,
+                                                  PARAMETER_REQUEST_LIST, 2, 1, 3,
+                                                  END]))
+            .unwrap();
+        let codes: Vec<u8> = p.options().map(|o| o.code).collect();
+        assert_eq!(codes,
+                   vec![DHCP_MESSAGE_TYPE, CLIENT_IDENTIFIER, HOST_NAME, 200, PARAMETER_REQUEST_LIST]);
+        assert_eq!(p.options().nth(3).unwrap().data, vec![7]);
+    }
+
+    #[test]
+    fn hardware_addresses() {
+        let mut b = raw_request(&[END]);
+        b[1] = 32;
+        b[2] = 20;
+        b[28..48].clone_from_slice(&[7; 20]);
+        assert_eq!(Packet::from_bytes(&b).err(), Some(ParseError::InvalidHardwareLength(20)));
+
+        // A 16 byte address of hardware type 32 (InfiniBand)
+        b[2] = 16;
+        let p = Packet::from_bytes(&b).unwrap();
+        assert_eq!(p.htype, 32);
+        assert_eq!(p.hardware_address(), &[7; 16][..]);
+        assert_eq!(p.chaddr(), [7; 6]);
+        let e = p.to_bytes();
+        assert_eq!(&e[..3], &[BOOT_REQUEST, 32, 16]);
+        assert_eq!(&e[28..44], &[7; 16]);
+
+        let p = PacketBuilder::new().chaddr([1, 2, 3, 4, 5, 6]).build();
+        assert_eq!(p.hardware_address(), &[1, 2, 3, 4, 5, 6][..]);
+        let p = PacketBuilder::new().hardware_address(HardwareType::Other(15), &[9; 8]).build();
+        assert_eq!((p.htype, p.hlen), (15, 8));
+        assert_eq!(Packet::from_bytes(&p.to_bytes()).unwrap().hardware_address(), &[9; 8][..]);
+    }
+
+    #[test]
+    fn hardware_types() {
+        assert_eq!(HardwareType::from(1), HardwareType::Ethernet);
+        assert_eq!(HardwareType::from(32), HardwareType::Other(32));
+        for t in 0..=255 {
+            assert_eq!(u8::from(HardwareType::from(t)), t);
+        }
+
+        // The address is fitted to the type's length
+        let p = PacketBuilder::new().hardware_address(HardwareType::Ethernet, &[9; 8]).build();
+        assert_eq!(p.hardware_type(), HardwareType::Ethernet);
+        assert_eq!(p.hardware_address(), &[9; 6][..]);
+        let p = PacketBuilder::new().hardware_address(HardwareType::Ieee802, &[9; 4]).build();
+        assert_eq!(p.hardware_address(), &[9, 9, 9, 9, 0, 0][..]);
+        let p = PacketBuilder::new().hardware_address(HardwareType::Arcnet, &[9]).build();
+        assert_eq!((p.htype, p.hlen, p.chaddr()), (7, 1, [9, 0, 0, 0, 0, 0]));
+        assert_eq!(p.validate(), Ok(()));
+
+        let mut p = PacketBuilder::new().chaddr([1, 2, 3, 4, 5, 6]).build();
+        p.hlen = 8;
+        assert_eq!(p.validate(), Err(ValidationError::InvalidHardwareLength(8)));
+    }
+
+    #[test]
+    fn option_map() {
+        let p = Packet::from_bytes(&raw_request(&[SUBNET_MASK, 4, 255, 255, 255, 0,
+                                                  DHCP_MESSAGE_TYPE, 1, 8, END]))
+            .unwrap();
+        let m = p.option_map();
+        assert_eq!(m.subnet_mask(), Some(Ipv4Addr::new(255, 255, 255, 0)));
+        assert!(matches!(m.message_type(), Some(MessageType::Inform)));
+    }
+
+    #[test]
+    fn set_and_remove_option() {
+        let mut p = Packet::from_bytes(&raw_request(&[DHCP_MESSAGE_TYPE, 1, 1, HOST_NAME, 1, b'a', END]))
+            .unwrap();
+        p.set_option(DhcpOption {
+            code: HOST_NAME,
+            data: b"b".to_vec(),
+        });
+        p.set_option(DhcpOption {
+            code: RELAY_AGENT_INFORMATION,
+            data: vec![1, 1, 9],
+        });
+        let codes: Vec<u8> = p.options().map(|o| o.code).collect();
+        assert_eq!(codes, vec![DHCP_MESSAGE_TYPE, HOST_NAME, RELAY_AGENT_INFORMATION]);
+        assert_eq!(p.option(HOST_NAME), Some(&b"b"[..]));
+
+        assert_eq!(p.remove_option(HOST_NAME).map(|o| o.data), Some(b"b".to_vec()));
+        assert!(p.remove_option(HOST_NAME).is_none());
+        assert_eq!(p.options().count(), 2);
+    }
+
+    #[test]
+    fn bad_magic_cookie() {
+        let good = raw_request(&[END]);
+        assert!(Packet::from_bytes(&good).is_ok());
+        for i in 236..240 {
+            let mut b = good.clone();
+            b[i] ^= 1;
+            assert_eq!(Packet::from_bytes(&b).err(), Some(ParseError::BadMagicCookie));
+        }
+    }
+
+    #[test]
+    fn from_hex() {
+        let p = PacketBuilder::new()
+            .xid(0x0badf00d)
+            .chaddr([0xaa, 0xbb, 0xcc, 0, 1, 2])
+            .message_type(MessageType::Discover)
+            .build();
+        let b = p.to_bytes();
+        let compact: String = b.iter().map(|b| format!("{:02x}", b)).collect();
+        let spaced: Vec<String> = b.iter().map(|b| format!("{:02X}", b)).collect();
+        let lines: Vec<String> = spaced.chunks(16).map(|l| l.join(" ")).collect();
+        for dump in [compact.clone(), lines.join("\n")] {
+            let h = Packet::from_hex(&dump).unwrap();
+            assert_eq!(h.xid, [0x0b, 0xad, 0xf0, 0x0d]);
+            assert_eq!(h.chaddr(), [0xaa, 0xbb, 0xcc, 0, 1, 2]);
+            assert_eq!(h.to_bytes(), b);
+        }
+        assert_eq!(Packet::from_hex(&compact[1..]).err(), Some(ParseError::InvalidHex));
+        assert_eq!(Packet::from_hex("01 0g").err(), Some(ParseError::InvalidHex));
+        assert_eq!(Packet::from_hex("0101").err(), Some(ParseError::TooShort));
+    }
+
+    #[test]
+    fn malformed_message_type() {
+        let packet = |data: &[u8]| {
+            let mut option = vec![DHCP_MESSAGE_TYPE, data.len() as u8];
+            option.extend_from_slice(data);
+            option.push(END);
+            raw_request(&option)
+        };
+        let cases: [(&[u8], Result<MessageType, String>); 5] =
+            [(&[5], Ok(MessageType::Ack)),
+             (&[255], Ok(MessageType::Unknown(255))),
+             (&[0], Ok(MessageType::Unknown(0))),
+             (&[], Err("Invalid length for DHCP MessageType: 0".to_string())),
+             (&[1, 3], Err("Invalid length for DHCP MessageType: 2".to_string()))];
+        for (data, expected) in cases.iter() {
+            let b = packet(data);
+            assert_eq!(&Packet::from_bytes(&b).unwrap().message_type(), expected);
+            assert_eq!(&PacketRef::parse(&b).unwrap().message_type(), expected);
+        }
+        assert!(Packet::from_bytes(&raw_request(&[END])).unwrap().message_type().is_err());
+    }
+
+    #[test]
+    fn default_packet() {
+        let b = Packet::default().to_bytes();
+        assert_eq!(b[..4], [BOOT_REQUEST, 1, 6, 0]);
+        assert_eq!(b[236..240], COOKIE);
+        assert_eq!(b[240], END);
+        let p = Packet::from_bytes(&b).unwrap();
+        assert!(!p.reply);
+        assert_eq!(p.hardware_type(), HardwareType::Ethernet);
+        assert!(p.options.is_empty());
+        assert_eq!(p.to_bytes(), b);
+    }
+
+    #[test]
+    fn builder() {
+        let p = PacketBuilder::new()
+            .xid(0x01020304)
+            .chaddr([1, 2, 3, 4, 5, 6])
+            .yiaddr(Ipv4Addr::new(192, 168, 0, 10))
+            .message_type(MessageType::Offer)
+            .option(DhcpOption {
+                code: SUBNET_MASK,
+                data: vec![255, 255, 255, 0],
+            })
+            .reply(true)
+            .build();
+        let d = Packet::from_bytes(&p.to_bytes()).unwrap();
+        assert!(d.reply);
+        assert_eq!(d.xid, [1, 2, 3, 4]);
+        assert_eq!(d.chaddr(), [1, 2, 3, 4, 5, 6]);
+        assert_eq!(d.yiaddr, [192, 168, 0, 10]);
+        assert!(matches!(d.message_type(), Ok(MessageType::Offer)));
+        assert_eq!(d.option(SUBNET_MASK), Some(&[255, 255, 255, 0][..]));
+    }
+
+    #[test]
+    fn message_type_inform() {
+        let p = Packet::from_bytes(&raw_request(&[DHCP_MESSAGE_TYPE, 1, 8, END])).unwrap();
+        assert!(matches!(p.message_type(), Ok(MessageType::Inform)));
+    }
+
+    #[test]
+    fn relay_agent_info() {
+        let p = Packet::from_bytes(&raw_request(&[RELAY_AGENT_INFORMATION, 5,
+                                                  AGENT_CIRCUIT_ID, 1, 3,
+                                                  AGENT_REMOTE_ID, 0,
+                                                  END]))
+            .unwrap();
+        let r = p.relay_agent_info().unwrap();
+        assert_eq!(r.circuit_id(), Some(&[3][..]));
+        assert_eq!(r.remote_id(), Some(&[][..]));
+    }
+
+    #[test]
+    fn pxe_boot_fields() {
+        let p = PacketBuilder::new()
+            .reply(true)
+            .server_name("tftp.example.com")
+            .bootfile("pxelinux.0")
+            .option(DhcpOption::bootfile_name("pxelinux.0"))
+            .option(DhcpOption::tftp_server_name("tftp.example.com"))
+            .build();
+        let b = p.to_bytes();
+        assert_eq!(&b[108..119], b"pxelinux.0\0");
+        let d = Packet::from_bytes(&b).unwrap();
+        assert_eq!(d.bootfile(), Some("pxelinux.0"));
+        assert_eq!(d.server_name(), Some("tftp.example.com"));
+        assert_eq!(d.option(BOOTFILE_NAME), Some(&b"pxelinux.0"[..]));
+        assert_eq!(d.option(TFTP_SERVER_NAME), Some(&b"tftp.example.com"[..]));
+
+        let url = format!("http://boot.example.com/{}/boot.efi", "a".repeat(300));
+        let b = PacketBuilder::new()
+            .reply(true)
+            .option(DhcpOption::bootfile_name(&url))
+            .option(DhcpOption::tftp_server_name(&"é".repeat(200)))
+            .build()
+            .to_bytes();
+        let d = Packet::from_bytes(&b).unwrap();
+        assert_eq!(d.option(BOOTFILE_NAME), Some(url.as_bytes()));
+        assert_eq!(d.option(TFTP_SERVER_NAME), Some("é".repeat(200).as_bytes()));
+
+        let mut p = d;
+        p.set_server_name(&"é".repeat(40));
+        assert_eq!(p.server_name().map(|s| s.len()), Some(62));
+        p.set_bootfile("");
+        assert_eq!(p.bootfile(), None);
+        assert_eq!(Packet::from_bytes(&raw_request(&[END])).unwrap().bootfile(), None);
+    }
+
+    #[test]
+    fn option_overload() {
+        let mut b = raw_request(&[OVERLOAD, 1, OVERLOAD_SNAME, END]);
+        b[44..48].clone_from_slice(&[DHCP_MESSAGE_TYPE, 1, 3, END]);
+        let p = Packet::from_bytes(&b).unwrap();
+        assert!(matches!(p.message_type(), Ok(MessageType::Request)));
+        assert_eq!(p.server_name(), None);
+
+        b[240..243].clone_from_slice(&[OVERLOAD, 1, OVERLOAD_FILE | OVERLOAD_SNAME]);
+        b[108..111].clone_from_slice(&[HOST_NAME, 1, b'a']);
+        assert_eq!(Packet::from_bytes(&b).err(), Some(ParseError::UnterminatedOptions));
+        b[111] = END;
+        let p = Packet::from_bytes(&b).unwrap();
+        assert_eq!(p.option(HOST_NAME), Some(&b"a"[..]));
+        assert!(p.option(DHCP_MESSAGE_TYPE).is_some());
+        assert_eq!(p.option(OVERLOAD), None);
+        assert_eq!(p.file, [0; 128]);
+
+        // The lifted options are written once, in the options field
+        let again = Packet::from_bytes(&p.to_bytes()).unwrap();
+        assert_eq!(again.option(HOST_NAME), Some(&b"a"[..]));
+        assert_eq!(again.options().collect::<Vec<_>>(), p.options().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn header_accessors() {
+        let mut b = raw_request(&[END]);
+        b[4..12].clone_from_slice(&[0xde, 0xad, 0xbe, 0xef, 0, 30, 0x80, 0]);
+        let mut p = Packet::from_bytes(&b).unwrap();
+        assert_eq!(p.xid(), 0xdeadbeef);
+        assert_eq!(p.secs(), 30);
+        assert!(p.is_broadcast());
+        p.set_broadcast(false);
+        assert_eq!(p.to_bytes()[10], 0);
+
+        // Reserved bits survive a round trip
+        b[10..12].clone_from_slice(&[0x80, 0x01]);
+        let mut p = Packet::from_bytes(&b).unwrap();
+        assert_eq!(u16::from(p.flags), 0x8001);
+        p.flags.set_broadcast(false);
+        assert_eq!(&p.to_bytes()[10..12], &[0, 1]);
+    }
+
+    #[test]
+    fn clone_for_reply() {
+        let req = PacketBuilder::new()
+            .xid(7)
+            .chaddr([1, 2, 3, 4, 5, 6])
+            .broadcast(true)
+            .ciaddr(Ipv4Addr::new(10, 0, 0, 9))
+            .giaddr(Ipv4Addr::new(10, 0, 0, 254))
+            .message_type(MessageType::Request)
+            .option(DhcpOption {
+                code: HOST_NAME,
+                data: b"laptop".to_vec(),
+            })
+            .build();
+        let r = req.clone_for_reply(MessageType::Ack);
+        assert!(r.reply);
+        assert_eq!(r.xid(), 7);
+        assert_eq!(r.chaddr(), req.chaddr());
+        assert_eq!((r.htype, r.hlen), (req.htype, req.hlen));
+        assert!(r.is_broadcast());
+        assert_eq!(r.giaddr, req.giaddr);
+        assert_eq!(r.ciaddr, [0; 4]);
+        assert_eq!(r.yiaddr, [0; 4]);
+        assert!(matches!(r.message_type(), Ok(MessageType::Ack)));
+        assert_eq!(r.options.len(), 1);
+    }
+
+    #[test]
+    fn pxe_clients() {
+        let pxe = PacketBuilder::new()
+            .option(DhcpOption {
+                code: VENDOR_CLASS_IDENTIFIER,
+                data: b"PXEClient:Arch:00000:UNDI:002001".to_vec(),
+            })
+            .build();
+        assert!(pxe.is_pxe_client());
+        let msft = PacketBuilder::new()
+            .option(DhcpOption {
+                code: VENDOR_CLASS_IDENTIFIER,
+                data: b"MSFT 5.0".to_vec(),
+            })
+            .build();
+        assert_eq!(msft.vendor_class_identifier(), Some(&b"MSFT 5.0"[..]));
+        assert!(!msft.is_pxe_client());
+        assert!(!PacketBuilder::new().build().is_pxe_client());
+    }
+
+    #[test]
+    fn validation() {
+        let mut p = PacketBuilder::new().message_type(MessageType::Discover).build();
+        assert_eq!(p.validate(), Ok(()));
+        assert!(parse(&p.to_bytes()).is_ok());
+
+        p.hlen = 17;
+        assert_eq!(p.validate(), Err(ValidationError::InvalidHardwareLength(17)));
+        p.hlen = 6;
+        p.options.push(DhcpOption { code: END, data: vec![] });
+        assert_eq!(p.validate(), Err(ValidationError::ReservedOptionCode(END)));
+        p.options.pop();
+
+        p.set_option(DhcpOption { code: OVERLOAD, data: vec![OVERLOAD_SNAME] });
+        assert_eq!(p.validate(), Err(ValidationError::InvalidOverloadedField));
+        assert!(parse(&p.to_bytes()).is_err());
+        p.sname[0] = END;
+        assert_eq!(p.validate(), Ok(()));
+        assert!(parse(&p.to_bytes()).is_ok());
+    }
+
+    #[test]
+    fn duplicate_options() {
+        let b = raw_request(&[DHCP_MESSAGE_TYPE, 1, 1, DHCP_MESSAGE_TYPE, 1, 3, END]);
+        assert_eq!(Packet::from_bytes_strict(&b).err(), Some(ParseError::DuplicateOption(53)));
+        let p = Packet::from_bytes(&b).unwrap();
+        assert!(matches!(p.message_type(), Ok(MessageType::Discover)));
+        assert_eq!(p.options().count(), 2);
+
+        let b = raw_request(&[DOMAIN_NAME_SERVER, 4, 8, 8, 8, 8, HOST_NAME, 1, b'a',
+                              DOMAIN_NAME_SERVER, 4, 8, 8, 4, 4, END]);
+        let p = Packet::from_bytes_strict(&b).unwrap();
+        assert_eq!(p.option(DOMAIN_NAME_SERVER), Some(&[8, 8, 8, 8, 8, 8, 4, 4][..]));
+        assert_eq!(p.options().count(), 2);
+    }
+
+    #[test]
+    fn long_options() {
+        let mut opts = vec![VENDOR_SPECIFIC_INFORMATION, 255];
+        opts.extend((0..255).map(|i| i as u8));
+        opts.extend([HOST_NAME, 1, b'a', VENDOR_SPECIFIC_INFORMATION, 45]);
+        opts.extend((0..45).map(|i| i as u8));
+        opts.push(END);
+        let p = Packet::from_bytes(&raw_request(&opts)).unwrap();
+        let v = p.option(VENDOR_SPECIFIC_INFORMATION).unwrap();
+        assert_eq!(v.len(), 300);
+        assert_eq!(&v[255..258], &[0, 1, 2]);
+        assert_eq!(p.options().count(), 2);
+
+        // Encoding splits the value again
+        let b = p.to_bytes();
+        assert_eq!(b.len(), p.encoded_len());
+        assert_eq!(&b[240..242], &[VENDOR_SPECIFIC_INFORMATION, 255]);
+        assert_eq!(&b[497..499], &[VENDOR_SPECIFIC_INFORMATION, 45]);
+        let d = Packet::from_bytes(&b).unwrap();
+        assert_eq!(d.option(VENDOR_SPECIFIC_INFORMATION), Some(v));
+    }
+
+    #[test]
+    fn client_fqdn() {
+        let p = Packet::from_bytes(&raw_request(&[CLIENT_FQDN, 5, 0, 0, 0, b'p', b'c', END]))
+            .unwrap();
+        assert_eq!(p.client_fqdn().unwrap().name, "pc");
+        assert!(Packet::from_bytes(&raw_request(&[END])).unwrap().client_fqdn().is_none());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_json_round_trip() {
+        let p = PacketBuilder::new()
+            .xid(3)
+            .chaddr([1, 2, 3, 0xa, 0xb, 0xc])
+            .giaddr(Ipv4Addr::new(10, 0, 0, 1))
+            .bootfile("pxelinux.0")
+            .message_type(MessageType::Discover)
+            .build();
+        let j = serde_json::to_value(&p).unwrap();
+        assert_eq!(j["giaddr"], "10.0.0.1");
+        assert_eq!(j["chaddr"], "01:02:03:0a:0b:0c");
+        assert_eq!(j["options"][0]["code"], DHCP_MESSAGE_TYPE);
+
+        let d: Packet = serde_json::from_value(j).unwrap();
+        assert_eq!(d.to_bytes(), p.to_bytes());
+
+        // Longer hardware addresses keep their bytes past the sixth
+        let mut long = p.clone();
+        long.set_hardware_address(HardwareType::Other(32), &[1, 2, 3, 4, 5, 6, 7, 8]);
+        let j = serde_json::to_value(&long).unwrap();
+        assert_eq!(j["chaddr"], "01:02:03:04:05:06:07:08");
+        let d: Packet = serde_json::from_value(j).unwrap();
+        assert_eq!(d.hardware_address(), long.hardware_address());
+        assert_eq!(serde_json::to_string(&MessageType::Unknown(42)).unwrap(), r#"{"Unknown":42}"#);
+    }
+
+    #[test]
+    fn discover_and_request() {
+        let d = Packet::from_bytes(&Packet::discover(7, [1, 2, 3, 4, 5, 6]).to_bytes()).unwrap();
+        assert!(!d.reply);
+        assert!(d.is_broadcast());
+        assert_eq!(d.xid, [0, 0, 0, 7]);
+        assert!(matches!(d.message_type(), Ok(MessageType::Discover)));
+        assert_eq!(d.option(PARAMETER_REQUEST_LIST), Some(&DEFAULT_PARAMETER_REQUEST_LIST[..]));
+
+        let r = Packet::request(7,
+                                [1, 2, 3, 4, 5, 6],
+                                Ipv4Addr::new(192, 168, 0, 9),
+                                Ipv4Addr::new(192, 168, 0, 1));
+        assert!(matches!(r.message_type(), Ok(MessageType::Request)));
+        assert_eq!(r.option(REQUESTED_IP_ADDRESS), Some(&[192, 168, 0, 9][..]));
+        assert_eq!(r.option(SERVER_IDENTIFIER), Some(&[192, 168, 0, 1][..]));
+        assert_eq!(r.requested_ip(), Some(Ipv4Addr::new(192, 168, 0, 9)));
+        assert_eq!(r.server_identifier(), Some(Ipv4Addr::new(192, 168, 0, 1)));
+        assert_eq!(d.requested_ip(), None);
+
+        let mut bad = r;
+        bad.set_option(DhcpOption {
+            code: REQUESTED_IP_ADDRESS,
+            data: vec![192, 168, 0],
+        });
+        assert_eq!(bad.requested_ip(), None);
+    }
+}
@@ -0,0 +1,229 @@
+//! A view of a packet that borrows the received buffer instead of copying its options.
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use core::net::Ipv4Addr;
+
+use crate::options::{MessageType, DHCP_MESSAGE_TYPE, OVERLOAD};
+
+use super::{check_header, next_option, Flags, HardwareType, Packet, ParseError, OVERLOAD_FILE,
+            OVERLOAD_SNAME};
+
+/// A parsed packet whose options are sliced out of the buffer it was parsed from, so parsing
+/// allocates nothing. For relays and monitors that only look at a few fields of each packet.
+///
+/// Options are seen as they appear on the wire: an option split into several instances
+/// (RFC 3396) is not concatenated, so `option` returns only its first part. Use `to_owned` for
+/// the `Packet`, which joins them.
+///
+/// ```
+/// use dhcp4r::options::MessageType;
+/// use dhcp4r::packet::{PacketBuilder, PacketRef};
+///
+/// let bytes = PacketBuilder::new().xid(7).message_type(MessageType::Discover).build().to_bytes();
+/// let p = PacketRef::parse(&bytes).unwrap();
+/// assert_eq!(p.xid(), 7);
+/// assert!(matches!(p.message_type(), Ok(MessageType::Discover)));
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct PacketRef<'a> {
+    buf: &'a [u8],
+    overload: u8,
+}
+
+impl<'a> PacketRef<'a> {
+    /// Checks `p` as `Packet::from_bytes` does, without copying anything out of it.
+    pub fn parse(p: &'a [u8]) -> Result<PacketRef<'a>, ParseError> {
+        check_header(p)?;
+        let mut overload = None;
+        let mut i = 0;
+        while let Some((code, data)) = next_option(&p[240..], &mut i)? {
+            if code == OVERLOAD && overload.is_none() {
+                overload = Some(match *data {
+                    [flags] => flags & (OVERLOAD_FILE | OVERLOAD_SNAME),
+                    _ => 0,
+                });
+            }
+        }
+        let overload = overload.unwrap_or(0);
+        let packet = PacketRef { buf: p, overload };
+        for field in packet.overloaded_fields().iter().flatten() {
+            let mut i = 0;
+            while next_option(field, &mut i)?.is_some() {}
+        }
+        Ok(packet)
+    }
+
+    /// The sname and file fields holding options, in the order they are read.
+    fn overloaded_fields(&self) -> [Option<&'a [u8]>; 2] {
+        let file = &self.buf[108..236];
+        let sname = &self.buf[44..108];
+        [Some(file).filter(|_| self.overload & OVERLOAD_FILE != 0),
+         Some(sname).filter(|_| self.overload & OVERLOAD_SNAME != 0)]
+    }
+
+    /// Whether this is a BOOTREPLY.
+    pub fn is_reply(&self) -> bool {
+        self.buf[0] == super::BOOT_REPLY
+    }
+
+    pub fn hops(&self) -> u8 {
+        self.buf[3]
+    }
+
+    /// See `Packet::xid`.
+    pub fn xid(&self) -> u32 {
+        bytes_u32!(self.buf[4..])
+    }
+
+    /// See `Packet::secs`.
+    pub fn secs(&self) -> u16 {
+        bytes_u16!(self.buf[8..])
+    }
+
+    pub fn flags(&self) -> Flags {
+        Flags::from(bytes_u16!(self.buf[10..]))
+    }
+
+    pub fn ciaddr(&self) -> Ipv4Addr {
+        self.addr(12)
+    }
+
+    pub fn yiaddr(&self) -> Ipv4Addr {
+        self.addr(16)
+    }
+
+    pub fn siaddr(&self) -> Ipv4Addr {
+        self.addr(20)
+    }
+
+    pub fn giaddr(&self) -> Ipv4Addr {
+        self.addr(24)
+    }
+
+    fn addr(&self, at: usize) -> Ipv4Addr {
+        let b = &self.buf[at..at + 4];
+        Ipv4Addr::new(b[0], b[1], b[2], b[3])
+    }
+
+    pub fn htype(&self) -> u8 {
+        self.buf[1]
+    }
+
+    /// See `Packet::hardware_type`.
+    pub fn hardware_type(&self) -> HardwareType {
+        HardwareType::from(self.buf[1])
+    }
+
+    /// The client hardware address, the first hlen bytes of the chaddr field.
+    pub fn hardware_address(&self) -> &'a [u8] {
+        &self.buf[28..28 + self.buf[2] as usize]
+    }
+
+    /// The first six bytes of the chaddr field, as in `Packet::chaddr`.
+    pub fn chaddr(&self) -> [u8; 6] {
+        let b = &self.buf[28..34];
+        [b[0], b[1], b[2], b[3], b[4], b[5]]
+    }
+
+    /// Iterates over the options as codes and data, in wire order, continuing into the file
+    /// and sname fields when they are overloaded. PAD and END never appear.
+    pub fn options(&self) -> RawOptions<'a> {
+        let [file, sname] = self.overloaded_fields();
+        RawOptions {
+            areas: [Some(&self.buf[240..]), file, sname],
+            area: 0,
+            i: 0,
+        }
+    }
+
+    /// Returns the data of the first instance of the option with this code.
+    pub fn option(&self, code: u8) -> Option<&'a [u8]> {
+        self.options().find(|&(c, _)| c == code).map(|(_, d)| d)
+    }
+
+    /// See `Packet::message_type`.
+    pub fn message_type(&self) -> Result<MessageType, String> {
+        match self.option(DHCP_MESSAGE_TYPE) {
+            Some(d) if d.len() == 1 => Ok(d[0].into()),
+            Some(d) => Err(format!("Invalid length for DHCP MessageType: {}", d.len())),
+            None => Err("Packet does not have MessageType option".to_string()),
+        }
+    }
+
+    /// Copies the packet into an owned `Packet`, as `Packet::from_bytes` would parse it.
+    pub fn to_owned(&self) -> Packet {
+        Packet::from_bytes(self.buf).expect("checked by PacketRef::parse")
+    }
+}
+
+/// Iterator over the options of a `PacketRef`, yielding each code with its data.
+#[derive(Debug, Clone)]
+pub struct RawOptions<'a> {
+    areas: [Option<&'a [u8]>; 3],
+    area: usize,
+    i: usize,
+}
+
+impl<'a> Iterator for RawOptions<'a> {
+    type Item = (u8, &'a [u8]);
+
+    fn next(&mut self) -> Option<(u8, &'a [u8])> {
+        while self.area < self.areas.len() {
+            if let Some(p) = self.areas[self.area] {
+                if let Ok(Some(o)) = next_option(p, &mut self.i) {
+                    return Some(o);
+                }
+            }
+            self.area += 1;
+            self.i = 0;
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::{DhcpOption, END, HOST_NAME, ROUTER};
+    use crate::packet::PacketBuilder;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn matches_owned() {
+        let p = PacketBuilder::new()
+            .xid(9)
+            .chaddr([1, 2, 3, 4, 5, 6])
+            .giaddr(Ipv4Addr::new(10, 0, 0, 254))
+            .message_type(MessageType::Request)
+            .option(DhcpOption {
+                code: HOST_NAME,
+                data: b"laptop".to_vec(),
+            })
+            .build();
+        let mut b = p.to_bytes();
+        // Continue the options into the file field
+        let end = b.len() - b.iter().rev().position(|&c| c == END).unwrap() - 1;
+        b.splice(end..end, [OVERLOAD, 1, OVERLOAD_FILE]);
+        b[108..115].copy_from_slice(&[ROUTER, 4, 10, 0, 0, 1, END]);
+
+        let r = PacketRef::parse(&b).unwrap();
+        assert_eq!(r.xid(), 9);
+        assert_eq!(r.chaddr(), [1, 2, 3, 4, 5, 6]);
+        assert_eq!(r.hardware_address(), &[1, 2, 3, 4, 5, 6]);
+        assert_eq!(r.hardware_type(), HardwareType::Ethernet);
+        assert_eq!(r.giaddr(), Ipv4Addr::new(10, 0, 0, 254));
+        assert!(!r.is_reply());
+        assert_eq!(r.option(HOST_NAME), Some(&b"laptop"[..]));
+        assert_eq!(r.option(ROUTER), Some(&[10, 0, 0, 1][..]));
+
+        let owned = r.to_owned();
+        // The owned packet lifts the overloaded options and drops OVERLOAD itself
+        let codes: Vec<u8> = r.options().map(|(c, _)| c).filter(|&c| c != OVERLOAD).collect();
+        assert_eq!(codes, owned.options().map(|o| o.code).collect::<Vec<u8>>());
+
+        b[114] = ROUTER;
+        assert_eq!(PacketRef::parse(&b).err(), Some(ParseError::UnterminatedOptions));
+        assert_eq!(PacketRef::parse(&b[..239]).err(), Some(ParseError::TooShort));
+    }
+}
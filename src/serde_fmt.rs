@@ -0,0 +1,68 @@
+//! Human readable serde representations for `Packet` fields, used with `#[serde(with)]`.
+
+/// `[u8; 4]` as a dotted IPv4 string.
+pub mod ip {
+    use alloc::string::String;
+    use core::net::Ipv4Addr;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(ip: &[u8; 4], s: S) -> Result<S::Ok, S::Error> {
+        s.collect_str(&Ipv4Addr::from(*ip))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<[u8; 4], D::Error> {
+        let s = String::deserialize(d)?;
+        s.parse::<Ipv4Addr>().map(|ip| ip.octets()).map_err(serde::de::Error::custom)
+    }
+}
+
+/// The `[u8; 16]` chaddr field as colon separated hex bytes, such as `01:02:03:0a:0b:0c` for an
+/// Ethernet address. Zeros after the sixth byte are left out.
+pub mod mac {
+    use alloc::format;
+    use alloc::string::String;
+    use alloc::vec::Vec;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(chaddr: &[u8; 16], s: S) -> Result<S::Ok, S::Error> {
+        let len = chaddr.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1).max(6);
+        let parts: Vec<String> = chaddr[..len].iter().map(|b| format!("{:02x}", b)).collect();
+        s.serialize_str(&parts.join(":"))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<[u8; 16], D::Error> {
+        let s = String::deserialize(d)?;
+        let parts: Option<Vec<u8>> = s.split(':').map(|p| u8::from_str_radix(p, 16).ok()).collect();
+        match parts {
+            Some(bytes) if (6..=16).contains(&bytes.len()) => {
+                let mut chaddr = [0; 16];
+                chaddr[..bytes.len()].copy_from_slice(&bytes);
+                Ok(chaddr)
+            }
+            _ => Err(serde::de::Error::custom("invalid MAC address")),
+        }
+    }
+}
+
+/// Fixed size, zero padded fields (sname and file) as byte lists without trailing zeros.
+pub mod field {
+    use alloc::vec::Vec;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer, const N: usize>(f: &[u8; N], s: S) -> Result<S::Ok, S::Error> {
+        let len = f.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+        s.collect_seq(&f[..len])
+    }
+
+    pub fn deserialize<'de, D, const N: usize>(d: D) -> Result<[u8; N], D::Error>
+        where D: Deserializer<'de>
+    {
+        let v = Vec::<u8>::deserialize(d)?;
+        if v.len() > N {
+            return Err(serde::de::Error::invalid_length(v.len(), &"a shorter field"));
+        }
+        let mut f = [0; N];
+        f[..v.len()].copy_from_slice(&v);
+        Ok(f)
+    }
+}
@@ -1,31 +1,404 @@
-use std::net::{UdpSocket, SocketAddr, Ipv4Addr, IpAddr};
-use std;
-use std::cell::Cell;
+//! This is a convenience module that simplifies the writing of a DHCP server service.
 
-use options::{DhcpOption, MessageType};
-use packet::*;
-use options;
+use std::net::{SocketAddr, Ipv4Addr, IpAddr};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 
-///! This is a convenience module that simplifies the writing of a DHCP server service.
+use crate::client::CLIENT_PORT;
+use crate::options::{DhcpOption, MessageType};
+use crate::packet::*;
+use crate::options;
+
+mod lease;
+pub use self::lease::{ClientId, Lease, LeaseError, LeaseQuery, LeaseStore, MemoryLeaseStore,
+                      PoolHandler};
+
+mod pool;
+pub use self::pool::{Pool, PoolSet};
+
+mod socket;
+pub use self::socket::{bind_to_device, RequestContext};
+
+mod transport;
+pub use self::transport::{MockTransport, Transport};
+
+#[cfg(all(target_os = "linux", any(feature = "arp-probe", feature = "raw-unicast")))]
+mod link;
+
+#[cfg(feature = "arp-probe")]
+mod probe;
+#[cfg(feature = "arp-probe")]
+pub use self::probe::probe_ip;
+
+#[cfg(feature = "raw-unicast")]
+mod raw;
+
+#[cfg(feature = "tokio")]
+mod async_server;
+#[cfg(feature = "tokio")]
+pub use self::async_server::{serve_async, AsyncHandler, AsyncServer};
 
 pub struct Server {
     out_buf: Cell<[u8; 1500]>,
-    socket: UdpSocket,
+    transport: Box<dyn Transport>,
     src: SocketAddr,
     server_ip: [u8; 4],
+    dedup_window: Option<Duration>,
+    accept_replies: bool,
+    reply_policy: ReplyPolicy,
+    recv_buffer_size: usize,
+    client_port: u16,
+    verify_chaddr: bool,
+    deferred: RefCell<HashMap<u64, Deferred>>,
+    next_token: Cell<u64>,
+    #[cfg(feature = "raw-unicast")]
+    raw: Option<raw::RawUnicast>,
+}
+
+/// A request put aside by `Server::defer`, with where it came from.
+struct Deferred {
+    request: Packet,
+    src: SocketAddr,
+    at: Instant,
+}
+
+/// Identifies a request put aside by `Server::defer`, to answer with `Server::send_deferred`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DeferredReply(u64);
+
+/// Configures a `Server` before serving, for settings beyond those of `Server::serve`, which
+/// serves with the defaults of each.
+///
+/// ```no_run
+/// # use dhcp4r::server::{Handler, Server};
+/// # struct MyHandler;
+/// # impl Handler for MyHandler {}
+/// use std::time::Duration;
+///
+/// let socket = std::net::UdpSocket::bind("0.0.0.0:67").unwrap();
+/// Server::builder()
+///     .server_ip([192, 168, 0, 1])
+///     .dedup_window(Duration::from_secs(1))
+///     .read_timeout(Duration::from_millis(100))
+///     .serve(socket, MyHandler);
+/// ```
+#[derive(Debug, Clone)]
+pub struct ServerBuilder {
+    server_ip: [u8; 4],
+    dedup_window: Option<Duration>,
+    read_timeout: Option<Duration>,
+    accept_replies: bool,
+    reply_policy: ReplyPolicy,
+    recv_buffer_size: usize,
+    client_port: u16,
+    verify_chaddr: bool,
+    #[cfg(feature = "raw-unicast")]
+    raw_iface: Option<String>,
+}
+
+impl Default for ServerBuilder {
+    fn default() -> ServerBuilder {
+        ServerBuilder {
+            server_ip: [0, 0, 0, 0],
+            dedup_window: None,
+            read_timeout: None,
+            accept_replies: false,
+            reply_policy: ReplyPolicy::default(),
+            recv_buffer_size: DEFAULT_RECV_BUFFER_SIZE,
+            client_port: CLIENT_PORT,
+            verify_chaddr: false,
+            #[cfg(feature = "raw-unicast")]
+            raw_iface: None,
+        }
+    }
+}
+
+impl ServerBuilder {
+    /// The address sent as SERVER_IDENTIFIER and checked by `Server::for_this_server`. Left at
+    /// the default 0.0.0.0, every packet is taken as addressed to this server.
+    pub fn server_ip(mut self, ip: [u8; 4]) -> ServerBuilder {
+        self.server_ip = ip;
+        self
+    }
+
+    /// Drops packets repeating the xid, chaddr and message type of one received less than
+    /// `window` earlier, so a client flooding retransmissions reaches the handler once.
+    /// Off by default.
+    pub fn dedup_window(mut self, window: Duration) -> ServerBuilder {
+        self.dedup_window = Some(window);
+        self
+    }
+
+    /// How long to wait for a packet before calling `Handler::tick`, which also bounds how
+    /// long shutdown takes to notice. Without one the socket's own read timeout is kept, or half
+    /// a second used if it has none.
+    pub fn read_timeout(mut self, timeout: Duration) -> ServerBuilder {
+        self.read_timeout = Some(timeout);
+        self
+    }
+
+    /// Passes BOOTREPLY packets, such as other servers' replies seen on a shared segment, to
+    /// the handler as well, e.g. for monitoring. By default only BOOTREQUESTs reach it.
+    pub fn accept_replies(mut self, accept: bool) -> ServerBuilder {
+        self.accept_replies = accept;
+        self
+    }
+
+    /// Chooses and orders the options of every reply. See `ReplyPolicy`.
+    pub fn reply_policy(mut self, policy: ReplyPolicy) -> ServerBuilder {
+        self.reply_policy = policy;
+        self
+    }
+
+    /// Size of the buffer packets are received into, allocated once and reused for every
+    /// packet. Longer packets are truncated, and so fail to parse. 1500 bytes, an Ethernet MTU,
+    /// by default.
+    pub fn recv_buffer_size(mut self, size: usize) -> ServerBuilder {
+        self.recv_buffer_size = size;
+        self
+    }
+
+    /// The port replies to clients that were not relayed are sent to, `CLIENT_PORT` (68) unless
+    /// the clients are non-standard ones listening elsewhere. Replies through a relay agent
+    /// always go to its `SERVER_PORT`.
+    pub fn client_port(mut self, port: u16) -> ServerBuilder {
+        self.client_port = port;
+        self
+    }
+
+    /// Drops requests whose chaddr is not the Ethernet source address of the frame they came
+    /// in, as DHCP snooping switches do, reporting each to `Handler::on_error` as an
+    /// `InvalidData` error. On an untrusted segment this stops a host from exhausting the pool
+    /// or taking over another client's lease by claiming hardware addresses not its own; it
+    /// cannot stop a host that spoofs its Ethernet source address too.
+    ///
+    /// Only transports that see whole frames fill in `RequestContext::src_mac`, so with a
+    /// `UdpSocket`, which never does, this checks nothing. Relayed requests are not checked,
+    /// as their frames come from the relay. The default `on_error` stops the server, so
+    /// override it to log such errors and keep serving. Off by default.
+    pub fn verify_chaddr(mut self, verify: bool) -> ServerBuilder {
+        self.verify_chaddr = verify;
+        self
+    }
+
+    /// Sends replies that RFC 2131 says to unicast to a client that has no address yet as raw
+    /// frames through the interface `iface`. See `Server::serve_raw_until`.
+    #[cfg(feature = "raw-unicast")]
+    pub fn raw_unicast(mut self, iface: &str) -> ServerBuilder {
+        self.raw_iface = Some(iface.to_string());
+        self
+    }
+
+    /// See `Server::serve`.
+    pub fn serve<T, H>(self, transport: T, handler: H) -> io::Error
+        where T: Transport + 'static,
+              H: Handler
+    {
+        let shutdown = AtomicBool::new(false);
+        match self.serve_until(transport, handler, &shutdown) {
+            Ok(()) => unreachable!("shutdown is never signalled"),
+            Err(e) => e,
+        }
+    }
+
+    /// See `Server::serve_until`.
+    pub fn serve_until<T, H>(self,
+                             transport: T,
+                             handler: H,
+                             shutdown: &AtomicBool)
+                             -> io::Result<()>
+        where T: Transport + 'static,
+              H: Handler
+    {
+        transport.prepare(self.read_timeout)?;
+        let mut s = Server::new(transport, self.server_ip);
+        s.dedup_window = self.dedup_window;
+        s.accept_replies = self.accept_replies;
+        s.reply_policy = self.reply_policy;
+        s.recv_buffer_size = self.recv_buffer_size;
+        s.client_port = self.client_port;
+        s.verify_chaddr = self.verify_chaddr;
+        #[cfg(feature = "raw-unicast")]
+        if let Some(ref iface) = self.raw_iface {
+            s.raw = Some(raw::RawUnicast::open(iface)?);
+        }
+        s.run(handler, shutdown)
+    }
+}
+
+/// What `Server::reply_with` puts in a reply. The remaining fields are taken from the request:
+/// xid, htype, hlen and chaddr, giaddr so relays can forward the reply, flags, with the
+/// broadcast bit replaced by `broadcast` if set, and ciaddr except in a NAK.
+#[derive(Clone)]
+pub struct ReplyParams {
+    pub msg_type: MessageType,
+    /// Address offered or assigned to the client, or zero.
+    pub yiaddr: [u8; 4],
+    /// Next server in the boot process, such as a TFTP server for PXE, or zero.
+    pub siaddr: [u8; 4],
+    /// Options besides DHCP_MESSAGE_TYPE and SERVER_IDENTIFIER, which are added automatically.
+    pub options: Vec<DhcpOption>,
+    pub broadcast: Option<bool>,
+}
+
+/// Which options a reply carries, and in what order.
+///
+/// When the request has a PARAMETER_REQUEST_LIST, options are filtered and ordered by it, as
+/// `filter_options_by_req` does. Without one, the reply carries the options set with
+/// `default_options`, in that order, or if none were set every option given to the reply, in
+/// the order given.
+///
+/// ```
+/// use dhcp4r::options;
+/// use dhcp4r::server::ReplyPolicy;
+///
+/// let policy = ReplyPolicy::new()
+///     .default_options(&[options::SUBNET_MASK, options::ROUTER, options::DOMAIN_NAME_SERVER]);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReplyPolicy {
+    defaults: Option<Vec<u8>>,
+}
+
+impl ReplyPolicy {
+    pub fn new() -> ReplyPolicy {
+        ReplyPolicy::default()
+    }
+
+    /// Sets the option codes sent to clients without a PARAMETER_REQUEST_LIST, in the order
+    /// sent. DHCP_MESSAGE_TYPE, SERVER_IDENTIFIER and IP_ADDRESS_LEASE_TIME are sent regardless.
+    pub fn default_options(mut self, codes: &[u8]) -> ReplyPolicy {
+        self.defaults = Some(codes.to_vec());
+        self
+    }
+
+    /// Filters and orders `opts` for a reply to `req`.
+    pub fn apply(&self, opts: &mut Vec<DhcpOption>, req: &Packet) {
+        match (req.option(options::PARAMETER_REQUEST_LIST), &self.defaults) {
+            (Some(prl), _) => filter_options_by_req(opts, prl),
+            (None, Some(defaults)) => filter_options(opts, &ALWAYS_SENT, defaults),
+            (None, None) => {}
+        }
+    }
 }
 
+/// Receives the requests accepted by `Server::serve`.
+///
+/// Implementors either override `handle_request_from` to see every packet, or rely on its
+/// default, which dispatches on the DHCP message type to the `handle_*` methods below. Those do
+/// nothing unless overridden.
 pub trait Handler {
-    fn handle_request(&mut self, &Server, Packet);
+    /// Called for each received packet before `handle_request_from`. Returning false drops the
+    /// packet, e.g. when its `Packet::authentication` fails verification. Accepts everything
+    /// by default.
+    fn authenticate(&mut self, _server: &Server, _packet: &Packet) -> bool {
+        true
+    }
+
+    /// Called for each accepted packet with the address it was received from, which for a
+    /// relayed request is the relay's.
+    fn handle_request_from(&mut self, server: &Server, packet: Packet, _src: SocketAddr) {
+        #[allow(deprecated)]
+        self.handle_request(server, packet)
+    }
+
+    /// Called for each accepted packet with where it was received, e.g. to choose a pool by the
+    /// receiving interface when the request was not relayed. The default calls
+    /// `handle_request_from` with the source address.
+    fn handle_request_with_context(&mut self,
+                                   server: &Server,
+                                   packet: Packet,
+                                   ctx: &RequestContext) {
+        self.handle_request_from(server, packet, ctx.src)
+    }
+
+    /// Called by the default `handle_request_from`, without the source address. Its default
+    /// dispatches on the message type.
+    #[deprecated(note = "Override `handle_request_from`, which also receives the source address")]
+    fn handle_request(&mut self, server: &Server, packet: Packet) {
+        match packet.message_type() {
+            Ok(MessageType::Discover) => self.handle_discover(server, packet),
+            Ok(MessageType::Request) => self.handle_request_msg(server, packet),
+            Ok(MessageType::Release) => self.handle_release(server, packet),
+            Ok(MessageType::Decline) => self.handle_decline(server, packet),
+            Ok(MessageType::Inform) => self.handle_inform(server, packet),
+            Ok(MessageType::LeaseQuery) => self.handle_leasequery(server, packet),
+            _ => {}
+        }
+    }
+
+    fn handle_discover(&mut self, _server: &Server, _packet: Packet) {}
+
+    fn handle_request_msg(&mut self, _server: &Server, _packet: Packet) {}
+
+    fn handle_release(&mut self, _server: &Server, _packet: Packet) {}
+
+    fn handle_decline(&mut self, _server: &Server, _packet: Packet) {}
+
+    fn handle_inform(&mut self, _server: &Server, _packet: Packet) {}
+
+    /// Called for a DHCPLEASEQUERY (RFC 4388), typically from an access concentrator. See
+    /// `LeaseQuery::of` for what it asks, and `Server::reply_lease_active` and its siblings for
+    /// the answers.
+    fn handle_leasequery(&mut self, _server: &Server, _packet: Packet) {}
+
+    /// The store the server sweeps for expired leases about every second, calling
+    /// `on_lease_expired` with each one removed. `None` by default, so nothing is swept.
+    fn lease_store(&mut self) -> Option<&mut dyn LeaseStore> {
+        None
+    }
+
+    /// Called for each lease removed from `lease_store` by a sweep.
+    fn on_lease_expired(&mut self, _lease: Lease) {}
+
+    /// Called whenever no packet arrives within the read timeout, for periodic work such as
+    /// flushing state. See `ServerBuilder::read_timeout`.
+    fn tick(&mut self) {}
+
+    /// Called before waiting for each packet, so at least once per read timeout, for sending
+    /// the replies put aside with `Server::defer` whose answer is now known.
+    fn poll_deferred(&mut self, _server: &Server) {}
+
+    /// Called when receiving from the socket fails, or for a request dropped by
+    /// `ServerBuilder::verify_chaddr`. Returning the error stops the server and
+    /// passes the error to the caller of `serve`; returning `Ok` keeps serving.
+    fn on_error(&mut self, error: io::Error) -> io::Result<()> {
+        Err(error)
+    }
 }
 
+/// How often `serve_until` checks its shutdown flag when the socket has no read timeout.
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Size of the receive buffer unless set with `ServerBuilder::recv_buffer_size`.
+const DEFAULT_RECV_BUFFER_SIZE: usize = 1500;
+
+/// How long `Server::defer` keeps a request unanswered.
+const DEFERRED_REPLY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often the server sweeps `Handler::lease_store` for expired leases.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Options kept at the front of every reply, whatever the client asked for.
+const ALWAYS_SENT: [u8; 3] =
+    [options::DHCP_MESSAGE_TYPE, options::SERVER_IDENTIFIER, options::IP_ADDRESS_LEASE_TIME];
+
 /// Orders and filters options based on PARAMETER_REQUEST_LIST received from client.
 /// DHCP_MESSAGE_TYPE and SERVER_IDENTIFIER are always first and always retained.
 /// This function is called by Reply.
 pub fn filter_options_by_req(opts: &mut Vec<DhcpOption>, req_params: &[u8]) {
+    filter_options(opts, &ALWAYS_SENT, req_params);
+}
+
+/// Orders and filters options such that those with codes in `always` come first (in that order)
+/// followed by those in `req_params` (in the order the client requested them). Options whose
+/// codes appear in neither list are removed.
+pub fn filter_options(opts: &mut Vec<DhcpOption>, always: &[u8], req_params: &[u8]) {
     let mut pos = 0;
-    let h = &[options::DHCP_MESSAGE_TYPE as u8, options::SERVER_IDENTIFIER as u8, options::IP_ADDRESS_LEASE_TIME as u8] as &[u8];
-    for z in [h, req_params].iter() {
+    for z in [always, req_params].iter() {
         for r in z.iter() {
             let mut found = false;
             let mut at = 0;
@@ -38,7 +411,7 @@ pub fn filter_options_by_req(opts: &mut Vec<DhcpOption>, req_params: &[u8]) {
             }
             if found {
                 opts.swap(pos, at);
-                pos = pos + 1;
+                pos += 1;
             }
         }
     }
@@ -46,31 +419,143 @@ pub fn filter_options_by_req(opts: &mut Vec<DhcpOption>, req_params: &[u8]) {
 }
 
 impl Server {
-    pub fn serve<H: Handler>(udp_soc: UdpSocket,
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder::default()
+    }
+
+    /// Serves requests received through `transport`, usually a `UdpSocket` bound to port 67,
+    /// until the handler gives up on a socket error, which is returned.
+    pub fn serve<T, H>(transport: T, server_ip: [u8; 4], handler: H) -> std::io::Error
+        where T: Transport + 'static,
+              H: Handler
+    {
+        Server::builder().server_ip(server_ip).serve(transport, handler)
+    }
+
+    /// Serves requests until `shutdown` is set, returning `Ok` once it is noticed, or until the
+    /// handler gives up on a socket error.
+    /// A read timeout is set on the socket, if it has none, so that the flag is checked
+    /// periodically while no packets arrive.
+    pub fn serve_until<T, H>(transport: T,
                              server_ip: [u8; 4],
-                             mut handler: H)
-                             -> std::io::Error {
-        let mut in_buf: [u8; 1500] = [0; 1500];
-        let mut s = Server {
+                             handler: H,
+                             shutdown: &AtomicBool)
+                             -> io::Result<()>
+        where T: Transport + 'static,
+              H: Handler
+    {
+        Server::builder().server_ip(server_ip).serve_until(transport, handler, shutdown)
+    }
+
+    /// Like `serve_until`, but replies that RFC 2131 says to unicast to a client that has no
+    /// address yet (broadcast flag clear, no relay, no ciaddr) are sent as raw frames to its
+    /// chaddr through the interface `iface`, rather than broadcast.
+    ///
+    /// Uses a Linux packet socket, so the process needs CAP_NET_RAW; elsewhere an `Unsupported`
+    /// error is returned.
+    #[cfg(feature = "raw-unicast")]
+    pub fn serve_raw_until<H: Handler>(udp_soc: std::net::UdpSocket,
+                                       iface: &str,
+                                       server_ip: [u8; 4],
+                                       handler: H,
+                                       shutdown: &AtomicBool)
+                                       -> io::Result<()> {
+        Server::builder()
+            .server_ip(server_ip)
+            .raw_unicast(iface)
+            .serve_until(udp_soc, handler, shutdown)
+    }
+
+    fn new<T: Transport + 'static>(transport: T, server_ip: [u8; 4]) -> Server {
+        Server {
             out_buf: Cell::new([0; 1500]),
-            socket: udp_soc,
-            server_ip: server_ip,
+            transport: Box::new(transport),
+            server_ip,
             src: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 0),
-        };
-        loop {
-            match s.socket.recv_from(&mut in_buf) {
-                Err(e) => return e,
-                Ok((l, src)) => {
-                    if let Ok(p) = decode(&in_buf[..l]) {
-                        s.src = src;
-                        handler.handle_request(&s, p);
+            dedup_window: None,
+            accept_replies: false,
+            reply_policy: ReplyPolicy::default(),
+            recv_buffer_size: DEFAULT_RECV_BUFFER_SIZE,
+            client_port: CLIENT_PORT,
+            verify_chaddr: false,
+            deferred: RefCell::new(HashMap::new()),
+            next_token: Cell::new(0),
+            #[cfg(feature = "raw-unicast")]
+            raw: None,
+        }
+    }
+
+    fn run<H: Handler>(mut self, mut handler: H, shutdown: &AtomicBool) -> io::Result<()> {
+        let mut in_buf = vec![0; self.recv_buffer_size];
+        let mut seen = HashMap::new();
+        let mut last_sweep = Instant::now();
+        while !shutdown.load(Ordering::SeqCst) {
+            if last_sweep.elapsed() >= SWEEP_INTERVAL {
+                last_sweep = Instant::now();
+                sweep_leases(&mut handler, last_sweep);
+            }
+            handler.poll_deferred(&self);
+            match self.transport.recv_request(&mut in_buf) {
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock ||
+                              e.kind() == io::ErrorKind::TimedOut => handler.tick(),
+                Err(e) => handler.on_error(e)?,
+                Ok((l, ctx)) => {
+                    let src = ctx.src;
+                    match parse(&in_buf[..l]) {
+                        Ok(p) => {
+                            log_at!(debug,
+                                    "Received {:?} from {} via {}, {}s into its exchange",
+                                    p.message_type(),
+                                    mac_string(&p.chaddr()),
+                                    src,
+                                    p.secs());
+                            self.src = src;
+                            if p.reply && !self.accept_replies {
+                                log_at!(debug, "Ignoring BOOTREPLY from {}", src);
+                                continue;
+                            }
+                            if self.verify_chaddr {
+                                if let Err(e) = check_chaddr(&p, &ctx) {
+                                    handler.on_error(e)?;
+                                    continue;
+                                }
+                            }
+                            if !handler.authenticate(&self, &p) {
+                                log_at!(info, "Rejected unauthenticated packet from {}", src);
+                                continue;
+                            }
+                            if let Some(window) = self.dedup_window {
+                                if is_duplicate(&mut seen, &p, window) {
+                                    log_at!(debug, "Dropping duplicate of xid {:?}", p.xid);
+                                    continue;
+                                }
+                            }
+                            handler.handle_request_with_context(&self, p, &ctx);
+                        }
+                        Err(e) => log_at!(warn, "Dropping packet from {}: {}", src, e),
                     }
                 }
             }
         }
+        Ok(())
     }
 
     /// Constructs and sends a reply packet back to the client.
+    /// May be called more than once per request, with clones of `req_packet`, e.g. for a
+    /// server sending an OFFER alongside its own boot service reply.
+    /// Fails with `InvalidInput`, sending nothing, if `offer_ip` is zero in an OFFER or ACK,
+    /// or given in a NAK, or if a NAK carries lease time, renewal, rebinding or subnet mask
+    /// options. Use `reply_inform` to acknowledge an INFORM, and `reply_with` for anything
+    /// else unusual, such as a proxyDHCP OFFER without an address.
+    /// Options the client did not ask for are dropped when a PARAMETER_REQUEST_LIST is present,
+    /// and the least wanted ones when the reply exceeds its MAXIMUM_DHCP_MESSAGE_SIZE, or 576
+    /// bytes if it gave none. A reply too large for the 1500 byte send buffer fails with
+    /// `InvalidInput`.
+    /// Relay Agent Information (option 82) in the request is copied to the end of the reply,
+    /// for the relay to strip.
+    /// An ACK to a DISCOVER where `Packet::wants_rapid_commit` holds gets the Rapid Commit
+    /// option added. Only a server that is the sole one on its network may answer so, as a
+    /// client keeps the first ACK and other servers' offers would be left reserved.
     /// additional_options should not include DHCP_MESSAGE_TYPE nor SERVER_IDENTIFIER as these
     /// are added automatically.
     pub fn reply(&self,
@@ -78,59 +563,1360 @@ impl Server {
                  additional_options: Vec<DhcpOption>,
                  offer_ip: [u8; 4],
                  req_packet: Packet)
-                 -> std::io::Result<usize> {
+                 -> std::io::Result<(usize, SocketAddr)> {
+        check_reply(msg_type, offer_ip, &additional_options)?;
+        self.reply_with(ReplyParams {
+                            msg_type,
+                            yiaddr: offer_ip,
+                            siaddr: [0, 0, 0, 0],
+                            options: additional_options,
+                            broadcast: None,
+                        },
+                        req_packet)
+    }
+
+    /// Like `reply`, but with control over every field the server fills in.
+    pub fn reply_with(&self,
+                      params: ReplyParams,
+                      req_packet: Packet)
+                      -> std::io::Result<(usize, SocketAddr)> {
+        self.send(reply_packet(self.server_ip, &self.reply_policy, params, req_packet))
+    }
+
+    /// Answers a DHCPINFORM with a DHCPACK carrying configuration parameters only.
+    /// The client already has an externally configured address (in ciaddr) and sends no
+    /// REQUESTED_IP_ADDRESS, so yiaddr is left zero and any lease time, renewal or rebinding
+    /// options are dropped before sending.
+    pub fn reply_inform(&self,
+                        additional_options: Vec<DhcpOption>,
+                        req_packet: Packet)
+                        -> std::io::Result<(usize, SocketAddr)> {
+        self.reply_with(inform_params(additional_options), req_packet)
+    }
 
-        let ciaddr = match msg_type {
-            MessageType::Nak => [0, 0, 0, 0],
-            _ => req_packet.ciaddr,
+    /// Refuses a request with a DHCPNAK carrying `message` (option 56, truncated to 255 bytes
+    /// by `DhcpOption::message`), as RFC 2131 section
+    /// 4.3.2 describes: yiaddr and ciaddr are zero and the broadcast bit is set, so the NAK is
+    /// broadcast, or forwarded by the relay in giaddr for it to broadcast, whatever the
+    /// client's own flag.
+    pub fn nak(&self, message: &str, req_packet: Packet) -> std::io::Result<(usize, SocketAddr)> {
+        self.reply_with(nak_params(message), req_packet)
+    }
+
+    /// Answers a DHCPLEASEQUERY with a DHCPLEASEACTIVE describing `lease`: its address in
+    /// ciaddr, the client's hardware address or identifier, the time left, the time since the
+    /// client was last heard from (option 91), and, for a query by client, any other addresses
+    /// leased to it (option 92). Like any reply's, these options are filtered by the query's
+    /// PARAMETER_REQUEST_LIST and trimmed to its MAXIMUM_DHCP_MESSAGE_SIZE.
+    pub fn reply_lease_active(&self,
+                              lease: &Lease,
+                              last_transaction: Duration,
+                              associated_ips: &[Ipv4Addr],
+                              req_packet: Packet)
+                              -> std::io::Result<(usize, SocketAddr)> {
+        let p = lease_active_packet(self.server_ip,
+                                    &self.reply_policy,
+                                    lease,
+                                    last_transaction,
+                                    associated_ips,
+                                    req_packet.clone());
+        self.send_reply(p, &req_packet)
+    }
+
+    /// Answers a DHCPLEASEQUERY for an address this server is responsible for but has not
+    /// leased with a DHCPLEASEUNASSIGNED.
+    pub fn reply_lease_unassigned(&self,
+                                  req_packet: Packet)
+                                  -> std::io::Result<(usize, SocketAddr)> {
+        self.reply_lease_query(MessageType::LeaseUnassigned, req_packet)
+    }
+
+    /// Answers a DHCPLEASEQUERY this server knows nothing about with a DHCPLEASEUNKNOWN.
+    pub fn reply_lease_unknown(&self,
+                               req_packet: Packet)
+                               -> std::io::Result<(usize, SocketAddr)> {
+        self.reply_lease_query(MessageType::LeaseUnknown, req_packet)
+    }
+
+    /// Answers a DHCPLEASEQUERY with an option-less `msg_type`, sent back to the querier.
+    fn reply_lease_query(&self,
+                         msg_type: MessageType,
+                         req_packet: Packet)
+                         -> std::io::Result<(usize, SocketAddr)> {
+        let params = ReplyParams {
+            msg_type,
+            yiaddr: [0, 0, 0, 0],
+            siaddr: [0, 0, 0, 0],
+            options: Vec::new(),
+            broadcast: None,
         };
+        let p = reply_packet(self.server_ip, &self.reply_policy, params, req_packet.clone());
+        self.send_reply(p, &req_packet)
+    }
 
-        let mt = &[msg_type as u8];
+    /// Checks the packet see if it was intended for this DHCP server (as opposed to some other also on the network).
+    /// A server serving with the unspecified address 0.0.0.0 takes every packet as its own;
+    /// see `options::is_for_server`.
+    pub fn for_this_server(&self, packet: &Packet) -> bool {
+        options::is_for_server(packet, Ipv4Addr::from(self.server_ip))
+    }
+
+    /// Puts `req_packet` aside to be answered later with `send_deferred`, for when the reply
+    /// waits on something slow, such as an ARP probe of the address to offer run on another
+    /// thread. The token is typically kept until `Handler::poll_deferred` finds the answer
+    /// ready. Requests not answered within 10 seconds are dropped, as the client will have
+    /// retransmitted by then.
+    ///
+    /// ```
+    /// use std::sync::mpsc;
+    /// use dhcp4r::options::MessageType;
+    /// use dhcp4r::packet::Packet;
+    /// use dhcp4r::server::{DeferredReply, Handler, ReplyParams, Server};
+    ///
+    /// /// Offers an address once a worker thread has checked that it is free.
+    /// struct Prober {
+    ///     pending: Vec<(DeferredReply, mpsc::Receiver<bool>)>,
+    /// }
+    ///
+    /// impl Handler for Prober {
+    ///     fn handle_discover(&mut self, server: &Server, packet: Packet) {
+    ///         let (tx, rx) = mpsc::channel();
+    ///         std::thread::spawn(move || tx.send(true)); // probe 10.0.0.5 here
+    ///         self.pending.push((server.defer(packet), rx));
+    ///     }
+    ///
+    ///     fn poll_deferred(&mut self, server: &Server) {
+    ///         self.pending.retain(|(token, rx)| match rx.try_recv() {
+    ///             Ok(free) => {
+    ///                 if free {
+    ///                     let offer = ReplyParams {
+    ///                         msg_type: MessageType::Offer,
+    ///                         yiaddr: [10, 0, 0, 5],
+    ///                         siaddr: [0, 0, 0, 0],
+    ///                         options: Vec::new(),
+    ///                         broadcast: None,
+    ///                     };
+    ///                     let _ = server.send_deferred(*token, offer);
+    ///                 }
+    ///                 false
+    ///             }
+    ///             Err(mpsc::TryRecvError::Empty) => true,
+    ///             Err(mpsc::TryRecvError::Disconnected) => false,
+    ///         });
+    ///     }
+    /// }
+    /// ```
+    pub fn defer(&self, req_packet: Packet) -> DeferredReply {
+        let now = Instant::now();
+        let mut deferred = self.deferred.borrow_mut();
+        deferred.retain(|_, d| now.duration_since(d.at) < DEFERRED_REPLY_TIMEOUT);
+        let token = self.next_token.get();
+        self.next_token.set(token.wrapping_add(1));
+        deferred.insert(token,
+                        Deferred {
+                            request: req_packet,
+                            src: self.src,
+                            at: now,
+                        });
+        DeferredReply(token)
+    }
+
+    /// Answers a request put aside with `defer`, as `reply_with` would have then, sending the
+    /// reply to where the request came from. Fails with `NotFound` if the request was already
+    /// answered or has been dropped.
+    pub fn send_deferred(&self,
+                         token: DeferredReply,
+                         params: ReplyParams)
+                         -> std::io::Result<(usize, SocketAddr)> {
+        let d = self.deferred
+            .borrow_mut()
+            .remove(&token.0)
+            .filter(|d| d.at.elapsed() < DEFERRED_REPLY_TIMEOUT)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No such deferred request"))?;
+        let p = reply_packet(self.server_ip, &self.reply_policy, params, d.request);
+        self.send_from(p, d.src)
+    }
+
+    /// Encodes and sends a DHCP packet back to the client, choosing the destination as
+    /// RFC 2131 section 4.1 describes, so a NAK to a client not behind a relay is always
+    /// broadcast. Returns the number of bytes sent and where they went, as do `reply` and the
+    /// other methods sending through it.
+    pub fn send(&self, p: Packet) -> std::io::Result<(usize, SocketAddr)> {
+        self.send_from(p, self.src)
+    }
+
+    /// Sends a reply to a request received from `src`.
+    fn send_from(&self, p: Packet, src: SocketAddr) -> std::io::Result<(usize, SocketAddr)> {
+        // A reply carries its request's giaddr, and its ciaddr wherever that decides the address
+        let addr = reply_destination(&p, &p, src, self.client_port);
+        self.deliver(p, addr)
+    }
+
+    /// Sends a reply built entirely by the caller, encoded as it is, to where RFC 2131
+    /// section 4.1 says to answer `req`: to the relay in its giaddr, else broadcast for a NAK,
+    /// else back to the querier for an answer to a DHCPLEASEQUERY (RFC 4388 section 6.4), else
+    /// as its ciaddr, broadcast flag and source address call for. Unlike `reply`, the
+    /// reply's own fields and options are left alone, so it must already carry the xid,
+    /// chaddr, SERVER_IDENTIFIER and anything else the client expects.
+    /// Returns the number of bytes sent and where they went.
+    pub fn send_reply(&self, reply: Packet, req: &Packet) -> std::io::Result<(usize, SocketAddr)> {
+        let addr = reply_destination(&reply, req, self.src, self.client_port);
+        self.deliver(reply, addr)
+    }
+
+    /// Sends a reply to `addr`, or as a raw frame if it has to be unicast to a client without
+    /// an address.
+    fn deliver(&self, p: Packet, addr: SocketAddr) -> std::io::Result<(usize, SocketAddr)> {
+        #[cfg(feature = "raw-unicast")]
+        {
+            use std::net::SocketAddrV4;
 
-        let mut opts: Vec<DhcpOption> = Vec::with_capacity(additional_options.len() + 2);
+            if let (Some(raw), SocketAddr::V4(src)) = (&self.raw, self.transport.local_addr()?) {
+                if raw::needs_raw_unicast(&p) {
+                    log_at!(debug,
+                            "Sending {:?} for {} to {} via the packet socket",
+                            p.message_type(),
+                            mac_string(&p.chaddr()),
+                            Ipv4Addr::from(p.yiaddr));
+                    let src = SocketAddrV4::new(Ipv4Addr::from(self.server_ip), src.port());
+                    let dst = SocketAddrV4::new(Ipv4Addr::from(p.yiaddr), addr.port());
+                    let mut buf = self.out_buf.get();
+                    let n = raw.send(p.chaddr(), src, dst, encode_reply(&p, &mut buf)?)?;
+                    return Ok((n, SocketAddr::V4(dst)));
+                }
+            }
+        }
+        self.send_to(p, addr)
+    }
+
+    /// Encodes and sends a DHCP packet to `addr` as given, e.g. to answer on the PXE boot
+    /// service port 4011. Returns the number of bytes sent and `addr`.
+    pub fn send_to(&self, p: Packet, addr: SocketAddr) -> std::io::Result<(usize, SocketAddr)> {
+        log_at!(debug,
+                "Sending {:?} for {} to {} at {}",
+                p.message_type(),
+                mac_string(&p.chaddr()),
+                Ipv4Addr::from(p.yiaddr),
+                addr);
+        let mut buf = self.out_buf.get();
+        let n = self.transport.send_reply(encode_reply(&p, &mut buf)?, addr)?;
+        Ok((n, addr))
+    }
+}
+
+/// Encodes a reply into `buf`, failing with `InvalidInput` if it does not fit.
+fn encode_reply<'b>(p: &Packet, buf: &'b mut [u8]) -> io::Result<&'b [u8]> {
+    match p.encode_into(buf) {
+        Ok(n) => Ok(&buf[..n]),
+        Err(e) => Err(io::Error::new(io::ErrorKind::InvalidInput, e)),
+    }
+}
+
+/// Removes expired leases from the handler's store and reports each to it.
+fn sweep_leases<H: Handler>(handler: &mut H, now: Instant) {
+    let expired = match handler.lease_store() {
+        Some(store) => store.sweep(now),
+        None => return,
+    };
+    for lease in expired {
+        log_at!(info, "Lease of {} to {} expired", lease.ip, lease.client);
+        handler.on_lease_expired(lease);
+    }
+}
+
+/// Checks that a request not relayed came from its chaddr, when the frame's source is known.
+fn check_chaddr(p: &Packet, ctx: &RequestContext) -> io::Result<()> {
+    match ctx.src_mac {
+        Some(mac) if p.giaddr == [0, 0, 0, 0] && mac != p.chaddr() => {
+            Err(io::Error::new(io::ErrorKind::InvalidData,
+                               format!("Dropping request for {} sent from {}",
+                                       mac_string(&p.chaddr()),
+                                       mac_string(&mac))))
+        }
+        _ => Ok(()),
+    }
+}
+
+type DedupKey = ([u8; 4], [u8; 6], Option<u8>);
+
+/// Records `p` in `seen`, returning true if a packet with the same xid, chaddr and message type
+/// was recorded less than `window` ago. Entries older than the window are pruned.
+fn is_duplicate(seen: &mut HashMap<DedupKey, Instant>, p: &Packet, window: Duration) -> bool {
+    let now = Instant::now();
+    seen.retain(|_, &mut t| now.duration_since(t) < window);
+    let key = (p.xid, p.chaddr(), p.message_type().ok().map(|t| t.to_u8()));
+    if seen.contains_key(&key) {
+        return true;
+    }
+    seen.insert(key, now);
+    false
+}
+
+/// Formats a hardware address for log messages.
+fn mac_string(mac: &[u8; 6]) -> String {
+    let parts: Vec<String> = mac.iter().map(|b| format!("{:02x}", b)).collect();
+    parts.join(":")
+}
+
+/// Assembles the reply sent by `Server::reply`.
+fn reply_packet(server_ip: [u8; 4],
+                policy: &ReplyPolicy,
+                params: ReplyParams,
+                req_packet: Packet)
+                -> Packet {
+    let ReplyParams { msg_type, yiaddr, siaddr, options: additional_options, broadcast } = params;
+    let mut p = req_packet.clone_for_reply(msg_type);
+    if let Some(b) = broadcast {
+        p.flags.set_broadcast(b);
+    }
+    if !matches!(msg_type, MessageType::Nak) {
+        p.ciaddr = req_packet.ciaddr;
+    }
+    p.yiaddr = yiaddr;
+    p.siaddr = siaddr;
+
+    let opts = &mut p.options;
+    opts.reserve(additional_options.len() + 1);
+    opts.push(DhcpOption {
+        code: options::SERVER_IDENTIFIER,
+        data: server_ip.to_vec(),
+    });
+    opts.extend(additional_options);
+
+    policy.apply(opts, &req_packet);
+    // An ACK to a DISCOVER commits the lease at once, which RFC 4039 has the server confirm
+    if matches!(msg_type, MessageType::Ack) && req_packet.wants_rapid_commit() {
+        opts.push(DhcpOption {
+            code: options::RAPID_COMMIT,
+            data: Vec::new(),
+        });
+    }
+    // RFC 3046 has the relay agent information echoed verbatim, as the last option
+    if let Some(info) = req_packet.option(options::RELAY_AGENT_INFORMATION) {
+        opts.retain(|o| o.code != options::RELAY_AGENT_INFORMATION);
         opts.push(DhcpOption {
-            code: options::DHCP_MESSAGE_TYPE,
-            data: mt,
+            code: options::RELAY_AGENT_INFORMATION,
+            data: info.to_vec(),
         });
+    }
+    // Without a MAXIMUM_DHCP_MESSAGE_SIZE, only the minimum every client accepts is safe
+    fit_options(opts, req_packet.max_message_size().unwrap_or(MIN_MAX_MESSAGE_SIZE));
+    p
+}
+
+/// What `Server::nak` replies with.
+fn nak_params(message: &str) -> ReplyParams {
+    ReplyParams {
+        msg_type: MessageType::Nak,
+        yiaddr: [0, 0, 0, 0],
+        siaddr: [0, 0, 0, 0],
+        options: vec![DhcpOption::message(message)],
+        broadcast: Some(true),
+    }
+}
+
+/// Assembles the reply sent by `Server::reply_lease_active`.
+fn lease_active_packet(server_ip: [u8; 4],
+                       policy: &ReplyPolicy,
+                       lease: &Lease,
+                       last_transaction: Duration,
+                       associated_ips: &[Ipv4Addr],
+                       req_packet: Packet)
+                       -> Packet {
+    let remaining = lease.remaining(Instant::now());
+    let mut opts = vec![DhcpOption::from_lease_time(options::IP_ADDRESS_LEASE_TIME, remaining)];
+    if let ClientId::Identifier(ref id) = lease.client {
         opts.push(DhcpOption {
-            code: options::SERVER_IDENTIFIER,
-            data: &self.server_ip,
+            code: options::CLIENT_IDENTIFIER,
+            data: id.clone(),
         });
-        opts.extend(additional_options);
+    }
+    opts.push(DhcpOption::from_duration(options::CLIENT_LAST_TRANSACTION_TIME, last_transaction));
+    if !associated_ips.is_empty() {
+        opts.push(DhcpOption {
+            code: options::ASSOCIATED_IP,
+            data: options::encode_ips(associated_ips),
+        });
+    }
+    let params = ReplyParams {
+        msg_type: MessageType::LeaseActive,
+        yiaddr: [0, 0, 0, 0],
+        siaddr: [0, 0, 0, 0],
+        options: opts,
+        broadcast: None,
+    };
+    let mut p = reply_packet(server_ip, policy, params, req_packet);
+    p.ciaddr = lease.ip.octets();
+    if let ClientId::Hardware(chaddr) = lease.client {
+        p.set_hardware_address(HardwareType::Ethernet, &chaddr);
+    }
+    p
+}
+
+/// Length of the IP and UDP headers counted in MAXIMUM_DHCP_MESSAGE_SIZE.
+const IP_UDP_HEADER_LEN: usize = 28;
+
+/// Smallest MAXIMUM_DHCP_MESSAGE_SIZE allowed by RFC 2132; every client must accept this much.
+const MIN_MAX_MESSAGE_SIZE: u16 = 576;
+
+/// Drops options from the end until a reply carrying `opts` fits in `max_size` bytes,
+/// including IP and UDP headers. The options `filter_options_by_req` always keeps first are
+/// never dropped, and after it the last options are those the client wanted least.
+fn fit_options(opts: &mut Vec<DhcpOption>, max_size: u16) {
+    let max_len = usize::from(max_size.max(MIN_MAX_MESSAGE_SIZE)) - IP_UDP_HEADER_LEN;
+    let mandatory = |o: &DhcpOption| {
+        matches!(o.code,
+                 options::DHCP_MESSAGE_TYPE | options::SERVER_IDENTIFIER |
+                 options::IP_ADDRESS_LEASE_TIME | options::RAPID_COMMIT |
+                 options::RELAY_AGENT_INFORMATION)
+    };
+    // Fixed fields, magic cookie and END
+    let mut len = 241 + opts.iter().map(|o| encoded_option_len(o.data.len())).sum::<usize>();
+    while len > max_len {
+        match opts.iter().rposition(|o| !mandatory(o)) {
+            Some(i) => {
+                let o = opts.remove(i);
+                log_at!(debug, "Dropping option {} to fit the client's maximum size", o.code);
+                len -= encoded_option_len(o.data.len());
+            }
+            None => break,
+        }
+    }
+}
+
+/// Checks the invariants `Server::reply` enforces: OFFERs and ACKs assign an address, and
+/// NAKs neither assign one nor carry the parameters of a lease.
+fn check_reply(msg_type: MessageType,
+               offer_ip: [u8; 4],
+               additional_options: &[DhcpOption])
+               -> io::Result<()> {
+    let invalid = |msg| Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+    match msg_type {
+        MessageType::Offer | MessageType::Ack if offer_ip == [0, 0, 0, 0] => {
+            invalid("An OFFER or ACK must carry the offered address")
+        }
+        MessageType::Nak if offer_ip != [0, 0, 0, 0] => invalid("A NAK must not offer an address"),
+        MessageType::Nak if additional_options.iter().any(|o| is_lease_option(o.code) ||
+                                                          o.code == options::SUBNET_MASK) => {
+            invalid("A NAK must not carry lease parameters")
+        }
+        _ => Ok(()),
+    }
+}
+
+fn is_lease_option(code: u8) -> bool {
+    matches!(code,
+             options::IP_ADDRESS_LEASE_TIME | options::RENEWAL_TIME_VALUE |
+             options::REBINDING_TIME_VALUE)
+}
+
+/// What `Server::reply_inform` sends: an ACK without an address, and without the lease time,
+/// renewal and rebinding options, which must not be sent to an INFORM.
+fn inform_params(opts: Vec<DhcpOption>) -> ReplyParams {
+    ReplyParams {
+        msg_type: MessageType::Ack,
+        yiaddr: [0, 0, 0, 0],
+        siaddr: [0, 0, 0, 0],
+        options: opts.into_iter().filter(|o| !is_lease_option(o.code)).collect(),
+        broadcast: None,
+    }
+}
+
+/// UDP port DHCP servers and relay agents listen on.
+pub const SERVER_PORT: u16 = 67;
+
+/// Chooses where to send a reply, following RFC 2131 section 4.1:
+/// a request forwarded by a relay agent (non-zero giaddr) is answered to the relay on the server
+/// port, a client that already has an address (non-zero ciaddr) is unicast to it, a client that
+/// set the broadcast flag is broadcast to. Otherwise the reply goes back to the address the
+/// request came from, falling back to broadcast when the sender has no address yet.
+///
+/// Replies to clients go to `client_port`, normally `CLIENT_PORT`, whichever port the request
+/// came from: a relay forwards from port 67, and a client's own request may have been sent
+/// through a NAT or from another socket.
+fn destination(p: &Packet, src: SocketAddr, client_port: u16) -> SocketAddr {
+    let giaddr = Ipv4Addr::from(p.giaddr);
+    if !giaddr.is_unspecified() {
+        return SocketAddr::new(IpAddr::V4(giaddr), SERVER_PORT);
+    }
+    let ciaddr = Ipv4Addr::from(p.ciaddr);
+    let ip = if !ciaddr.is_unspecified() {
+        ciaddr
+    } else if p.is_broadcast() || src.ip().is_unspecified() {
+        Ipv4Addr::BROADCAST
+    } else {
+        match src.ip() {
+            IpAddr::V4(ip) => ip,
+            IpAddr::V6(_) => Ipv4Addr::BROADCAST,
+        }
+    };
+    SocketAddr::new(IpAddr::V4(ip), client_port)
+}
+
+/// Where `Server::send_reply` sends `reply` to a request received from `src`.
+fn reply_destination(reply: &Packet,
+                     req: &Packet,
+                     src: SocketAddr,
+                     client_port: u16)
+                     -> SocketAddr {
+    if req.giaddr != [0, 0, 0, 0] {
+        return destination(req, src, client_port);
+    }
+    match reply.message_type() {
+        Ok(MessageType::Nak) => SocketAddr::new(IpAddr::V4(Ipv4Addr::BROADCAST), client_port),
+        // The querier is not the client whose address a LEASEQUERY names in ciaddr
+        Ok(MessageType::LeaseActive | MessageType::LeaseUnassigned | MessageType::LeaseUnknown) => {
+            src
+        }
+        _ => destination(req, src, client_port),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::PacketBuilder;
+    use std::net::UdpSocket;
+    use std::sync::mpsc;
+    use std::sync::Arc;
+    use std::thread;
+
+    struct Recorder(mpsc::Sender<[u8; 4]>);
+
+    impl Handler for Recorder {
+        fn handle_request_from(&mut self, _: &Server, packet: Packet, _: SocketAddr) {
+            self.0.send(packet.xid).unwrap();
+        }
+    }
+
+    /// Accepts only packets carrying a configuration token of "secret".
+    struct TokenCheck(Recorder);
+
+    impl Handler for TokenCheck {
+        fn authenticate(&mut self, _: &Server, packet: &Packet) -> bool {
+            packet.authentication().is_some_and(|a| {
+                a.protocol == options::AUTH_PROTOCOL_CONFIGURATION_TOKEN && a.auth_info == b"secret"
+            })
+        }
+
+        fn handle_request_from(&mut self, server: &Server, packet: Packet, src: SocketAddr) {
+            self.0.handle_request_from(server, packet, src);
+        }
+    }
+
+    /// Answers with an OFFER and a copy sent to a second socket, as a proxyDHCP server might.
+    struct TwoReplies(SocketAddr);
+
+    impl Handler for TwoReplies {
+        fn handle_request_from(&mut self, server: &Server, packet: Packet, _: SocketAddr) {
+            server.reply(MessageType::Offer, Vec::new(), [10, 0, 0, 5], packet.clone()).unwrap();
+            let mut p = PacketBuilder::new().reply(true).build();
+            p.xid = packet.xid;
+            server.send_to(p, self.0).unwrap();
+        }
+    }
+
+    /// Holds leases that have all expired, reporting each swept one.
+    struct Expiring {
+        store: MemoryLeaseStore,
+        expired: mpsc::Sender<Lease>,
+    }
+
+    impl Handler for Expiring {
+        fn lease_store(&mut self) -> Option<&mut dyn LeaseStore> {
+            Some(&mut self.store)
+        }
+
+        fn on_lease_expired(&mut self, lease: Lease) {
+            self.expired.send(lease).unwrap();
+        }
+    }
+
+    struct Ticker(mpsc::Sender<()>);
+
+    impl Handler for Ticker {
+        fn tick(&mut self) {
+            let _ = self.0.send(());
+        }
+    }
+
+    /// A server serving a loopback socket on its own thread, for the tests of `serve_until`
+    /// and read timeouts that a `MockTransport` cannot stand in for.
+    struct Running {
+        addr: SocketAddr,
+        shutdown: Arc<AtomicBool>,
+        handle: thread::JoinHandle<io::Result<()>>,
+    }
 
-        if let Some(prl) = req_packet.option(options::PARAMETER_REQUEST_LIST) {
-            filter_options_by_req(&mut opts, &prl);
+    impl Running {
+        fn spawn<F>(serve: F) -> Running
+            where F: FnOnce(UdpSocket, &AtomicBool) -> io::Result<()> + Send + 'static
+        {
+            let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+            let addr = socket.local_addr().unwrap();
+            let shutdown = Arc::new(AtomicBool::new(false));
+            let flag = shutdown.clone();
+            let handle = thread::spawn(move || serve(socket, &flag));
+            Running { addr, shutdown, handle }
         }
 
-        self.send(Packet {
-            reply: true,
-            hops: 0,
-            xid: req_packet.xid,
-            secs: 0,
-            broadcast: req_packet.broadcast,
-            ciaddr: ciaddr,
-            yiaddr: offer_ip,
+        /// Signals shutdown and checks that the server stopped cleanly.
+        fn stop(self) {
+            self.shutdown.store(true, Ordering::SeqCst);
+            assert!(self.handle.join().unwrap().is_ok());
+        }
+    }
+
+    /// Where the `MockTransport` tests' requests come from: a configured client, not relayed.
+    fn client_addr() -> SocketAddr {
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), CLIENT_PORT)
+    }
+
+    #[derive(Default)]
+    struct Dispatch {
+        calls: Vec<&'static str>,
+    }
+
+    impl Handler for Dispatch {
+        fn handle_discover(&mut self, _: &Server, _: Packet) {
+            self.calls.push("discover");
+        }
+
+        fn handle_release(&mut self, _: &Server, _: Packet) {
+            self.calls.push("release");
+        }
+    }
+
+    #[test]
+    fn default_dispatch() {
+        let s = Server::new(UdpSocket::bind("127.0.0.1:0").unwrap(), [127, 0, 0, 1]);
+        let mut h = Dispatch::default();
+        for t in [MessageType::Discover, MessageType::Request, MessageType::Release] {
+            h.handle_request_from(&s, PacketBuilder::new().message_type(t).build(), s.src);
+        }
+        h.handle_request_from(&s, PacketBuilder::new().build(), s.src);
+        assert_eq!(h.calls, vec!["discover", "release"]);
+    }
+
+    #[test]
+    fn filter_requested_options() {
+        let mut opts: Vec<DhcpOption> = [options::ROUTER,
+                                         options::DOMAIN_NAME_SERVER,
+                                         options::SUBNET_MASK,
+                                         options::DOMAIN_NAME]
+            .iter()
+            .map(|&code| DhcpOption { code, data: vec![] })
+            .collect();
+        let req = PacketBuilder::new()
+            .option(DhcpOption {
+                code: options::PARAMETER_REQUEST_LIST,
+                data: vec![options::DOMAIN_NAME, options::ROUTER, options::HOST_NAME],
+            })
+            .build();
+        filter_options(&mut opts, &[options::SUBNET_MASK], &req.requested_parameters().unwrap());
+        let codes: Vec<u8> = opts.iter().map(|o| o.code).collect();
+        assert_eq!(codes, vec![options::SUBNET_MASK, options::DOMAIN_NAME, options::ROUTER]);
+    }
+
+    #[test]
+    fn relay_agent_info_echo() {
+        let info = vec![options::AGENT_CIRCUIT_ID, 3, b'e', b't', b'0'];
+        let req = PacketBuilder::new()
+            .giaddr(Ipv4Addr::new(10, 0, 0, 254))
+            .option(DhcpOption {
+                code: options::PARAMETER_REQUEST_LIST,
+                data: vec![options::ROUTER],
+            })
+            .option(DhcpOption {
+                code: options::RELAY_AGENT_INFORMATION,
+                data: info.clone(),
+            })
+            .build();
+        let params = ReplyParams {
+            msg_type: MessageType::Offer,
+            yiaddr: [10, 0, 0, 9],
             siaddr: [0, 0, 0, 0],
-            giaddr: req_packet.giaddr,
-            chaddr: req_packet.chaddr,
-            options: opts,
-        })
+            options: vec![DhcpOption { code: options::ROUTER, data: vec![10, 0, 0, 254] }],
+            broadcast: None,
+        };
+        let r = reply_packet([10, 0, 0, 1], &ReplyPolicy::default(), params, req);
+        let last = r.options.last().unwrap();
+        assert_eq!(last.code, options::RELAY_AGENT_INFORMATION);
+        assert_eq!(last.data, info);
+        let r = Packet::from_bytes(&r.to_bytes()).unwrap();
+        assert_eq!(r.option(options::RELAY_AGENT_INFORMATION), Some(&info[..]));
     }
 
-    /// Checks the packet see if it was intended for this DHCP server (as opposed to some other also on the network).
-    pub fn for_this_server(&self, packet: &Packet) -> bool {
-        match packet.option(options::SERVER_IDENTIFIER) {
-            None => false,
-            Some(x) => (x == &self.server_ip),
+    #[test]
+    fn nak() {
+        let req = PacketBuilder::new()
+            .xid(3)
+            .ciaddr(Ipv4Addr::new(10, 0, 0, 9))
+            .message_type(MessageType::Request)
+            .build();
+        let r = reply_packet([10, 0, 0, 1], &ReplyPolicy::default(), nak_params("Moved"), req);
+        assert!(matches!(r.message_type(), Ok(MessageType::Nak)));
+        assert_eq!(r.yiaddr, [0; 4]);
+        assert_eq!(r.ciaddr, [0; 4]);
+        assert!(r.is_broadcast());
+        assert_eq!(r.server_identifier(), Some(Ipv4Addr::new(10, 0, 0, 1)));
+        assert_eq!(r.option(options::MESSAGE), Some(&b"Moved"[..]));
+        let src = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 9)), 68);
+        assert_eq!(destination(&r, src, CLIENT_PORT).ip(), IpAddr::V4(Ipv4Addr::BROADCAST));
+
+        // A long reason stays a single valid option
+        let reason = "é".repeat(300);
+        let req = PacketBuilder::new().xid(4).message_type(MessageType::Request).build();
+        let r = reply_packet([10, 0, 0, 1], &ReplyPolicy::default(), nak_params(&reason), req);
+        let b = r.to_bytes();
+        let p = PacketRef::parse(&b).unwrap();
+        assert_eq!(p.options().filter(|&(c, _)| c == options::MESSAGE).count(), 1);
+        assert_eq!(p.option(options::MESSAGE), Some(&reason.as_bytes()[..254]));
+    }
+
+    #[test]
+    fn reply_invariants() {
+        let lease = || vec![DhcpOption::from_duration(options::IP_ADDRESS_LEASE_TIME,
+                                                       Duration::from_secs(60))];
+        let mask = vec![DhcpOption {
+                            code: options::SUBNET_MASK,
+                            data: vec![255, 255, 255, 0],
+                        }];
+        let ip = [10, 0, 0, 5];
+        assert!(check_reply(MessageType::Offer, ip, &lease()).is_ok());
+        assert!(check_reply(MessageType::Ack, ip, &mask).is_ok());
+        assert!(check_reply(MessageType::Nak, [0; 4], &[DhcpOption::message("No")]).is_ok());
+        assert!(check_reply(MessageType::LeaseUnassigned, [0; 4], &[]).is_ok());
+        for (msg_type, ip, opts) in [(MessageType::Offer, [0; 4], Vec::new()),
+                                     (MessageType::Ack, [0; 4], lease()),
+                                     (MessageType::Nak, ip, Vec::new()),
+                                     (MessageType::Nak, [0; 4], lease()),
+                                     (MessageType::Nak, [0; 4], mask.clone())] {
+            let e = check_reply(msg_type, ip, &opts).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
         }
+
+        // Nothing is sent for a rejected reply
+        let mock = MockTransport::new();
+        let s = Server::new(mock.clone(), [10, 0, 0, 1]);
+        let req = PacketBuilder::new().xid(1).message_type(MessageType::Request).build();
+        assert!(s.reply(MessageType::Ack, Vec::new(), [0; 4], req.clone()).is_err());
+        assert!(mock.take_replies().is_empty());
+        assert!(s.reply_inform(lease(), req).is_ok());
+        let r = Packet::from_bytes(&mock.take_replies()[0].0).unwrap();
+        assert!(matches!(r.message_type(), Ok(MessageType::Ack)));
+        assert_eq!(r.option(options::IP_ADDRESS_LEASE_TIME), None);
+    }
+
+    #[test]
+    fn reply_policy() {
+        let opts = || -> Vec<DhcpOption> {
+            [options::DOMAIN_NAME, options::IP_ADDRESS_LEASE_TIME, options::ROUTER,
+             options::SUBNET_MASK]
+                .iter()
+                .map(|&code| DhcpOption { code, data: vec![] })
+                .collect()
+        };
+        let codes = |o: &[DhcpOption]| o.iter().map(|o| o.code).collect::<Vec<u8>>();
+        let policy = ReplyPolicy::new().default_options(&[options::SUBNET_MASK, options::ROUTER]);
+
+        let mut o = opts();
+        policy.apply(&mut o, &PacketBuilder::new().build());
+        assert_eq!(codes(&o),
+                   vec![options::IP_ADDRESS_LEASE_TIME, options::SUBNET_MASK, options::ROUTER]);
+
+        let req = PacketBuilder::new()
+            .option(DhcpOption {
+                code: options::PARAMETER_REQUEST_LIST,
+                data: vec![options::ROUTER, options::DOMAIN_NAME],
+            })
+            .build();
+        let mut o = opts();
+        policy.apply(&mut o, &req);
+        assert_eq!(codes(&o),
+                   vec![options::IP_ADDRESS_LEASE_TIME, options::ROUTER, options::DOMAIN_NAME]);
+
+        let mut o = opts();
+        ReplyPolicy::default().apply(&mut o, &PacketBuilder::new().build());
+        assert_eq!(o.len(), 4);
+    }
+
+    #[test]
+    fn lease_queries() {
+        let query = PacketBuilder::new()
+            .giaddr(Ipv4Addr::new(10, 0, 0, 254))
+            .ciaddr(Ipv4Addr::new(10, 0, 0, 9))
+            .message_type(MessageType::LeaseQuery)
+            .build();
+        assert_eq!(LeaseQuery::of(&query), Some(LeaseQuery::Ip(Ipv4Addr::new(10, 0, 0, 9))));
+        let by_mac = PacketBuilder::new()
+            .chaddr([1, 2, 3, 4, 5, 6])
+            .message_type(MessageType::LeaseQuery)
+            .build();
+        assert_eq!(LeaseQuery::of(&by_mac), Some(LeaseQuery::Client([1, 2, 3, 4, 5, 6].into())));
+        assert_eq!(LeaseQuery::of(&PacketBuilder::new().build()), None);
+
+        let lease = Lease {
+            client: [1, 2, 3, 4, 5, 6].into(),
+            ip: Ipv4Addr::new(10, 0, 0, 9),
+            expires: Some(Instant::now() + Duration::from_secs(600)),
+        };
+        let r = lease_active_packet([10, 0, 0, 1],
+                                    &ReplyPolicy::default(),
+                                    &lease,
+                                    Duration::from_secs(30),
+                                    &[Ipv4Addr::new(10, 0, 0, 10)],
+                                    query);
+        assert!(matches!(r.message_type(), Ok(MessageType::LeaseActive)));
+        assert_eq!(r.ciaddr, [10, 0, 0, 9]);
+        assert_eq!(r.chaddr(), [1, 2, 3, 4, 5, 6]);
+        assert_eq!(r.giaddr, [10, 0, 0, 254]);
+        let opts = r.option_map();
+        assert!(opts.lease_time().is_some_and(|t| t <= Duration::from_secs(600)));
+        assert_eq!(opts.get(options::CLIENT_LAST_TRANSACTION_TIME), Some(&[0, 0, 0, 30][..]));
+        assert_eq!(opts.get(options::ASSOCIATED_IP), Some(&[10, 0, 0, 10][..]));
+
+        // A relay's agent information stays the last option
+        let relayed = PacketBuilder::new()
+            .giaddr(Ipv4Addr::new(10, 0, 0, 254))
+            .ciaddr(Ipv4Addr::new(10, 0, 0, 9))
+            .message_type(MessageType::LeaseQuery)
+            .option(DhcpOption {
+                code: options::RELAY_AGENT_INFORMATION,
+                data: vec![1, 2, b'e', b'0'],
+            })
+            .build();
+        let r = lease_active_packet([10, 0, 0, 1],
+                                    &ReplyPolicy::default(),
+                                    &lease,
+                                    Duration::from_secs(30),
+                                    &[Ipv4Addr::new(10, 0, 0, 10)],
+                                    relayed);
+        let codes: Vec<u8> = r.options().map(|o| o.code).collect();
+        assert_eq!(codes.last(), Some(&options::RELAY_AGENT_INFORMATION));
+        assert!(codes.contains(&options::ASSOCIATED_IP));
+        assert!(codes.contains(&options::CLIENT_LAST_TRANSACTION_TIME));
+    }
+
+    #[test]
+    fn reply_params() {
+        let req = PacketBuilder::new()
+            .xid(8)
+            .chaddr([1, 2, 3, 4, 5, 6])
+            .giaddr(Ipv4Addr::new(10, 0, 0, 254))
+            .ciaddr(Ipv4Addr::new(10, 0, 0, 9))
+            .build();
+        let params = ReplyParams {
+            msg_type: MessageType::Ack,
+            yiaddr: [10, 0, 0, 9],
+            siaddr: [10, 0, 0, 2],
+            options: vec![DhcpOption::bootfile_name("pxelinux.0")],
+            broadcast: Some(true),
+        };
+        let r = reply_packet([10, 0, 0, 1], &ReplyPolicy::default(), params.clone(), req.clone());
+        assert!(r.reply);
+        assert_eq!(r.xid, [0, 0, 0, 8]);
+        assert_eq!(r.chaddr(), [1, 2, 3, 4, 5, 6]);
+        assert_eq!(r.giaddr, [10, 0, 0, 254]);
+        assert_eq!(r.ciaddr, [10, 0, 0, 9]);
+        assert_eq!(r.yiaddr, [10, 0, 0, 9]);
+        assert_eq!(r.siaddr, [10, 0, 0, 2]);
+        assert!(r.is_broadcast());
+        assert_eq!(r.option(options::BOOTFILE_NAME), Some(&b"pxelinux.0"[..]));
+
+        let nak = ReplyParams { msg_type: MessageType::Nak, broadcast: None, ..params };
+        let r = reply_packet([10, 0, 0, 1], &ReplyPolicy::default(), nak, req);
+        assert!(!r.is_broadcast());
+        assert_eq!(r.ciaddr, [0; 4]);
     }
 
-    /// Encodes and sends a DHCP packet back to the client.
-    pub fn send(&self, p: Packet) -> std::io::Result<usize> {
-        let mut addr = self.src;
-        if p.broadcast || addr.ip() == IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)) {
-            addr.set_ip(IpAddr::V4(Ipv4Addr::new(255, 255, 255, 255)));
+    #[test]
+    fn max_message_size() {
+        let big = |code| DhcpOption { code, data: vec![0; 200] };
+        let extra = vec![DhcpOption::from_duration(options::IP_ADDRESS_LEASE_TIME, Duration::from_secs(60)),
+                         big(options::VENDOR_SPECIFIC_INFORMATION),
+                         big(options::STATIC_ROUTE),
+                         big(options::CLASSLESS_ROUTE_FORMAT)];
+        let req = PacketBuilder::new()
+            .option(DhcpOption {
+                code: options::MAXIMUM_DHCP_MESSAGE_SIZE,
+                data: 576u16.to_be_bytes().to_vec(),
+            })
+            .build();
+        assert_eq!(req.max_message_size(), Some(576));
+        let params = ReplyParams {
+            msg_type: MessageType::Offer,
+            yiaddr: [0; 4],
+            siaddr: [0; 4],
+            options: extra,
+            broadcast: None,
+        };
+        let r = reply_packet([10, 0, 0, 1], &ReplyPolicy::default(), params.clone(), req);
+        assert!(r.to_bytes().len() <= 576 - 28);
+        let codes: Vec<u8> = r.options().map(|o| o.code).collect();
+        assert_eq!(codes,
+                   vec![options::DHCP_MESSAGE_TYPE,
+                        options::SERVER_IDENTIFIER,
+                        options::IP_ADDRESS_LEASE_TIME,
+                        options::VENDOR_SPECIFIC_INFORMATION]);
+
+        // A client that gives no maximum is held to the 576 bytes every client accepts
+        let req = PacketBuilder::new().build();
+        let r = reply_packet([10, 0, 0, 1], &ReplyPolicy::default(), params, req);
+        assert_eq!(r.options().count(), 4);
+    }
+
+    #[test]
+    fn oversized_reply() {
+        struct BigOffer(mpsc::Sender<io::Result<(usize, SocketAddr)>>);
+
+        impl Handler for BigOffer {
+            fn handle_discover(&mut self, server: &Server, packet: Packet) {
+                let vendor = DhcpOption {
+                    code: options::VENDOR_SPECIFIC_INFORMATION,
+                    data: vec![0; 1400],
+                };
+                let sent = server.reply(MessageType::Offer, vec![vendor], [10, 0, 0, 5], packet);
+                self.0.send(sent).unwrap();
+            }
         }
-        self.socket.send_to(p.encode(&mut self.out_buf.get()), addr)
+
+        let discover = |max_size: Option<u16>| {
+            let mut b = PacketBuilder::new().message_type(MessageType::Discover);
+            if let Some(size) = max_size {
+                b = b.option(DhcpOption {
+                    code: options::MAXIMUM_DHCP_MESSAGE_SIZE,
+                    data: size.to_be_bytes().to_vec(),
+                });
+            }
+            b.build().to_bytes()
+        };
+        let mock = MockTransport::new();
+        mock.push_request(&discover(None), client_addr());
+        mock.push_request(&discover(Some(9000)), client_addr());
+        let (tx, rx) = mpsc::channel();
+        Server::builder().server_ip([10, 0, 0, 1]).serve(mock.clone(), BigOffer(tx));
+
+        // Trimmed to fit 576 bytes without a maximum size, and refused past the send buffer
+        let (n, _) = rx.recv().unwrap().unwrap();
+        assert!(n <= 576 - 28);
+        let err = rx.recv().unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(mock.take_replies().len(), 1);
+    }
+
+    #[test]
+    fn serve_until_shutdown() {
+        let (tx, rx) = mpsc::channel();
+        let server = Running::spawn(|socket, flag| {
+            Server::serve_until(socket, [127, 0, 0, 1], Recorder(tx), flag)
+        });
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client.send_to(&PacketBuilder::new().xid(3).build().to_bytes(), server.addr).unwrap();
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap(), [0, 0, 0, 3]);
+        server.stop();
+    }
+
+    #[test]
+    fn lease_expiry() {
+        let mut store = MemoryLeaseStore::new(Ipv4Addr::new(10, 0, 0, 10), 5, Duration::ZERO);
+        store.request(&[1; 6].into(), Ipv4Addr::new(10, 0, 0, 10)).unwrap();
+        let (tx, rx) = mpsc::channel();
+        let handler = Expiring { store, expired: tx };
+        let server = Running::spawn(|socket, flag| {
+            Server::serve_until(socket, [127, 0, 0, 1], handler, flag)
+        });
+
+        let lease = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!((lease.client, lease.ip), ([1; 6].into(), Ipv4Addr::new(10, 0, 0, 10)));
+        server.stop();
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn tick_on_timeout() {
+        let (tx, rx) = mpsc::channel();
+        let server = Running::spawn(|socket, flag| {
+            Server::builder()
+                .read_timeout(Duration::from_millis(10))
+                .serve_until(socket, Ticker(tx), flag)
+        });
+        for _ in 0..3 {
+            rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        }
+        server.stop();
+    }
+
+    #[test]
+    fn dedup_window() {
+        let mock = MockTransport::new();
+        for xid in [3, 3, 3, 4] {
+            let discover = PacketBuilder::new().xid(xid).message_type(MessageType::Discover);
+            mock.push_request(&discover.build().to_bytes(), client_addr());
+        }
+        let (tx, rx) = mpsc::channel();
+        Server::builder().dedup_window(Duration::from_secs(60)).serve(mock, Recorder(tx));
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec![[0, 0, 0, 3], [0, 0, 0, 4]]);
+    }
+
+    #[test]
+    fn ignore_replies() {
+        let reply = PacketBuilder::new().xid(5).reply(true).build();
+        let request = PacketBuilder::new().xid(6).build();
+        for accept in [false, true] {
+            let mock = MockTransport::new();
+            mock.push_request(&reply.to_bytes(), client_addr());
+            mock.push_request(&request.to_bytes(), client_addr());
+            let (tx, rx) = mpsc::channel();
+            Server::builder().accept_replies(accept).serve(mock, Recorder(tx));
+
+            let xids: Vec<[u8; 4]> = rx.try_iter().collect();
+            if accept {
+                assert_eq!(xids, vec![[0, 0, 0, 5], [0, 0, 0, 6]]);
+            } else {
+                assert_eq!(xids, vec![[0, 0, 0, 6]]);
+            }
+        }
+    }
+
+    #[test]
+    fn mock_transport() {
+        let mock = MockTransport::new();
+        let client = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 68);
+        let relay = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 1, 0, 1)), 67);
+        let discover = PacketBuilder::new()
+            .xid(1)
+            .chaddr([1, 2, 3, 4, 5, 6])
+            .message_type(MessageType::Discover)
+            .build();
+        let relayed = PacketBuilder::new()
+            .xid(2)
+            .chaddr([1, 2, 3, 4, 5, 7])
+            .giaddr(Ipv4Addr::new(10, 1, 0, 1))
+            .message_type(MessageType::Discover)
+            .build();
+        mock.push_request(&discover.to_bytes(), client);
+        mock.push_request(b"not a packet", client);
+        mock.push_request(&relayed.to_bytes(), relay);
+
+        let store = MemoryLeaseStore::new(Ipv4Addr::new(10, 0, 0, 10), 5, Duration::from_secs(60));
+        let e = Server::builder()
+            .server_ip([10, 0, 0, 1])
+            .serve(mock.clone(), PoolHandler::new(store, Vec::new()));
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+
+        let replies = mock.take_replies();
+        assert_eq!(replies.len(), 2);
+        let offer = Packet::from_bytes(&replies[0].0).unwrap();
+        assert!(matches!(offer.message_type(), Ok(MessageType::Offer)));
+        assert_eq!(offer.xid, [0, 0, 0, 1]);
+        assert_eq!(replies[0].1, SocketAddr::new(IpAddr::V4(Ipv4Addr::BROADCAST), CLIENT_PORT));
+        assert_eq!(Packet::from_bytes(&replies[1].0).unwrap().xid, [0, 0, 0, 2]);
+        assert_eq!(replies[1].1, relay);
+        assert!(mock.take_replies().is_empty());
+    }
+
+    #[test]
+    fn verify_chaddr() {
+        /// Records the xids handled and the errors reported, and keeps serving until the mock
+        /// runs out of requests.
+        struct Snooping(mpsc::Sender<Result<[u8; 4], io::ErrorKind>>);
+
+        impl Handler for Snooping {
+            fn handle_request_from(&mut self, _: &Server, packet: Packet, _: SocketAddr) {
+                self.0.send(Ok(packet.xid)).unwrap();
+            }
+
+            fn on_error(&mut self, error: io::Error) -> io::Result<()> {
+                if error.kind() == io::ErrorKind::UnexpectedEof {
+                    return Err(error);
+                }
+                self.0.send(Err(error.kind())).unwrap();
+                Ok(())
+            }
+        }
+
+        let mock = MockTransport::new();
+        let mac = [1, 2, 3, 4, 5, 6];
+        let from = |src_mac| RequestContext {
+            src: SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), CLIENT_PORT),
+            interface: Some(2),
+            dst: None,
+            src_mac,
+        };
+        let request = |xid, giaddr| {
+            PacketBuilder::new().xid(xid).chaddr(mac).giaddr(giaddr).build().to_bytes()
+        };
+        let relay = Ipv4Addr::new(10, 1, 0, 1);
+        mock.push_request_with_context(&request(1, Ipv4Addr::UNSPECIFIED), from(Some(mac)));
+        mock.push_request_with_context(&request(2, Ipv4Addr::UNSPECIFIED), from(Some([9; 6])));
+        mock.push_request_with_context(&request(3, relay), from(Some([9; 6])));
+        mock.push_request_with_context(&request(4, Ipv4Addr::UNSPECIFIED), from(None));
+        let (tx, rx) = mpsc::channel();
+        Server::builder().verify_chaddr(true).serve(mock, Snooping(tx));
+
+        let seen: Vec<_> = rx.try_iter().collect();
+        assert_eq!(seen,
+                   vec![Ok([0, 0, 0, 1]),
+                        Err(io::ErrorKind::InvalidData),
+                        Ok([0, 0, 0, 3]),
+                        Ok([0, 0, 0, 4])]);
+    }
+
+    #[test]
+    fn deferred_replies() {
+        /// Defers the first request and answers it while handling the second.
+        struct Later(Option<DeferredReply>, mpsc::Sender<io::ErrorKind>);
+
+        impl Handler for Later {
+            fn handle_discover(&mut self, server: &Server, packet: Packet) {
+                match self.0 {
+                    None => self.0 = Some(server.defer(packet)),
+                    Some(_) => {
+                        let _ = server.reply(MessageType::Offer, Vec::new(), [10, 0, 0, 6], packet);
+                    }
+                }
+            }
+
+            fn poll_deferred(&mut self, server: &Server) {
+                let token = match self.0 {
+                    Some(token) => token,
+                    None => return,
+                };
+                let offer = ReplyParams {
+                    msg_type: MessageType::Offer,
+                    yiaddr: [10, 0, 0, 5],
+                    siaddr: [0; 4],
+                    options: Vec::new(),
+                    broadcast: None,
+                };
+                if let Err(e) = server.send_deferred(token, offer) {
+                    self.1.send(e.kind()).unwrap();
+                }
+            }
+        }
+
+        let mock = MockTransport::new();
+        let relay = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 1, 0, 1)), 67);
+        let client = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), CLIENT_PORT);
+        let relayed = PacketBuilder::new()
+            .xid(1)
+            .giaddr(Ipv4Addr::new(10, 1, 0, 1))
+            .message_type(MessageType::Discover)
+            .build();
+        let direct = PacketBuilder::new().xid(2).message_type(MessageType::Discover).build();
+        mock.push_request(&relayed.to_bytes(), relay);
+        mock.push_request(&direct.to_bytes(), client);
+        let (tx, rx) = mpsc::channel();
+        Server::builder().serve(mock.clone(), Later(None, tx));
+
+        // Sent before the second request was handled, to the relay it came through
+        let replies = mock.take_replies();
+        assert_eq!(replies.len(), 2);
+        let first = Packet::from_bytes(&replies[0].0).unwrap();
+        assert_eq!((first.xid, first.yiaddr), ([0, 0, 0, 1], [10, 0, 0, 5]));
+        assert_eq!(replies[0].1, relay);
+        assert_eq!(Packet::from_bytes(&replies[1].0).unwrap().xid, [0, 0, 0, 2]);
+        assert_eq!(replies[1].1.ip(), IpAddr::V4(Ipv4Addr::BROADCAST));
+        // A token is only good once
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec![io::ErrorKind::NotFound]);
+    }
+
+    #[test]
+    fn send_reply() {
+        let mock = MockTransport::new();
+        let mut s = Server::new(mock.clone(), [10, 0, 0, 1]);
+        s.src = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 9)), 68);
+        let req = PacketBuilder::new()
+            .xid(6)
+            .ciaddr(Ipv4Addr::new(10, 0, 0, 9))
+            .message_type(MessageType::Request)
+            .build();
+        // The reply is sent as built, addressed as the request asks
+        let mut ack = PacketBuilder::new()
+            .reply(true)
+            .xid(6)
+            .option(DhcpOption::string(options::HOST_NAME, "custom"))
+            .message_type(MessageType::Ack)
+            .build();
+        ack.set_server_name("boot");
+        let (n, dst) = s.send_reply(ack.clone(), &req).unwrap();
+        assert_eq!(dst, SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 9)), CLIENT_PORT));
+        let sent = mock.take_replies();
+        assert_eq!(sent[0].0, ack.to_bytes());
+        assert_eq!(n, sent[0].0.len());
+
+        let nak = PacketBuilder::new().reply(true).message_type(MessageType::Nak).build();
+        let (_, dst) = s.send_reply(nak.clone(), &req).unwrap();
+        assert_eq!(dst.ip(), IpAddr::V4(Ipv4Addr::BROADCAST));
+        let relayed = PacketBuilder::new().giaddr(Ipv4Addr::new(10, 1, 0, 1)).build();
+        let (_, dst) = s.send_reply(nak, &relayed).unwrap();
+        assert_eq!(dst, SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 1, 0, 1)), SERVER_PORT));
+
+        // However it is built, a NAK to a client not behind a relay is broadcast
+        let (_, dst) = s.reply(MessageType::Nak, Vec::new(), [0; 4], req.clone()).unwrap();
+        assert_eq!(dst.ip(), IpAddr::V4(Ipv4Addr::BROADCAST));
+        let (_, dst) = s.reply(MessageType::Nak, Vec::new(), [0; 4], relayed).unwrap();
+        assert_eq!(dst, SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 1, 0, 1)), SERVER_PORT));
+    }
+
+    #[test]
+    fn lease_query_destination() {
+        let mock = MockTransport::new();
+        let mut s = Server::new(mock.clone(), [10, 0, 0, 1]);
+        let querier = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 50)), 4011);
+        s.src = querier;
+        let query = PacketBuilder::new()
+            .ciaddr(Ipv4Addr::new(10, 0, 0, 9))
+            .message_type(MessageType::LeaseQuery)
+            .build();
+        let lease = Lease {
+            client: [1, 2, 3, 4, 5, 6].into(),
+            ip: Ipv4Addr::new(10, 0, 0, 9),
+            expires: None,
+        };
+        // Answered to the querier, not to the leased address in the reply's ciaddr
+        let (_, dst) = s.reply_lease_active(&lease, Duration::ZERO, &[], query.clone()).unwrap();
+        assert_eq!(dst, querier);
+        assert_eq!(s.reply_lease_unassigned(query.clone()).unwrap().1, querier);
+        assert_eq!(s.reply_lease_unknown(query.clone()).unwrap().1, querier);
+        let sent = mock.take_replies();
+        assert_eq!(Packet::from_bytes(&sent[0].0).unwrap().ciaddr, [10, 0, 0, 9]);
+        assert!(sent.iter().all(|(_, dst)| *dst == querier));
+
+        // A relayed query is answered to the relay
+        let mut relayed = query;
+        relayed.giaddr = [10, 1, 0, 1];
+        let (_, dst) = s.reply_lease_active(&lease, Duration::ZERO, &[], relayed).unwrap();
+        assert_eq!(dst, SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 1, 0, 1)), SERVER_PORT));
+    }
+
+    #[test]
+    fn reply_sent_to() {
+        struct Offerer(mpsc::Sender<std::io::Result<(usize, SocketAddr)>>);
+
+        impl Handler for Offerer {
+            fn handle_discover(&mut self, server: &Server, packet: Packet) {
+                let sent = server.reply(MessageType::Offer, Vec::new(), [10, 0, 0, 5], packet);
+                self.0.send(sent).unwrap();
+            }
+        }
+
+        let mock = MockTransport::new();
+        let discover = PacketBuilder::new().xid(1).message_type(MessageType::Discover).build();
+        mock.push_request(&discover.to_bytes(),
+                          SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), CLIENT_PORT));
+        let (tx, rx) = mpsc::channel();
+        Server::builder().server_ip([10, 0, 0, 1]).serve(mock.clone(), Offerer(tx));
+
+        let replies = mock.take_replies();
+        let (n, dst) = rx.recv().unwrap().unwrap();
+        assert_eq!(n, replies[0].0.len());
+        assert_eq!(dst, SocketAddr::new(IpAddr::V4(Ipv4Addr::BROADCAST), CLIENT_PORT));
+    }
+
+    #[test]
+    fn recv_buffer_size() {
+        let big = PacketBuilder::new()
+            .xid(7)
+            .option(DhcpOption {
+                code: options::VENDOR_SPECIFIC_INFORMATION,
+                data: vec![0; 2000],
+            })
+            .build();
+        let mock = MockTransport::new();
+        mock.push_request(&big.to_bytes(), client_addr());
+        let (tx, rx) = mpsc::channel();
+        Server::builder().recv_buffer_size(4096).serve(mock.clone(), Recorder(tx));
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec![[0, 0, 0, 7]]);
+
+        // Truncated to the default buffer, the options run off its end
+        mock.push_request(&big.to_bytes(), client_addr());
+        let (tx, rx) = mpsc::channel();
+        Server::builder().serve(mock, Recorder(tx));
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn authenticate_hook() {
+        let token = |info: &[u8]| DhcpOption {
+            code: options::AUTHENTICATION,
+            data: options::Authentication {
+                    protocol: options::AUTH_PROTOCOL_CONFIGURATION_TOKEN,
+                    algorithm: 0,
+                    rdm: options::AUTH_RDM_MONOTONIC,
+                    replay_detection: 0,
+                    auth_info: info.to_vec(),
+                }
+                .encode(),
+        };
+        let mock = MockTransport::new();
+        for p in [PacketBuilder::new().xid(1).build(),
+                  PacketBuilder::new().xid(2).option(token(b"wrong")).build(),
+                  PacketBuilder::new().xid(3).option(token(b"secret")).build()] {
+            mock.push_request(&p.to_bytes(), client_addr());
+        }
+        let (tx, rx) = mpsc::channel();
+        Server::builder().serve(mock, TokenCheck(Recorder(tx)));
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec![[0, 0, 0, 3]]);
+    }
+
+    #[test]
+    fn multiple_replies() {
+        let boot = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2)), 4011);
+        let mock = MockTransport::new();
+        mock.push_request(&PacketBuilder::new().xid(5).build().to_bytes(), client_addr());
+        Server::builder().server_ip([127, 0, 0, 1]).serve(mock.clone(), TwoReplies(boot));
+
+        let replies = mock.take_replies();
+        assert_eq!(replies.len(), 2);
+        let offer = Packet::from_bytes(&replies[0].0).unwrap();
+        assert!(matches!(offer.message_type(), Ok(MessageType::Offer)));
+        assert_eq!(replies[0].1, client_addr());
+        assert_eq!(replies[1].1, boot);
+        assert_eq!(Packet::from_bytes(&replies[1].0).unwrap().xid, [0, 0, 0, 5]);
+    }
+
+    fn src(ip: [u8; 4]) -> SocketAddr {
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::from(ip)), 68)
+    }
+
+    #[test]
+    fn destination_selection() {
+        // Renewing client with an address is unicast, even if it asked for broadcast
+        let p = PacketBuilder::new().ciaddr(Ipv4Addr::new(192, 168, 0, 5)).broadcast(true).build();
+        assert_eq!(destination(&p, src([192, 168, 0, 5]), CLIENT_PORT), src([192, 168, 0, 5]));
+
+        // Unconfigured client asking for broadcast
+        let p = PacketBuilder::new().broadcast(true).build();
+        assert_eq!(destination(&p, src([0, 0, 0, 0]), CLIENT_PORT), src([255, 255, 255, 255]));
+
+        // Unconfigured client with the broadcast flag clear: no usable address, so broadcast
+        let p = PacketBuilder::new().yiaddr(Ipv4Addr::new(192, 168, 0, 6)).build();
+        assert_eq!(destination(&p, src([0, 0, 0, 0]), CLIENT_PORT), src([255, 255, 255, 255]));
+    }
+
+    #[test]
+    fn destination_relayed() {
+        let relay = Ipv4Addr::new(10, 0, 0, 1);
+        let p = PacketBuilder::new().giaddr(relay).broadcast(true).build();
+        let from = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)), 67);
+        assert_eq!(destination(&p, from, CLIENT_PORT),
+                   SocketAddr::new(IpAddr::V4(relay), SERVER_PORT));
+    }
+
+    #[test]
+    fn destination_ports() {
+        // A direct client gets port 68, even if its request came from elsewhere
+        let p = PacketBuilder::new().ciaddr(Ipv4Addr::new(192, 168, 0, 5)).build();
+        let from = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 0, 5)), 40000);
+        assert_eq!(destination(&p, from, CLIENT_PORT).port(), 68);
+        assert_eq!(destination(&p, from, 1068).port(), 1068);
+
+        // A relay agent gets port 67, whatever the client port
+        let p = PacketBuilder::new().giaddr(Ipv4Addr::new(10, 0, 0, 1)).build();
+        assert_eq!(destination(&p, from, CLIENT_PORT).port(), 67);
+        assert_eq!(destination(&p, from, 1068).port(), 67);
     }
 }
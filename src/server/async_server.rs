@@ -0,0 +1,201 @@
+//! Asynchronous counterpart of `Server::serve`, for running inside an existing tokio runtime.
+
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+use tokio::net::UdpSocket;
+
+use crate::client::CLIENT_PORT;
+use crate::options::{self, DhcpOption, MessageType};
+use crate::packet::{parse, Packet};
+
+use super::{check_reply, inform_params, mac_string, nak_params, reply_destination, reply_packet,
+            ReplyParams, ReplyPolicy};
+
+/// Asynchronous version of `Handler`. Implementations may use `async fn handle_request`.
+pub trait AsyncHandler {
+    /// See `Handler::authenticate`.
+    fn authenticate(&mut self, _server: &AsyncServer, _packet: &Packet) -> bool {
+        true
+    }
+
+    fn handle_request(&mut self, server: &AsyncServer, packet: Packet) -> impl Future<Output = ()>;
+}
+
+pub struct AsyncServer {
+    socket: UdpSocket,
+    src: SocketAddr,
+    server_ip: [u8; 4],
+    client_port: u16,
+}
+
+/// Receives requests and awaits the handler for each, until the socket reports an error.
+/// BOOTREPLY packets, such as another server's replies, are dropped. Replies to clients that
+/// were not relayed go to `CLIENT_PORT`.
+pub async fn serve_async<H: AsyncHandler>(socket: UdpSocket,
+                                          server_ip: [u8; 4],
+                                          handler: H)
+                                          -> io::Error {
+    let s = AsyncServer {
+        socket,
+        server_ip,
+        src: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 0),
+        client_port: CLIENT_PORT,
+    };
+    run(s, handler).await
+}
+
+async fn run<H: AsyncHandler>(mut s: AsyncServer, mut handler: H) -> io::Error {
+    let mut in_buf: [u8; 1500] = [0; 1500];
+    loop {
+        match s.socket.recv_from(&mut in_buf).await {
+            Err(e) => return e,
+            Ok((l, src)) => {
+                match parse(&in_buf[..l]) {
+                    Ok(p) => {
+                        log_at!(debug,
+                                "Received {:?} from {} via {}, {}s into its exchange",
+                                p.message_type(),
+                                mac_string(&p.chaddr()),
+                                src,
+                                p.secs());
+                        s.src = src;
+                        if p.reply {
+                            log_at!(debug, "Ignoring BOOTREPLY from {}", src);
+                            continue;
+                        }
+                        if !handler.authenticate(&s, &p) {
+                            log_at!(info, "Rejected unauthenticated packet from {}", src);
+                            continue;
+                        }
+                        handler.handle_request(&s, p).await;
+                    }
+                    Err(e) => log_at!(warn, "Dropping packet from {}: {}", src, e),
+                }
+            }
+        }
+    }
+}
+
+impl AsyncServer {
+    /// See `Server::reply`.
+    pub async fn reply(&self,
+                       msg_type: MessageType,
+                       additional_options: Vec<DhcpOption>,
+                       offer_ip: [u8; 4],
+                       req_packet: Packet)
+                       -> io::Result<(usize, SocketAddr)> {
+        check_reply(msg_type, offer_ip, &additional_options)?;
+        self.reply_with(ReplyParams {
+                            msg_type,
+                            yiaddr: offer_ip,
+                            siaddr: [0, 0, 0, 0],
+                            options: additional_options,
+                            broadcast: None,
+                        },
+                        req_packet)
+            .await
+    }
+
+    /// See `Server::reply_with`.
+    pub async fn reply_with(&self,
+                            params: ReplyParams,
+                            req_packet: Packet)
+                            -> io::Result<(usize, SocketAddr)> {
+        let policy = ReplyPolicy::default();
+        self.send(reply_packet(self.server_ip, &policy, params, req_packet)).await
+    }
+
+    /// See `Server::nak`.
+    pub async fn nak(&self, message: &str, req_packet: Packet) -> io::Result<(usize, SocketAddr)> {
+        self.reply_with(nak_params(message), req_packet).await
+    }
+
+    /// See `Server::reply_inform`.
+    pub async fn reply_inform(&self,
+                              additional_options: Vec<DhcpOption>,
+                              req_packet: Packet)
+                              -> io::Result<(usize, SocketAddr)> {
+        self.reply_with(inform_params(additional_options), req_packet).await
+    }
+
+    /// See `Server::for_this_server`.
+    pub fn for_this_server(&self, packet: &Packet) -> bool {
+        options::is_for_server(packet, Ipv4Addr::from(self.server_ip))
+    }
+
+    /// Encodes and sends a DHCP packet back to the client. See `Server::send`.
+    pub async fn send(&self, p: Packet) -> io::Result<(usize, SocketAddr)> {
+        let addr = reply_destination(&p, &p, self.src, self.client_port);
+        log_at!(debug,
+                "Sending {:?} for {} to {} at {}",
+                p.message_type(),
+                mac_string(&p.chaddr()),
+                Ipv4Addr::from(p.yiaddr),
+                addr);
+        let n = self.socket.send_to(&p.to_bytes(), addr).await?;
+        Ok((n, addr))
+    }
+
+    /// See `Server::send_reply`.
+    pub async fn send_reply(&self,
+                            reply: Packet,
+                            req: &Packet)
+                            -> io::Result<(usize, SocketAddr)> {
+        let addr = reply_destination(&reply, req, self.src, self.client_port);
+        self.send_to(reply, addr).await
+    }
+
+    /// See `Server::send_to`.
+    pub async fn send_to(&self, p: Packet, addr: SocketAddr) -> io::Result<(usize, SocketAddr)> {
+        log_at!(debug,
+                "Sending {:?} for {} to {}",
+                p.message_type(),
+                mac_string(&p.chaddr()),
+                addr);
+        let n = self.socket.send_to(&p.to_bytes(), addr).await?;
+        Ok((n, addr))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::PacketBuilder;
+
+    struct Offerer;
+
+    impl AsyncHandler for Offerer {
+        async fn handle_request(&mut self, server: &AsyncServer, packet: Packet) {
+            server.reply(MessageType::Offer, Vec::new(), [10, 0, 0, 5], packet).await.unwrap();
+        }
+    }
+
+    #[test]
+    fn serve_async_replies() {
+        let rt = tokio::runtime::Builder::new_current_thread().enable_io().build().unwrap();
+        rt.block_on(async {
+            let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            let server_addr = socket.local_addr().unwrap();
+            let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            let server = AsyncServer {
+                socket,
+                server_ip: [10, 0, 0, 1],
+                src: server_addr,
+                client_port: client.local_addr().unwrap().port(),
+            };
+            tokio::spawn(run(server, Offerer));
+
+            let discover = PacketBuilder::new().xid(9).message_type(MessageType::Discover).build();
+            client.send_to(&discover.to_bytes(), server_addr).await.unwrap();
+
+            let mut buf = [0; 1500];
+            let (l, _) = client.recv_from(&mut buf).await.unwrap();
+            let offer = Packet::from_bytes(&buf[..l]).unwrap();
+            assert!(matches!(offer.message_type(), Ok(MessageType::Offer)));
+            assert_eq!(offer.xid, [0, 0, 0, 9]);
+            assert_eq!(offer.yiaddr, [10, 0, 0, 5]);
+        });
+    }
+}
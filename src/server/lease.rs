@@ -0,0 +1,791 @@
+//! Lease bookkeeping, and a `Handler` that serves addresses from any `LeaseStore`.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::Ipv4Addr;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::options::{self, DhcpOption, LeaseTime, MessageType};
+use crate::packet::Packet;
+
+use super::{Handler, Server};
+
+/// Identifies a client for leasing. RFC 2131 section 4.2 keys leases on the Client Identifier
+/// (option 61) when the client sends one, and its hardware address otherwise, so that a client
+/// keeps its lease when its network card changes, and clients sharing a card get their own.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ClientId {
+    Identifier(Vec<u8>),
+    Hardware([u8; 6]),
+}
+
+impl ClientId {
+    /// The key for the client sending `packet`.
+    pub fn of(packet: &Packet) -> ClientId {
+        match packet.client_identifier() {
+            Some(id) => ClientId::Identifier(id.to_vec()),
+            None => ClientId::Hardware(packet.chaddr()),
+        }
+    }
+}
+
+impl From<[u8; 6]> for ClientId {
+    fn from(chaddr: [u8; 6]) -> ClientId {
+        ClientId::Hardware(chaddr)
+    }
+}
+
+/// Hardware addresses are shown like `01:02:03:04:05:06`, identifiers as `id:` followed by
+/// their bytes in hex.
+impl fmt::Display for ClientId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ClientId::Identifier(ref id) => {
+                write!(f, "id:")?;
+                for b in id {
+                    write!(f, "{:02x}", b)?;
+                }
+                Ok(())
+            }
+            ClientId::Hardware(ref mac) => {
+                let parts: Vec<String> = mac.iter().map(|b| format!("{:02x}", b)).collect();
+                write!(f, "{}", parts.join(":"))
+            }
+        }
+    }
+}
+
+/// What a DHCPLEASEQUERY asks about (RFC 4388 section 6.1): the address in ciaddr if set,
+/// otherwise the client with the Client Identifier or, failing that, hardware address given.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LeaseQuery {
+    Ip(Ipv4Addr),
+    Client(ClientId),
+}
+
+impl LeaseQuery {
+    /// Returns the query made by `packet`, or `None` if it is not a DHCPLEASEQUERY or names
+    /// neither an address nor a client.
+    pub fn of(packet: &Packet) -> Option<LeaseQuery> {
+        if !matches!(packet.message_type(), Ok(MessageType::LeaseQuery)) {
+            return None;
+        }
+        if packet.ciaddr != [0; 4] {
+            return Some(LeaseQuery::Ip(Ipv4Addr::from(packet.ciaddr)));
+        }
+        if packet.client_identifier().is_none() && packet.chaddr() == [0; 6] {
+            return None;
+        }
+        Some(LeaseQuery::Client(ClientId::of(packet)))
+    }
+}
+
+/// An address bound to a client until `expires`, or for good if that is `None`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lease {
+    pub client: ClientId,
+    pub ip: Ipv4Addr,
+    pub expires: Option<Instant>,
+}
+
+impl Lease {
+    /// Whether the lease still binds the address at `now`. An expired lease may still be
+    /// recorded, but no longer reserves the address.
+    pub fn is_active(&self, now: Instant) -> bool {
+        match self.expires {
+            Some(expires) => now < expires,
+            None => true,
+        }
+    }
+
+    /// The time left on the lease at `now`.
+    pub fn remaining(&self, now: Instant) -> LeaseTime {
+        match self.expires {
+            Some(expires) => LeaseTime::Finite(expires.saturating_duration_since(now)),
+            None => LeaseTime::Infinite,
+        }
+    }
+}
+
+/// Reasons a `LeaseStore` refuses a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LeaseError {
+    /// The address is not one the store hands out.
+    OutOfRange,
+    /// The address is leased to another client.
+    Unavailable,
+}
+
+impl fmt::Display for LeaseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LeaseError::OutOfRange => write!(f, "Address not in pool"),
+            LeaseError::Unavailable => write!(f, "Address leased to another client"),
+        }
+    }
+}
+
+impl Error for LeaseError {}
+
+/// Storage for leases, keyed by `ClientId`.
+pub trait LeaseStore {
+    /// Chooses an address to offer the client, without committing to it.
+    fn allocate(&mut self, client: &ClientId) -> Option<Ipv4Addr>;
+
+    /// Leases `ip` to the client, or renews its existing lease on it.
+    fn request(&mut self, client: &ClientId, ip: Ipv4Addr) -> Result<(), LeaseError>;
+
+    /// Drops the client's lease, if any.
+    fn release(&mut self, client: &ClientId);
+
+    /// Drops the client's lease after it found `ip` already in use (a DHCPDECLINE). RFC 2131
+    /// section 4.3.3 has the server mark the address as not available, so stores should keep
+    /// it from being handed out again for a while. The default only calls `release`.
+    fn decline(&mut self, client: &ClientId, _ip: Ipv4Addr) {
+        self.release(client);
+    }
+
+    /// Returns the client's active lease.
+    fn lookup(&self, client: &ClientId) -> Option<Lease>;
+
+    /// How long leases granted by `request` last.
+    fn lease_duration(&self) -> Duration;
+
+    /// How long leases granted by `request` last, as sent to clients. Stores granting infinite
+    /// leases override the default, which is `lease_duration`.
+    fn lease_time(&self) -> LeaseTime {
+        LeaseTime::Finite(self.lease_duration())
+    }
+
+    /// Removes the leases that expired by `now` and returns them. Stores that keep nothing
+    /// after expiry can rely on the default, which removes nothing.
+    fn sweep(&mut self, _now: Instant) -> Vec<Lease> {
+        Vec::new()
+    }
+}
+
+/// A `LeaseStore` handing out a contiguous range of addresses, held in memory only.
+pub struct MemoryLeaseStore {
+    start: u32,
+    count: u32,
+    lease_time: LeaseTime,
+    leases: HashMap<u32, Lease>,
+    /// Declined addresses, held until the time given, or for good with infinite leases.
+    declined: HashMap<u32, Option<Instant>>,
+    last: u32,
+}
+
+impl MemoryLeaseStore {
+    /// Creates a store for the `count` addresses beginning at `start`, stopping short at
+    /// 255.255.255.255.
+    pub fn new(start: Ipv4Addr, count: u32, lease_duration: Duration) -> MemoryLeaseStore {
+        let start = u32::from(start);
+        MemoryLeaseStore {
+            start,
+            count: count.min((u32::MAX - start).saturating_add(1)),
+            lease_time: LeaseTime::Finite(lease_duration),
+            leases: HashMap::new(),
+            declined: HashMap::new(),
+            last: 0,
+        }
+    }
+
+    /// Makes the leases granted from now on last `t`, for instance forever for a store of
+    /// static reservations.
+    pub fn set_lease_time(&mut self, t: LeaseTime) {
+        self.lease_time = t;
+    }
+
+    /// Writes the leases to `path`, one per line as `<client> <ip> <expiry>`, where the client
+    /// is shown as by `ClientId`'s `Display` and the expiry is in seconds since the Unix epoch,
+    /// or `infinite`. The file is replaced atomically.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let now = Instant::now();
+        let wall = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let mut out = String::new();
+        for l in self.leases.values() {
+            let expiry = match l.remaining(now) {
+                LeaseTime::Finite(d) => (wall + d).as_secs().to_string(),
+                LeaseTime::Infinite => "infinite".to_string(),
+            };
+            out.push_str(&format!("{} {} {}\n", l.client, l.ip, expiry));
+        }
+        let path = path.as_ref();
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, out)?;
+        fs::rename(&tmp, path)
+    }
+
+    /// Adds the leases saved by `save` to the store, skipping those that have expired or lie
+    /// outside the store's range.
+    pub fn load<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let now = Instant::now();
+        let wall = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Invalid lease line");
+        for line in fs::read_to_string(path)?.lines().filter(|l| !l.trim().is_empty()) {
+            let (client, ip, expiry) = parse_lease_line(line).ok_or_else(invalid)?;
+            if expiry.is_some_and(|e| e <= wall) || !self.in_range(u32::from(ip)) {
+                continue;
+            }
+            let expires = match expiry {
+                Some(e) => {
+                    Some(now.checked_add(Duration::from_secs(e - wall)).ok_or_else(invalid)?)
+                }
+                None => None,
+            };
+            self.leases.insert(u32::from(ip), Lease { client, ip, expires });
+        }
+        Ok(())
+    }
+
+    fn in_range(&self, ip: u32) -> bool {
+        ip.wrapping_sub(self.start) < self.count
+    }
+
+    fn is_declined(&self, ip: u32, now: Instant) -> bool {
+        self.declined.get(&ip).is_some_and(|until| until.is_none_or(|u| now < u))
+    }
+
+    fn available(&self, client: &ClientId, ip: u32) -> bool {
+        if self.is_declined(ip, Instant::now()) {
+            return false;
+        }
+        match self.leases.get(&ip) {
+            Some(l) => l.client == *client || !l.is_active(Instant::now()),
+            None => true,
+        }
+    }
+
+    /// The lease recorded for the client, active or not.
+    fn recorded(&self, client: &ClientId) -> Option<&Lease> {
+        self.leases.values().find(|l| l.client == *client)
+    }
+}
+
+impl LeaseStore for MemoryLeaseStore {
+    /// Chooses, in order of preference:
+    ///
+    /// 1. the client's active lease;
+    /// 2. the client's expired lease, which is only still recorded if no other client has
+    ///    since been granted the address;
+    /// 3. an address that has never been leased, searching onwards from the last one handed out;
+    /// 4. the address whose lease to another client expired longest ago.
+    ///
+    /// Reusing other clients' expired leases last gives their owners the best chance of getting
+    /// their old address back, until `sweep` forgets them.
+    fn allocate(&mut self, client: &ClientId) -> Option<Ipv4Addr> {
+        let now = Instant::now();
+        if let Some(l) = self.recorded(client) {
+            // Both an active and an expired lease are still held by this client
+            return Some(l.ip);
+        }
+        for _ in 0..self.count {
+            self.last = (self.last + 1) % self.count;
+            let ip = self.start + self.last;
+            if !self.leases.contains_key(&ip) && !self.is_declined(ip, now) {
+                return Some(Ipv4Addr::from(ip));
+            }
+        }
+        self.leases
+            .values()
+            .filter(|l| !l.is_active(now) && !self.is_declined(u32::from(l.ip), now))
+            .min_by_key(|l| l.expires)
+            .map(|l| l.ip)
+    }
+
+    fn request(&mut self, client: &ClientId, ip: Ipv4Addr) -> Result<(), LeaseError> {
+        let n = u32::from(ip);
+        if !self.in_range(n) {
+            return Err(LeaseError::OutOfRange);
+        }
+        if !self.available(client, n) {
+            return Err(LeaseError::Unavailable);
+        }
+        self.release(client);
+        self.leases.insert(n,
+                           Lease {
+                               client: client.clone(),
+                               ip,
+                               expires: match self.lease_time {
+                                   LeaseTime::Finite(d) => Some(Instant::now() + d),
+                                   LeaseTime::Infinite => None,
+                               },
+                           });
+        Ok(())
+    }
+
+    fn release(&mut self, client: &ClientId) {
+        self.leases.retain(|_, l| l.client != *client);
+    }
+
+    /// Holds `ip` out of use for as long as a lease granted now would last. Declines are not
+    /// saved by `save`.
+    fn decline(&mut self, client: &ClientId, ip: Ipv4Addr) {
+        self.release(client);
+        let n = u32::from(ip);
+        if self.in_range(n) {
+            let until = match self.lease_time {
+                LeaseTime::Finite(d) => Instant::now().checked_add(d),
+                LeaseTime::Infinite => None,
+            };
+            self.declined.insert(n, until);
+        }
+    }
+
+    fn lookup(&self, client: &ClientId) -> Option<Lease> {
+        self.recorded(client).filter(|l| l.is_active(Instant::now())).cloned()
+    }
+
+    /// `Duration::MAX` for infinite leases.
+    fn lease_duration(&self) -> Duration {
+        match self.lease_time {
+            LeaseTime::Finite(d) => d,
+            LeaseTime::Infinite => Duration::MAX,
+        }
+    }
+
+    fn lease_time(&self) -> LeaseTime {
+        self.lease_time
+    }
+
+    fn sweep(&mut self, now: Instant) -> Vec<Lease> {
+        let mut expired: Vec<Lease> =
+            self.leases.values().filter(|l| !l.is_active(now)).cloned().collect();
+        self.leases.retain(|_, l| l.is_active(now));
+        self.declined.retain(|_, until| until.is_none_or(|u| now < u));
+        expired.sort_by_key(|l| l.expires);
+        expired
+    }
+}
+
+/// Parses a line written by `MemoryLeaseStore::save`, with no expiry for an infinite lease.
+fn parse_lease_line(line: &str) -> Option<(ClientId, Ipv4Addr, Option<u64>)> {
+    let mut fields = line.split_whitespace();
+    let client = parse_client_id(fields.next()?)?;
+    let ip = fields.next()?.parse().ok()?;
+    let expiry = match fields.next()? {
+        "infinite" => None,
+        secs => Some(secs.parse().ok()?),
+    };
+    Some((client, ip, expiry))
+}
+
+/// Parses a `ClientId` in the form written by its `Display`.
+fn parse_client_id(s: &str) -> Option<ClientId> {
+    if let Some(hex) = s.strip_prefix("id:") {
+        if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+            return None;
+        }
+        let id = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+            .collect::<Option<Vec<u8>>>()?;
+        return Some(ClientId::Identifier(id));
+    }
+    let mut chaddr = [0; 6];
+    let mut mac = s.split(':');
+    for b in &mut chaddr {
+        *b = u8::from_str_radix(mac.next()?, 16).ok()?;
+    }
+    if mac.next().is_some() {
+        return None;
+    }
+    Some(ClientId::Hardware(chaddr))
+}
+
+/// A `Handler` leasing addresses from a `LeaseStore`, sending `options` (such as the subnet
+/// mask, router and DNS servers) with every reply.
+pub struct PoolHandler<S: LeaseStore> {
+    pub store: S,
+    pub options: Vec<DhcpOption>,
+    /// Whether to answer a DISCOVER asking for rapid commit with an ACK straight away. Must
+    /// only be set when this is the only DHCP server on the network, see `Server::reply`.
+    /// False by default.
+    pub rapid_commit: bool,
+}
+
+impl<S: LeaseStore> PoolHandler<S> {
+    pub fn new(store: S, options: Vec<DhcpOption>) -> PoolHandler<S> {
+        PoolHandler { store, options, rapid_commit: false }
+    }
+
+    fn reply_options(&self) -> Vec<DhcpOption> {
+        let mut opts = vec![DhcpOption::from_lease_time(options::IP_ADDRESS_LEASE_TIME,
+                                                        self.store.lease_time())];
+        opts.extend(self.config_options());
+        opts
+    }
+
+    fn config_options(&self) -> Vec<DhcpOption> {
+        self.options.clone()
+    }
+}
+
+impl<S: LeaseStore> Handler for PoolHandler<S> {
+    fn handle_discover(&mut self, server: &Server, packet: Packet) {
+        let client = ClientId::of(&packet);
+        if let Some(ip) = self.store.allocate(&client) {
+            if self.rapid_commit && packet.wants_rapid_commit() &&
+               self.store.request(&client, ip).is_ok() {
+                let _ = server.reply(MessageType::Ack, self.reply_options(), ip.octets(), packet);
+                return;
+            }
+            let _ = server.reply(MessageType::Offer, self.reply_options(), ip.octets(), packet);
+        }
+    }
+
+    fn handle_request_msg(&mut self, server: &Server, packet: Packet) {
+        // A server identifier for another server means the client chose its offer. Without one
+        // the client is verifying or renewing a lease it already has.
+        if packet.option(options::SERVER_IDENTIFIER).is_some() && !server.for_this_server(&packet) {
+            return;
+        }
+        let ip = match packet.requested_ip() {
+            Some(ip) => ip,
+            None if packet.option(options::REQUESTED_IP_ADDRESS).is_some() => return,
+            None => Ipv4Addr::from(packet.ciaddr),
+        };
+        let selecting = packet.option(options::SERVER_IDENTIFIER).is_some();
+        match self.store.request(&ClientId::of(&packet), ip) {
+            Ok(()) => {
+                let _ = server.reply(MessageType::Ack, self.reply_options(), ip.octets(), packet);
+            }
+            // An address outside the store may be another server's to confirm, so with no
+            // record of the client stay silent (RFC 2131 section 4.3.2)
+            Err(LeaseError::OutOfRange) if !selecting => {}
+            Err(e) => {
+                let _ = server.nak(&e.to_string(), packet);
+            }
+        }
+    }
+
+    fn handle_release(&mut self, server: &Server, packet: Packet) {
+        if server.for_this_server(&packet) {
+            self.store.release(&ClientId::of(&packet));
+        }
+    }
+
+    fn handle_decline(&mut self, server: &Server, packet: Packet) {
+        if !server.for_this_server(&packet) {
+            return;
+        }
+        let client = ClientId::of(&packet);
+        match packet.requested_ip() {
+            Some(ip) => self.store.decline(&client, ip),
+            None => self.store.release(&client),
+        }
+    }
+
+    fn handle_inform(&mut self, server: &Server, packet: Packet) {
+        let _ = server.reply_inform(self.config_options(), packet);
+    }
+
+    fn lease_store(&mut self) -> Option<&mut dyn LeaseStore> {
+        Some(&mut self.store)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::PacketBuilder;
+    use std::net::{IpAddr, SocketAddr, UdpSocket};
+
+    const A: [u8; 6] = [1, 1, 1, 1, 1, 1];
+    const B: [u8; 6] = [2, 2, 2, 2, 2, 2];
+
+    #[test]
+    fn memory_store() {
+        let mut s = MemoryLeaseStore::new(Ipv4Addr::new(10, 0, 0, 10), 2, Duration::from_secs(60));
+        let a = s.allocate(&A.into()).unwrap();
+        s.request(&A.into(), a).unwrap();
+        assert_eq!(s.allocate(&A.into()), Some(a));
+        assert_eq!(s.lookup(&A.into()).map(|l| l.ip), Some(a));
+        assert_eq!(s.request(&B.into(), a), Err(LeaseError::Unavailable));
+        assert_eq!(s.request(&B.into(), Ipv4Addr::new(10, 0, 0, 12)), Err(LeaseError::OutOfRange));
+
+        let b = s.allocate(&B.into()).unwrap();
+        assert!(b != a);
+        s.request(&B.into(), b).unwrap();
+        assert_eq!(s.allocate(&[3; 6].into()), None);
+
+        s.release(&A.into());
+        assert_eq!(s.lookup(&A.into()), None);
+        assert_eq!(s.allocate(&[3; 6].into()), Some(a));
+    }
+
+    #[test]
+    fn declined_addresses() {
+        let mut s = MemoryLeaseStore::new(Ipv4Addr::new(10, 0, 0, 10), 2, Duration::from_secs(60));
+        let a = s.allocate(&A.into()).unwrap();
+        s.request(&A.into(), a).unwrap();
+        s.decline(&A.into(), a);
+        assert_eq!(s.lookup(&A.into()), None);
+
+        // Neither offered nor granted while held, to the decliner or anyone else
+        let b = s.allocate(&A.into()).unwrap();
+        assert!(b != a);
+        s.request(&A.into(), b).unwrap();
+        assert_eq!(s.allocate(&B.into()), None);
+        assert_eq!(s.request(&B.into(), a), Err(LeaseError::Unavailable));
+
+        // Free again once the hold runs out
+        s.sweep(Instant::now() + Duration::from_secs(61));
+        assert!(s.declined.is_empty());
+        assert_eq!(s.request(&B.into(), a), Ok(()));
+    }
+
+    #[test]
+    fn top_of_address_space() {
+        let start = Ipv4Addr::new(255, 255, 255, 250);
+        let mut s = MemoryLeaseStore::new(start, 10, Duration::from_secs(60));
+        let mut ips = Vec::new();
+        while let Some(ip) = s.allocate(&[ips.len() as u8; 6].into()) {
+            s.request(&[ips.len() as u8; 6].into(), ip).unwrap();
+            ips.push(ip);
+        }
+        assert_eq!(ips.len(), 6);
+        assert!(ips.contains(&Ipv4Addr::BROADCAST));
+        assert_eq!(s.request(&B.into(), Ipv4Addr::new(0, 0, 0, 3)), Err(LeaseError::OutOfRange));
+    }
+
+    /// Ends the lease on `ip` now, as if its time had run out.
+    fn expire(s: &mut MemoryLeaseStore, ip: Ipv4Addr) {
+        s.leases.get_mut(&u32::from(ip)).unwrap().expires = Some(Instant::now());
+    }
+
+    #[test]
+    fn expired_lease_contention() {
+        let mut s = MemoryLeaseStore::new(Ipv4Addr::new(10, 0, 0, 10), 2, Duration::from_secs(60));
+        let a = s.allocate(&A.into()).unwrap();
+        s.request(&A.into(), a).unwrap();
+        expire(&mut s, a);
+        assert_eq!(s.lookup(&A.into()), None);
+
+        // B is steered away from A's expired address while another is free
+        let b = s.allocate(&B.into()).unwrap();
+        assert!(b != a);
+        assert_eq!(s.allocate(&A.into()), Some(a));
+        s.request(&B.into(), b).unwrap();
+        expire(&mut s, b);
+
+        // With the pool exhausted a third client takes the lease that expired first, after which
+        // A no longer gets it back
+        let c = s.allocate(&[3; 6].into()).unwrap();
+        assert_eq!(c, a);
+        s.request(&[3; 6].into(), c).unwrap();
+        assert_eq!(s.request(&A.into(), a), Err(LeaseError::Unavailable));
+        assert_eq!(s.allocate(&A.into()), Some(b));
+    }
+
+    #[test]
+    fn sweep() {
+        let mut s = MemoryLeaseStore::new(Ipv4Addr::new(10, 0, 0, 10), 5, Duration::from_secs(60));
+        s.request(&A.into(), Ipv4Addr::new(10, 0, 0, 11)).unwrap();
+        s.request(&B.into(), Ipv4Addr::new(10, 0, 0, 12)).unwrap();
+        expire(&mut s, Ipv4Addr::new(10, 0, 0, 12));
+        assert_eq!(s.sweep(Instant::now() - Duration::from_secs(1)), vec![]);
+
+        let swept = s.sweep(Instant::now());
+        assert_eq!(swept.len(), 1);
+        assert_eq!((&swept[0].client, swept[0].ip), (&B.into(), Ipv4Addr::new(10, 0, 0, 12)));
+        assert!(s.recorded(&B.into()).is_none());
+        assert!(s.lookup(&A.into()).is_some());
+        assert_eq!(s.sweep(Instant::now()), vec![]);
+    }
+
+    #[test]
+    fn save_and_load() {
+        let path = std::env::temp_dir().join(format!("dhcp4r-leases-{}", std::process::id()));
+        let mut s = MemoryLeaseStore::new(Ipv4Addr::new(10, 0, 0, 10), 5, Duration::from_secs(60));
+        s.request(&A.into(), Ipv4Addr::new(10, 0, 0, 11)).unwrap();
+        s.request(&B.into(), Ipv4Addr::new(10, 0, 0, 12)).unwrap();
+        expire(&mut s, Ipv4Addr::new(10, 0, 0, 12));
+        s.save(&path).unwrap();
+
+        let mut r = MemoryLeaseStore::new(Ipv4Addr::new(10, 0, 0, 10), 5, Duration::from_secs(60));
+        r.load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let a = r.lookup(&A.into()).unwrap();
+        assert_eq!(a.ip, Ipv4Addr::new(10, 0, 0, 11));
+        assert!(a.expires.is_some_and(|e| e > Instant::now() + Duration::from_secs(50)));
+        assert_eq!(r.lookup(&B.into()), None);
+
+        let ip = Ipv4Addr::new(10, 0, 0, 1);
+        assert_eq!(parse_lease_line("01:02:03:04:05:06 10.0.0.1 5"),
+                   Some((ClientId::Hardware([1, 2, 3, 4, 5, 6]), ip, Some(5))));
+        assert_eq!(parse_lease_line("id:01ff 10.0.0.1 infinite"),
+                   Some((ClientId::Identifier(vec![1, 0xff]), ip, None)));
+        assert_eq!(parse_lease_line("01:02:03:04:05 10.0.0.1 5"), None);
+
+        // An expiry too far off to represent is as bad as a malformed one
+        fs::write(&path, format!("01:02:03:04:05:06 10.0.0.11 {}\n", u64::MAX)).unwrap();
+        let err = r.load(&path).unwrap_err();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn infinite_leases() {
+        let path = std::env::temp_dir().join(format!("dhcp4r-static-{}", std::process::id()));
+        let mut s = MemoryLeaseStore::new(Ipv4Addr::new(10, 0, 0, 10), 1, Duration::from_secs(60));
+        s.set_lease_time(LeaseTime::Infinite);
+        assert_eq!(s.lease_time(), LeaseTime::Infinite);
+        let a = s.allocate(&A.into()).unwrap();
+        s.request(&A.into(), a).unwrap();
+        let lease = s.lookup(&A.into()).unwrap();
+        assert_eq!(lease.expires, None);
+        assert_eq!(lease.remaining(Instant::now()), LeaseTime::Infinite);
+
+        // Never swept, and never handed to another client
+        let later = Instant::now() + Duration::from_secs(1 << 32);
+        assert!(lease.is_active(later));
+        assert_eq!(s.sweep(later), vec![]);
+        assert_eq!(s.allocate(&B.into()), None);
+        assert_eq!(s.request(&B.into(), a), Err(LeaseError::Unavailable));
+
+        s.save(&path).unwrap();
+        let mut r = MemoryLeaseStore::new(Ipv4Addr::new(10, 0, 0, 10), 1, Duration::from_secs(60));
+        r.load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(r.lookup(&A.into()), Some(lease));
+    }
+
+    #[test]
+    fn client_identifiers() {
+        let with_id = |id: &[u8]| {
+            PacketBuilder::new()
+                .chaddr(A)
+                .option(DhcpOption {
+                    code: options::CLIENT_IDENTIFIER,
+                    data: id.to_vec(),
+                })
+                .build()
+        };
+        let first = ClientId::of(&with_id(b"\x00vm-1"));
+        let second = ClientId::of(&with_id(b"\x00vm-2"));
+        assert_eq!(first, ClientId::Identifier(b"\x00vm-1".to_vec()));
+        assert_eq!(ClientId::of(&PacketBuilder::new().chaddr(A).build()), A.into());
+        assert_eq!(first.to_string(), "id:00766d2d31");
+        assert_eq!(ClientId::from(A).to_string(), "01:01:01:01:01:01");
+
+        // Clients sharing a hardware address get their own leases
+        let mut s = MemoryLeaseStore::new(Ipv4Addr::new(10, 0, 0, 10), 5, Duration::from_secs(60));
+        let a = s.allocate(&first).unwrap();
+        s.request(&first, a).unwrap();
+        let b = s.allocate(&second).unwrap();
+        assert!(b != a);
+        s.request(&second, b).unwrap();
+        assert_eq!(s.lookup(&first).map(|l| l.ip), Some(a));
+        assert_eq!(s.lookup(&second).map(|l| l.ip), Some(b));
+        assert_eq!(s.lookup(&A.into()), None);
+    }
+
+    #[test]
+    fn pool_handler() {
+        // Bound to every address so it also receives the NAK, which is broadcast
+        let client = UdpSocket::bind("0.0.0.0:0").unwrap();
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.set_broadcast(true).unwrap();
+        let mut server = Server::new(socket, [10, 0, 0, 1]);
+        server.src = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+        server.client_port = client.local_addr().unwrap().port();
+        let store = MemoryLeaseStore::new(Ipv4Addr::new(10, 0, 0, 10), 5, Duration::from_secs(60));
+        let mut h = PoolHandler::new(store,
+                                     vec![DhcpOption {
+                                              code: options::SUBNET_MASK,
+                                              data: vec![255, 255, 255, 0],
+                                          }]);
+        let mut buf = [0; 1500];
+
+        let discover = PacketBuilder::new().chaddr(A).message_type(MessageType::Discover).build();
+        h.handle_request_from(&server, discover, server.src);
+        let (l, _) = client.recv_from(&mut buf).unwrap();
+        let offer = Packet::from_bytes(&buf[..l]).unwrap();
+        assert!(matches!(offer.message_type(), Ok(MessageType::Offer)));
+        assert_eq!(offer.option(options::SUBNET_MASK), Some(&[255, 255, 255, 0][..]));
+
+        let request = PacketBuilder::new()
+            .chaddr(A)
+            .message_type(MessageType::Request)
+            .option(DhcpOption {
+                code: options::REQUESTED_IP_ADDRESS,
+                data: offer.yiaddr.to_vec(),
+            })
+            .option(DhcpOption {
+                code: options::SERVER_IDENTIFIER,
+                data: vec![10, 0, 0, 1],
+            })
+            .build();
+        h.handle_request_from(&server, request, server.src);
+        let (l, _) = client.recv_from(&mut buf).unwrap();
+        let ack = Packet::from_bytes(&buf[..l]).unwrap();
+        assert!(matches!(ack.message_type(), Ok(MessageType::Ack)));
+        assert_eq!(h.store.lookup(&A.into()).map(|l| l.ip.octets()), Some(offer.yiaddr));
+
+        let taken = PacketBuilder::new()
+            .chaddr(B)
+            .message_type(MessageType::Request)
+            .option(DhcpOption {
+                code: options::REQUESTED_IP_ADDRESS,
+                data: offer.yiaddr.to_vec(),
+            })
+            .build();
+        h.handle_request_from(&server, taken, server.src);
+        let (l, _) = client.recv_from(&mut buf).unwrap();
+        let nak = Packet::from_bytes(&buf[..l]).unwrap();
+        assert!(matches!(nak.message_type(), Ok(MessageType::Nak)));
+
+        // Rebooting with an address outside the store is left to the server that leased it
+        let elsewhere = PacketBuilder::new()
+            .chaddr(B)
+            .message_type(MessageType::Request)
+            .option(DhcpOption {
+                code: options::REQUESTED_IP_ADDRESS,
+                data: vec![192, 168, 5, 5],
+            })
+            .build();
+        h.handle_request_from(&server, elsewhere, server.src);
+        client.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+        assert!(client.recv_from(&mut buf).is_err());
+    }
+
+    #[test]
+    fn rapid_commit() {
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut server = Server::new(UdpSocket::bind("127.0.0.1:0").unwrap(), [10, 0, 0, 1]);
+        server.src = client.local_addr().unwrap();
+        server.client_port = server.src.port();
+        let store = MemoryLeaseStore::new(Ipv4Addr::new(10, 0, 0, 10), 5, Duration::from_secs(60));
+        let mut h = PoolHandler::new(store, Vec::new());
+        let mut buf = [0; 1500];
+        let discover = |chaddr| {
+            PacketBuilder::new()
+                .chaddr(chaddr)
+                .message_type(MessageType::Discover)
+                .option(DhcpOption {
+                    code: options::RAPID_COMMIT,
+                    data: Vec::new(),
+                })
+                .build()
+        };
+
+        h.handle_request_from(&server, discover(A), server.src);
+        let (l, _) = client.recv_from(&mut buf).unwrap();
+        let offer = Packet::from_bytes(&buf[..l]).unwrap();
+        assert!(matches!(offer.message_type(), Ok(MessageType::Offer)));
+        assert_eq!(offer.option(options::RAPID_COMMIT), None);
+
+        h.rapid_commit = true;
+        h.handle_request_from(&server, discover(B), server.src);
+        let (l, _) = client.recv_from(&mut buf).unwrap();
+        let ack = Packet::from_bytes(&buf[..l]).unwrap();
+        assert!(matches!(ack.message_type(), Ok(MessageType::Ack)));
+        assert_eq!(ack.option(options::RAPID_COMMIT), Some(&[][..]));
+        assert_eq!(h.store.lookup(&B.into()).map(|l| l.ip.octets()), Some(ack.yiaddr));
+    }
+}
@@ -0,0 +1,101 @@
+//! Linux packet socket plumbing shared by ARP probing and raw unicast replies.
+
+use std::io;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+use std::time::Duration;
+
+/// Reads an interface's hardware address from sysfs.
+#[cfg_attr(not(feature = "arp-probe"), allow(dead_code))]
+pub fn hardware_address(iface: &str) -> io::Result<[u8; 6]> {
+    let s = std::fs::read_to_string(format!("/sys/class/net/{}/address", iface))?;
+    let mut mac = [0; 6];
+    let mut parts = s.trim().split(':');
+    for b in &mut mac {
+        *b = parts.next()
+            .and_then(|p| u8::from_str_radix(p, 16).ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Invalid hardware address"))?;
+    }
+    Ok(mac)
+}
+
+pub fn interface_index(iface: &str) -> io::Result<u32> {
+    let name = std::ffi::CString::new(iface)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Invalid interface name"))?;
+    match unsafe { libc::if_nametoindex(name.as_ptr()) } {
+        0 => Err(io::Error::last_os_error()),
+        i => Ok(i),
+    }
+}
+
+/// AF_PACKET datagram socket for one EtherType; the kernel adds and strips the Ethernet
+/// header. Closed on drop.
+pub struct PacketSocket {
+    fd: OwnedFd,
+    protocol: u16,
+}
+
+impl PacketSocket {
+    pub fn open(protocol: u16) -> io::Result<PacketSocket> {
+        let fd = unsafe {
+            libc::socket(libc::AF_PACKET,
+                         libc::SOCK_DGRAM | libc::SOCK_CLOEXEC,
+                         protocol.to_be() as i32)
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(PacketSocket {
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+            protocol,
+        })
+    }
+
+    /// Sends `p` to the hardware address `dest` through the interface with index `ifindex`.
+    pub fn send_to(&self, ifindex: u32, dest: [u8; 6], p: &[u8]) -> io::Result<usize> {
+        let mut sll: libc::sockaddr_ll = unsafe { std::mem::zeroed() };
+        sll.sll_family = libc::AF_PACKET as u16;
+        sll.sll_protocol = self.protocol.to_be();
+        sll.sll_ifindex = ifindex as i32;
+        sll.sll_halen = 6;
+        sll.sll_addr[..6].copy_from_slice(&dest);
+        let r = unsafe {
+            libc::sendto(self.fd.as_raw_fd(),
+                         p.as_ptr() as *const libc::c_void,
+                         p.len(),
+                         0,
+                         &sll as *const libc::sockaddr_ll as *const libc::sockaddr,
+                         std::mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t)
+        };
+        if r < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(r as usize)
+    }
+
+    #[cfg_attr(not(feature = "arp-probe"), allow(dead_code))]
+    pub fn recv(&self, buf: &mut [u8], timeout: Duration) -> io::Result<usize> {
+        // A zero timeval would block forever
+        let timeout = timeout.max(Duration::from_micros(1));
+        let tv = libc::timeval {
+            tv_sec: timeout.as_secs() as libc::time_t,
+            tv_usec: timeout.subsec_micros() as libc::suseconds_t,
+        };
+        let r = unsafe {
+            libc::setsockopt(self.fd.as_raw_fd(),
+                             libc::SOL_SOCKET,
+                             libc::SO_RCVTIMEO,
+                             &tv as *const libc::timeval as *const libc::c_void,
+                             std::mem::size_of::<libc::timeval>() as libc::socklen_t)
+        };
+        if r < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let l = unsafe {
+            libc::recv(self.fd.as_raw_fd(), buf.as_mut_ptr() as *mut libc::c_void, buf.len(), 0)
+        };
+        if l < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(l as usize)
+    }
+}
@@ -0,0 +1,268 @@
+//! Address pools: which addresses a server may hand out.
+
+use std::collections::HashSet;
+use std::net::Ipv4Addr;
+
+use crate::packet::Packet;
+
+/// An inclusive range of addresses available for leasing, minus any exclusions.
+///
+/// ```
+/// use std::collections::HashSet;
+/// use std::net::Ipv4Addr;
+/// use dhcp4r::server::Pool;
+///
+/// let mut pool = Pool::new(Ipv4Addr::new(192, 168, 0, 100), Ipv4Addr::new(192, 168, 0, 199));
+/// pool.exclude(Ipv4Addr::new(192, 168, 0, 100));
+/// assert_eq!(pool.next_free(&HashSet::new()), Some(Ipv4Addr::new(192, 168, 0, 101)));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pool {
+    start: u32,
+    end: u32,
+    excluded: Vec<(u32, u32)>,
+    subnet_mask: Option<u32>,
+}
+
+impl Pool {
+    /// Creates a pool of the addresses from `start` to `end`, both included. The pool is empty
+    /// if `end` comes before `start`.
+    pub fn new(start: Ipv4Addr, end: Ipv4Addr) -> Pool {
+        Pool {
+            start: u32::from(start),
+            end: u32::from(end),
+            excluded: Vec::new(),
+            subnet_mask: None,
+        }
+    }
+
+    /// Removes the network and broadcast addresses of the subnet with this mask from the
+    /// pool. Has no effect for /31 and /32 subnets, which have neither.
+    pub fn set_subnet_mask(&mut self, mask: Ipv4Addr) {
+        self.subnet_mask = Some(u32::from(mask));
+    }
+
+    /// Removes one address from the pool, e.g. one assigned statically.
+    pub fn exclude(&mut self, ip: Ipv4Addr) {
+        self.exclude_range(ip, ip);
+    }
+
+    /// Removes the addresses from `a` to `b`, both included, from the pool.
+    pub fn exclude_range(&mut self, a: Ipv4Addr, b: Ipv4Addr) {
+        self.excluded.push((u32::from(a), u32::from(b)));
+    }
+
+    /// Whether `ip` is one the pool hands out.
+    pub fn contains(&self, ip: Ipv4Addr) -> bool {
+        let n = u32::from(ip);
+        if n < self.start || n > self.end {
+            return false;
+        }
+        if self.excluded.iter().any(|&(a, b)| a <= n && n <= b) {
+            return false;
+        }
+        match self.subnet_mask {
+            Some(mask) if !mask > 1 => {
+                let host = n & !mask;
+                host != 0 && host != !mask
+            }
+            _ => true,
+        }
+    }
+
+    /// Returns the lowest address in the pool that is not in `taken`.
+    pub fn next_free(&self, taken: &HashSet<Ipv4Addr>) -> Option<Ipv4Addr> {
+        if self.start > self.end {
+            return None;
+        }
+        (self.start..=self.end)
+            .map(Ipv4Addr::from)
+            .find(|&ip| self.contains(ip) && !taken.contains(&ip))
+    }
+}
+
+/// Pools for several subnets, such as VLANs whose relays forward to one server. A request is
+/// served from the pool of the subnet containing its giaddr, or, if it was not relayed, the
+/// subnet containing the local address set with `set_local_address`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PoolSet {
+    subnets: Vec<(u32, u32, Pool)>,
+    local: Option<Ipv4Addr>,
+}
+
+impl PoolSet {
+    pub fn new() -> PoolSet {
+        PoolSet::default()
+    }
+
+    /// Adds the pool serving the subnet `network`/`prefix_len`. Where subnets overlap, the one
+    /// with the longest prefix is used.
+    ///
+    /// # Panics
+    ///
+    /// If `prefix_len` is greater than 32.
+    pub fn insert(&mut self, network: Ipv4Addr, prefix_len: u8, pool: Pool) {
+        assert!(prefix_len <= 32, "Invalid prefix length {}", prefix_len);
+        let mask = u32::MAX.checked_shl(32 - u32::from(prefix_len)).unwrap_or(0);
+        self.subnets.push((u32::from(network) & mask, mask, pool));
+    }
+
+    /// Sets the address of the interface receiving requests directly from clients.
+    pub fn set_local_address(&mut self, ip: Ipv4Addr) {
+        self.local = Some(ip);
+    }
+
+    /// Returns the pool for the subnet containing `ip`.
+    pub fn pool_for_addr(&self, ip: Ipv4Addr) -> Option<&Pool> {
+        self.subnet_for_addr(ip).map(|(_, _, pool)| pool)
+    }
+
+    /// Returns the pool to serve `packet` from, chosen by its giaddr or, if zero, the local
+    /// address.
+    pub fn pool_for(&self, packet: &Packet) -> Option<&Pool> {
+        self.subnet_for(packet).map(|(_, _, pool)| pool)
+    }
+
+    /// Whether a DHCPREQUEST must be answered with a DHCPNAK because the address it asks for
+    /// (REQUESTED_IP_ADDRESS, or ciaddr when renewing or rebinding) is not on the subnet the
+    /// client is now attached to, as when a laptop moves between networks (RFC 2131 section
+    /// 4.3.2).
+    ///
+    /// Returns false when the client is selecting an offer, which carries a SERVER_IDENTIFIER,
+    /// and when the subnet is not one in the set, since a server must then stay silent rather
+    /// than NAK. Whether an address on the right subnet is still leased to the client is for
+    /// the lease store to decide.
+    pub fn should_nak(&self, packet: &Packet) -> bool {
+        if packet.server_identifier().is_some() {
+            return false;
+        }
+        let ip = match packet.requested_ip() {
+            Some(ip) => ip,
+            None if packet.ciaddr != [0; 4] => Ipv4Addr::from(packet.ciaddr),
+            None => return false,
+        };
+        match self.subnet_for(packet) {
+            Some(&(network, mask, _)) => u32::from(ip) & mask != network,
+            None => false,
+        }
+    }
+
+    fn subnet_for(&self, packet: &Packet) -> Option<&(u32, u32, Pool)> {
+        let giaddr = Ipv4Addr::from(packet.giaddr);
+        if !giaddr.is_unspecified() {
+            return self.subnet_for_addr(giaddr);
+        }
+        self.local.and_then(|ip| self.subnet_for_addr(ip))
+    }
+
+    fn subnet_for_addr(&self, ip: Ipv4Addr) -> Option<&(u32, u32, Pool)> {
+        let n = u32::from(ip);
+        self.subnets
+            .iter()
+            .filter(|&&(network, mask, _)| n & mask == network)
+            .max_by_key(|&&(_, mask, _)| mask.count_ones())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(last: u8) -> Ipv4Addr {
+        Ipv4Addr::new(10, 0, 0, last)
+    }
+
+    #[test]
+    fn exclusions() {
+        let mut pool = Pool::new(ip(10), ip(20));
+        pool.exclude(ip(10));
+        pool.exclude_range(ip(12), ip(14));
+        assert!(!pool.contains(ip(9)));
+        assert!(!pool.contains(ip(10)));
+        assert!(pool.contains(ip(11)));
+        assert!(!pool.contains(ip(13)));
+        assert!(pool.contains(ip(20)));
+        assert!(!pool.contains(ip(21)));
+
+        let mut taken = HashSet::new();
+        assert_eq!(pool.next_free(&taken), Some(ip(11)));
+        taken.insert(ip(11));
+        assert_eq!(pool.next_free(&taken), Some(ip(15)));
+        taken.extend((15..=20).map(ip));
+        assert_eq!(pool.next_free(&taken), None);
+        assert_eq!(Pool::new(ip(20), ip(10)).next_free(&HashSet::new()), None);
+    }
+
+    #[test]
+    fn network_and_broadcast() {
+        let mut pool = Pool::new(ip(0), ip(255));
+        pool.set_subnet_mask(Ipv4Addr::new(255, 255, 255, 0));
+        assert!(!pool.contains(ip(0)));
+        assert!(!pool.contains(ip(255)));
+        assert_eq!(pool.next_free(&HashSet::new()), Some(ip(1)));
+
+        let mut pool = Pool::new(ip(0), ip(1));
+        pool.set_subnet_mask(Ipv4Addr::new(255, 255, 255, 254));
+        assert!(pool.contains(ip(0)));
+        assert!(pool.contains(ip(1)));
+    }
+
+    #[test]
+    fn pool_selection() {
+        use crate::packet::PacketBuilder;
+
+        let vlan = |n| {
+            Pool::new(Ipv4Addr::new(10, n, 0, 10), Ipv4Addr::new(10, n, 0, 99))
+        };
+        let mut set = PoolSet::new();
+        set.insert(Ipv4Addr::new(10, 1, 0, 0), 24, vlan(1));
+        set.insert(Ipv4Addr::new(10, 2, 0, 0), 24, vlan(2));
+        set.insert(Ipv4Addr::new(10, 0, 0, 0), 8, vlan(0));
+
+        let relayed = PacketBuilder::new().giaddr(Ipv4Addr::new(10, 2, 0, 1)).build();
+        assert_eq!(set.pool_for(&relayed), Some(&vlan(2)));
+        let relayed = PacketBuilder::new().giaddr(Ipv4Addr::new(10, 9, 0, 1)).build();
+        assert_eq!(set.pool_for(&relayed), Some(&vlan(0)));
+        let relayed = PacketBuilder::new().giaddr(Ipv4Addr::new(192, 168, 0, 1)).build();
+        assert_eq!(set.pool_for(&relayed), None);
+
+        let direct = PacketBuilder::new().build();
+        assert_eq!(set.pool_for(&direct), None);
+        set.set_local_address(Ipv4Addr::new(10, 1, 0, 1));
+        assert_eq!(set.pool_for(&direct), Some(&vlan(1)));
+    }
+
+    #[test]
+    fn nak_decisions() {
+        use crate::options::{DhcpOption, REQUESTED_IP_ADDRESS, SERVER_IDENTIFIER};
+        use crate::packet::PacketBuilder;
+
+        let mut set = PoolSet::new();
+        set.insert(Ipv4Addr::new(10, 1, 0, 0), 24, Pool::new(ip(0), ip(0)));
+        let relay = Ipv4Addr::new(10, 1, 0, 1);
+        let requesting = |addr: Ipv4Addr| {
+            PacketBuilder::new().giaddr(relay).option(DhcpOption {
+                code: REQUESTED_IP_ADDRESS,
+                data: addr.octets().to_vec(),
+            })
+        };
+
+        // Rebooting with an address from another network
+        assert!(set.should_nak(&requesting(Ipv4Addr::new(10, 2, 0, 7)).build()));
+        assert!(!set.should_nak(&requesting(Ipv4Addr::new(10, 1, 0, 7)).build()));
+        // Renewing or rebinding
+        let renewing = PacketBuilder::new().giaddr(relay).ciaddr(Ipv4Addr::new(10, 2, 0, 7));
+        assert!(set.should_nak(&renewing.build()));
+        // Selecting another server's offer
+        let selecting = requesting(Ipv4Addr::new(10, 2, 0, 7)).option(DhcpOption {
+            code: SERVER_IDENTIFIER,
+            data: vec![10, 2, 0, 1],
+        });
+        assert!(!set.should_nak(&selecting.build()));
+        // A network this server knows nothing about
+        let unknown = PacketBuilder::new()
+            .giaddr(Ipv4Addr::new(10, 3, 0, 1))
+            .ciaddr(Ipv4Addr::new(10, 2, 0, 7));
+        assert!(!set.should_nak(&unknown.build()));
+    }
+}
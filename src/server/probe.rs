@@ -0,0 +1,136 @@
+//! ARP conflict detection, so a handler can check an address is unused before offering it
+//! (RFC 2131 section 2.2).
+
+use std::io;
+use std::net::Ipv4Addr;
+use std::time::Duration;
+
+/// Sends an ARP probe (RFC 5227) for `ip` on the local interface whose subnet contains it and
+/// waits up to `timeout` for a reply. Returns `true` if a host answered, meaning the address is
+/// in use and should not be offered.
+///
+/// Uses a packet socket, so the process needs CAP_NET_RAW. Only available on Linux; elsewhere an
+/// `Unsupported` error is returned.
+#[cfg(target_os = "linux")]
+pub fn probe_ip(ip: Ipv4Addr, timeout: Duration) -> io::Result<bool> {
+    use std::time::Instant;
+
+    use super::link::{hardware_address, interface_index, PacketSocket};
+
+    let iface = interface_for(ip)?;
+    let mac = hardware_address(&iface)?;
+    let index = interface_index(&iface)?;
+
+    let socket = PacketSocket::open(libc::ETH_P_ARP as u16)?;
+    socket.send_to(index, [0xff; 6], &arp_probe(mac, ip))?;
+
+    let deadline = Instant::now() + timeout;
+    let mut buf = [0; 64];
+    loop {
+        let now = Instant::now();
+        if now >= deadline {
+            return Ok(false);
+        }
+        let l = match socket.recv(&mut buf, deadline - now) {
+            Ok(l) => l,
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock ||
+                          e.kind() == io::ErrorKind::TimedOut => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        if is_reply_from(&buf[..l], ip) {
+            return Ok(true);
+        }
+    }
+}
+
+/// Sends an ARP probe for `ip` and reports whether it is in use.
+///
+/// Only available on Linux; on this platform an `Unsupported` error is always returned.
+#[cfg(not(target_os = "linux"))]
+pub fn probe_ip(_ip: Ipv4Addr, _timeout: Duration) -> io::Result<bool> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "ARP probing is only supported on Linux"))
+}
+
+const ARP_REQUEST: u16 = 1;
+const ARP_REPLY: u16 = 2;
+
+/// Builds an ARP request with a zero sender address, as RFC 5227 specifies for probes so that
+/// neighbours' caches are left alone.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn arp_probe(mac: [u8; 6], ip: Ipv4Addr) -> [u8; 28] {
+    let mut p = [0; 28];
+    p[..8].copy_from_slice(&[0, 1, 8, 0, 6, 4, 0, ARP_REQUEST as u8]);
+    p[8..14].copy_from_slice(&mac);
+    // Sender IP (14..18) and target hardware address (18..24) stay zero
+    p[24..28].copy_from_slice(&ip.octets());
+    p
+}
+
+/// Whether `p` is an Ethernet/IPv4 ARP reply sent by the holder of `ip`.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn is_reply_from(p: &[u8], ip: Ipv4Addr) -> bool {
+    p.len() >= 28 && p[..6] == [0, 1, 8, 0, 6, 4] &&
+    u16::from_be_bytes([p[6], p[7]]) == ARP_REPLY && p[14..18] == ip.octets()
+}
+
+/// Finds the interface with an IPv4 address on the same subnet as `ip`.
+#[cfg(target_os = "linux")]
+fn interface_for(ip: Ipv4Addr) -> io::Result<String> {
+    let mut addrs: *mut libc::ifaddrs = std::ptr::null_mut();
+    if unsafe { libc::getifaddrs(&mut addrs) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut found = None;
+    let mut a = addrs;
+    while !a.is_null() {
+        let ifa = unsafe { &*a };
+        a = ifa.ifa_next;
+        if ifa.ifa_addr.is_null() || ifa.ifa_netmask.is_null() ||
+           i32::from(unsafe { (*ifa.ifa_addr).sa_family }) != libc::AF_INET {
+            continue;
+        }
+        let addr = unsafe { &*(ifa.ifa_addr as *const libc::sockaddr_in) };
+        let mask = unsafe { &*(ifa.ifa_netmask as *const libc::sockaddr_in) };
+        let mask = u32::from_be(mask.sin_addr.s_addr);
+        if u32::from_be(addr.sin_addr.s_addr) & mask == u32::from(ip) & mask {
+            let name = unsafe { std::ffi::CStr::from_ptr(ifa.ifa_name) };
+            found = Some(name.to_string_lossy().into_owned());
+            break;
+        }
+    }
+    unsafe { libc::freeifaddrs(addrs) };
+    found.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, "No interface on the address's subnet")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arp_frames() {
+        let ip = Ipv4Addr::new(192, 168, 0, 9);
+        let p = arp_probe([1, 2, 3, 4, 5, 6], ip);
+        assert_eq!(&p[..8], &[0, 1, 8, 0, 6, 4, 0, 1]);
+        assert_eq!(&p[14..18], &[0, 0, 0, 0]);
+        assert_eq!(&p[24..28], &[192, 168, 0, 9]);
+        // A probe is not an answer
+        assert!(!is_reply_from(&p, ip));
+
+        let mut reply = p;
+        reply[7] = ARP_REPLY as u8;
+        reply[14..18].copy_from_slice(&ip.octets());
+        assert!(is_reply_from(&reply, ip));
+        assert!(!is_reply_from(&reply, Ipv4Addr::new(192, 168, 0, 10)));
+        assert!(!is_reply_from(&reply[..20], ip));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn probe_without_interface() {
+        // TEST-NET-3 is not configured on any interface of a test machine
+        let r = probe_ip(Ipv4Addr::new(203, 0, 113, 1), Duration::from_millis(10));
+        assert_eq!(r.unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+}
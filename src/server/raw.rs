@@ -0,0 +1,168 @@
+//! Unicasting replies to clients that cannot receive broadcasts and have no address yet, by
+//! writing the IP and UDP headers ourselves and addressing the frame to chaddr
+//! (RFC 2131 section 4.1).
+
+use std::io;
+use std::net::SocketAddrV4;
+
+use crate::options::MessageType;
+use crate::packet::Packet;
+
+/// Whether RFC 2131 asks for `p` to be unicast to its yiaddr and chaddr, which a `UdpSocket`
+/// cannot do because the client cannot answer ARP yet. NAKs are always broadcast.
+pub fn needs_raw_unicast(p: &Packet) -> bool {
+    p.giaddr == [0; 4] && p.ciaddr == [0; 4] && !p.is_broadcast() && p.yiaddr != [0; 4] &&
+    !matches!(p.message_type(), Ok(MessageType::Nak))
+}
+
+/// Wraps `payload` in IPv4 and UDP headers, with both checksums filled in.
+pub fn udp_datagram(src: SocketAddrV4, dst: SocketAddrV4, payload: &[u8]) -> Vec<u8> {
+    let udp_len = 8 + payload.len();
+    let total_len = 20 + udp_len;
+    let mut d = Vec::with_capacity(total_len);
+    // Version 4, 5 word header, no TOS, no fragmentation, TTL 64, UDP
+    d.extend_from_slice(&[0x45, 0]);
+    d.extend_from_slice(&(total_len as u16).to_be_bytes());
+    d.extend_from_slice(&[0, 0, 0x40, 0, 64, 17, 0, 0]);
+    d.extend_from_slice(&src.ip().octets());
+    d.extend_from_slice(&dst.ip().octets());
+    let ip_sum = checksum(&[&d[..20]]);
+    d[10..12].copy_from_slice(&ip_sum.to_be_bytes());
+
+    d.extend_from_slice(&src.port().to_be_bytes());
+    d.extend_from_slice(&dst.port().to_be_bytes());
+    d.extend_from_slice(&(udp_len as u16).to_be_bytes());
+    d.extend_from_slice(&[0, 0]);
+    d.extend_from_slice(payload);
+    let mut pseudo = [0; 12];
+    pseudo[..4].copy_from_slice(&src.ip().octets());
+    pseudo[4..8].copy_from_slice(&dst.ip().octets());
+    pseudo[9] = 17;
+    pseudo[10..].copy_from_slice(&(udp_len as u16).to_be_bytes());
+    let udp_sum = match checksum(&[&pseudo, &d[20..]]) {
+        // Zero means no checksum in UDP over IPv4
+        0 => 0xffff,
+        s => s,
+    };
+    d[26..28].copy_from_slice(&udp_sum.to_be_bytes());
+    d
+}
+
+/// Internet checksum (RFC 1071) over the concatenation of `parts`, each of even length except
+/// possibly the last.
+fn checksum(parts: &[&[u8]]) -> u16 {
+    let mut sum: u32 = 0;
+    for part in parts {
+        for c in part.chunks(2) {
+            let word = if c.len() == 2 {
+                u16::from_be_bytes([c[0], c[1]])
+            } else {
+                u16::from_be_bytes([c[0], 0])
+            };
+            sum += word as u32;
+        }
+    }
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Packet socket bound to one interface for sending unicast replies.
+#[cfg(target_os = "linux")]
+pub struct RawUnicast {
+    socket: super::link::PacketSocket,
+    ifindex: u32,
+}
+
+#[cfg(target_os = "linux")]
+impl RawUnicast {
+    pub fn open(iface: &str) -> io::Result<RawUnicast> {
+        Ok(RawUnicast {
+            socket: super::link::PacketSocket::open(libc::ETH_P_IP as u16)?,
+            ifindex: super::link::interface_index(iface)?,
+        })
+    }
+
+    /// Sends the encoded DHCP packet to `dst` at hardware address `mac`, returning the payload
+    /// length.
+    pub fn send(&self,
+                mac: [u8; 6],
+                src: SocketAddrV4,
+                dst: SocketAddrV4,
+                payload: &[u8])
+                -> io::Result<usize> {
+        self.socket.send_to(self.ifindex, mac, &udp_datagram(src, dst, payload))?;
+        Ok(payload.len())
+    }
+}
+
+/// Stand-in that can never be constructed on platforms without packet sockets.
+#[cfg(not(target_os = "linux"))]
+pub enum RawUnicast {}
+
+#[cfg(not(target_os = "linux"))]
+impl RawUnicast {
+    pub fn open(_iface: &str) -> io::Result<RawUnicast> {
+        Err(io::Error::new(io::ErrorKind::Unsupported,
+                           "Packet sockets are only supported on Linux"))
+    }
+
+    pub fn send(&self,
+                _mac: [u8; 6],
+                _src: SocketAddrV4,
+                _dst: SocketAddrV4,
+                _payload: &[u8])
+                -> io::Result<usize> {
+        match *self {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::PacketBuilder;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn unicast_selection() {
+        let offer = PacketBuilder::new()
+            .yiaddr(Ipv4Addr::new(10, 0, 0, 5))
+            .message_type(MessageType::Offer)
+            .build();
+        assert!(needs_raw_unicast(&offer));
+
+        let mut p = offer;
+        p.set_broadcast(true);
+        assert!(!needs_raw_unicast(&p));
+        let nak = PacketBuilder::new()
+            .yiaddr(Ipv4Addr::new(10, 0, 0, 5))
+            .message_type(MessageType::Nak)
+            .build();
+        assert!(!needs_raw_unicast(&nak));
+        let relayed = PacketBuilder::new()
+            .yiaddr(Ipv4Addr::new(10, 0, 0, 5))
+            .giaddr(Ipv4Addr::new(10, 0, 0, 254))
+            .build();
+        assert!(!needs_raw_unicast(&relayed));
+    }
+
+    #[test]
+    fn datagram_headers() {
+        let src = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 67);
+        let dst = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 5), 68);
+        let d = udp_datagram(src, dst, b"abc");
+        assert_eq!(d.len(), 31);
+        assert_eq!(&d[..4], &[0x45, 0, 0, 31]);
+        assert_eq!(&d[12..20], &[10, 0, 0, 1, 10, 0, 0, 5]);
+        assert_eq!(&d[20..26], &[0, 67, 0, 68, 0, 11]);
+        assert_eq!(&d[28..], b"abc");
+        // A correct checksum makes the header sum to zero
+        assert_eq!(checksum(&[&d[..20]]), 0);
+        let mut pseudo = [0; 12];
+        pseudo[..8].copy_from_slice(&d[12..20]);
+        pseudo[9] = 17;
+        pseudo[11] = 11;
+        assert_eq!(checksum(&[&pseudo, &d[20..]]), 0);
+    }
+}
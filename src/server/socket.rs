@@ -0,0 +1,211 @@
+//! Socket setup helpers for servers on multihomed hosts.
+
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+
+/// Where a packet was received, passed to `Handler::handle_request_with_context`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestContext {
+    /// The sender, which for a relayed request is the relay.
+    pub src: SocketAddr,
+    /// Index of the interface the packet arrived on, as from `if_nametoindex`. Known on Linux
+    /// only, where it is read from IP_PKTINFO.
+    pub interface: Option<u32>,
+    /// The address the packet was sent to, such as 255.255.255.255 for a broadcast. Known on
+    /// Linux only, like `interface`.
+    pub dst: Option<Ipv4Addr>,
+    /// The Ethernet source address of the frame carrying the packet, known only to transports
+    /// that receive whole frames, such as one reading a Linux packet socket. Always `None` from
+    /// a `UdpSocket`, as the kernel strips the link layer header. See
+    /// `ServerBuilder::verify_chaddr`.
+    pub src_mac: Option<[u8; 6]>,
+}
+
+/// Asks for IP_PKTINFO on packets received by `socket`, returning whether the platform
+/// supports it.
+#[cfg(target_os = "linux")]
+pub(super) fn enable_pktinfo(socket: &UdpSocket) -> bool {
+    use std::os::unix::io::AsRawFd;
+
+    let on: libc::c_int = 1;
+    let r = unsafe {
+        libc::setsockopt(socket.as_raw_fd(),
+                         libc::IPPROTO_IP,
+                         libc::IP_PKTINFO,
+                         &on as *const libc::c_int as *const libc::c_void,
+                         std::mem::size_of::<libc::c_int>() as libc::socklen_t)
+    };
+    r == 0
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(super) fn enable_pktinfo(_socket: &UdpSocket) -> bool {
+    false
+}
+
+/// Receives a packet like `recv_from`, with the interface and destination address as well
+/// when `pktinfo` was enabled with `enable_pktinfo`.
+#[cfg(target_os = "linux")]
+pub(super) fn recv_with_context(socket: &UdpSocket,
+                                buf: &mut [u8],
+                                pktinfo: bool)
+                                -> io::Result<(usize, RequestContext)> {
+    use std::net::SocketAddrV4;
+    use std::os::unix::io::AsRawFd;
+
+    if !pktinfo {
+        return recv_without_context(socket, buf);
+    }
+    let mut src: libc::sockaddr_in = unsafe { std::mem::zeroed() };
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+    // Aligned for the cmsghdr, with room for one in_pktinfo
+    let mut control = [0u64; 8];
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_name = &mut src as *mut libc::sockaddr_in as *mut libc::c_void;
+    msg.msg_namelen = std::mem::size_of::<libc::sockaddr_in>() as libc::socklen_t;
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = std::mem::size_of_val(&control) as _;
+
+    let n = unsafe { libc::recvmsg(socket.as_raw_fd(), &mut msg, 0) };
+    if n < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut ctx = RequestContext {
+        src: SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::from(u32::from_be(src.sin_addr.s_addr)),
+                                              u16::from_be(src.sin_port))),
+        interface: None,
+        dst: None,
+        src_mac: None,
+    };
+    let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(&msg) };
+    while !cmsg.is_null() {
+        let c = unsafe { &*cmsg };
+        if c.cmsg_level == libc::IPPROTO_IP && c.cmsg_type == libc::IP_PKTINFO {
+            let info = unsafe {
+                std::ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const libc::in_pktinfo)
+            };
+            ctx.interface = Some(info.ipi_ifindex as u32);
+            ctx.dst = Some(Ipv4Addr::from(u32::from_be(info.ipi_addr.s_addr)));
+        }
+        cmsg = unsafe { libc::CMSG_NXTHDR(&msg, cmsg) };
+    }
+    Ok((n as usize, ctx))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(super) fn recv_with_context(socket: &UdpSocket,
+                                buf: &mut [u8],
+                                _pktinfo: bool)
+                                -> io::Result<(usize, RequestContext)> {
+    recv_without_context(socket, buf)
+}
+
+fn recv_without_context(socket: &UdpSocket,
+                        buf: &mut [u8])
+                        -> io::Result<(usize, RequestContext)> {
+    let (n, src) = socket.recv_from(buf)?;
+    Ok((n,
+        RequestContext {
+            src,
+            interface: None,
+            dst: None,
+            src_mac: None,
+        }))
+}
+
+fn first_v4<A: ToSocketAddrs>(addr: A) -> io::Result<std::net::SocketAddrV4> {
+    for a in addr.to_socket_addrs()? {
+        if let SocketAddr::V4(a) = a {
+            return Ok(a);
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::InvalidInput, "No IPv4 address to bind to"))
+}
+
+/// Binds a UDP socket that only sends and receives on the named interface, using
+/// SO_BINDTODEVICE. Typically called with `("0.0.0.0", 67)` and followed by `set_broadcast(true)`.
+///
+/// Only available on Linux; elsewhere an `Unsupported` error is returned. Older kernels
+/// (before 5.7) require CAP_NET_RAW to bind to a device.
+#[cfg(target_os = "linux")]
+pub fn bind_to_device<A: ToSocketAddrs>(addr: A, iface: &str) -> io::Result<UdpSocket> {
+    use std::os::unix::io::{AsRawFd, FromRawFd};
+
+    let addr = first_v4(addr)?;
+    let fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // Owns the descriptor from here on, so it is closed on any error below
+    let socket = unsafe { UdpSocket::from_raw_fd(fd) };
+
+    let r = unsafe {
+        libc::setsockopt(socket.as_raw_fd(),
+                         libc::SOL_SOCKET,
+                         libc::SO_BINDTODEVICE,
+                         iface.as_ptr() as *const libc::c_void,
+                         iface.len() as libc::socklen_t)
+    };
+    if r < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let sin = libc::sockaddr_in {
+        sin_family: libc::AF_INET as libc::sa_family_t,
+        sin_port: addr.port().to_be(),
+        sin_addr: libc::in_addr { s_addr: u32::from(*addr.ip()).to_be() },
+        sin_zero: [0; 8],
+    };
+    let r = unsafe {
+        libc::bind(socket.as_raw_fd(),
+                   &sin as *const libc::sockaddr_in as *const libc::sockaddr,
+                   std::mem::size_of::<libc::sockaddr_in>() as libc::socklen_t)
+    };
+    if r < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(socket)
+}
+
+/// Binds a UDP socket that only sends and receives on the named interface, using
+/// SO_BINDTODEVICE.
+///
+/// Only available on Linux; on this platform an `Unsupported` error is always returned.
+#[cfg(not(target_os = "linux"))]
+pub fn bind_to_device<A: ToSocketAddrs>(addr: A, _iface: &str) -> io::Result<UdpSocket> {
+    first_v4(addr)?;
+    Err(io::Error::new(io::ErrorKind::Unsupported, "SO_BINDTODEVICE is only supported on Linux"))
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pktinfo() {
+        let s = UdpSocket::bind("127.0.0.1:0").unwrap();
+        assert!(enable_pktinfo(&s));
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client.send_to(b"hello", s.local_addr().unwrap()).unwrap();
+
+        let mut buf = [0; 16];
+        let (n, ctx) = recv_with_context(&s, &mut buf, true).unwrap();
+        assert_eq!(&buf[..n], b"hello");
+        assert_eq!(ctx.src, client.local_addr().unwrap());
+        let lo = unsafe { libc::if_nametoindex(b"lo\0".as_ptr() as *const libc::c_char) };
+        assert_eq!(ctx.interface, Some(lo));
+        assert_eq!(ctx.dst, Some(Ipv4Addr::LOCALHOST));
+    }
+
+    #[test]
+    fn bind_loopback() {
+        let s = bind_to_device("127.0.0.1:0", "lo").unwrap();
+        assert!(s.local_addr().unwrap().port() != 0);
+        assert!(bind_to_device("127.0.0.1:0", "no-such-if0").is_err());
+    }
+}
@@ -0,0 +1,154 @@
+//! What a `Server` receives requests from and sends replies through.
+
+use std::collections::VecDeque;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use super::socket::{self, RequestContext};
+use super::{SERVER_PORT, SHUTDOWN_POLL_INTERVAL};
+
+/// Carries packets for a `Server`: a `UdpSocket` normally, or a `MockTransport` in tests.
+pub trait Transport {
+    /// Called once before serving, with the timeout set by `ServerBuilder::read_timeout`.
+    /// Afterwards `recv_request` must fail with `WouldBlock` or `TimedOut` when no packet
+    /// arrives for about that long, or half a second without one, so that the server can call
+    /// `Handler::tick` and check the flag given to `serve_until`. Does nothing by default.
+    fn prepare(&self, _read_timeout: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Receives one packet into `buf`, returning its length and where it came from.
+    fn recv_request(&self, buf: &mut [u8]) -> io::Result<(usize, RequestContext)>;
+
+    /// Sends one encoded packet to `dst`.
+    fn send_reply(&self, buf: &[u8], dst: SocketAddr) -> io::Result<usize>;
+
+    /// The address packets are received on.
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+}
+
+/// Sets the read timeout, keeping the socket's own when none is given unless it has none, and
+/// asks for IP_PKTINFO so that the `RequestContext` has the interface and destination.
+impl Transport for UdpSocket {
+    fn prepare(&self, read_timeout: Option<Duration>) -> io::Result<()> {
+        if read_timeout.is_some() {
+            self.set_read_timeout(read_timeout)?;
+        } else if self.read_timeout()?.is_none() {
+            self.set_read_timeout(Some(SHUTDOWN_POLL_INTERVAL))?;
+        }
+        socket::enable_pktinfo(self);
+        Ok(())
+    }
+
+    fn recv_request(&self, buf: &mut [u8]) -> io::Result<(usize, RequestContext)> {
+        let pktinfo = matches!(UdpSocket::local_addr(self), Ok(SocketAddr::V4(_)));
+        socket::recv_with_context(self, buf, pktinfo)
+    }
+
+    fn send_reply(&self, buf: &[u8], dst: SocketAddr) -> io::Result<usize> {
+        self.send_to(buf, dst)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        UdpSocket::local_addr(self)
+    }
+}
+
+/// A `Transport` without sockets, for testing handlers: it hands the server the requests
+/// pushed to it, in order, and records the replies. Clones share the same queues, so a test
+/// keeps one clone to inspect while the server owns another.
+///
+/// Once every request has been received, `recv_request` fails with `UnexpectedEof`, which
+/// stops a server whose handler keeps the default `Handler::on_error`.
+///
+/// ```
+/// use std::net::SocketAddr;
+/// use dhcp4r::options::MessageType;
+/// use dhcp4r::packet::{Packet, PacketBuilder};
+/// use dhcp4r::server::{Handler, MockTransport, Server};
+///
+/// struct Offerer;
+///
+/// impl Handler for Offerer {
+///     fn handle_discover(&mut self, server: &Server, packet: Packet) {
+///         let _ = server.reply(MessageType::Offer, vec![], [10, 0, 0, 5], packet);
+///     }
+/// }
+///
+/// let mock = MockTransport::new();
+/// let discover = PacketBuilder::new().xid(1).message_type(MessageType::Discover).build();
+/// mock.push_request(&discover.to_bytes(), "0.0.0.0:68".parse().unwrap());
+/// Server::builder().server_ip([10, 0, 0, 1]).serve(mock.clone(), Offerer);
+///
+/// let replies = mock.take_replies();
+/// let offer = Packet::from_bytes(&replies[0].0).unwrap();
+/// assert!(matches!(offer.message_type(), Ok(MessageType::Offer)));
+/// assert_eq!(replies[0].1, "255.255.255.255:68".parse::<SocketAddr>().unwrap());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct MockTransport {
+    queues: Arc<Mutex<Queues>>,
+}
+
+#[derive(Debug, Default)]
+struct Queues {
+    requests: VecDeque<(Vec<u8>, RequestContext)>,
+    replies: Vec<(Vec<u8>, SocketAddr)>,
+}
+
+impl MockTransport {
+    pub fn new() -> MockTransport {
+        MockTransport::default()
+    }
+
+    /// Queues a request as if received from `src`, on an unknown interface.
+    pub fn push_request(&self, bytes: &[u8], src: SocketAddr) {
+        self.push_request_with_context(bytes,
+                                       RequestContext {
+                                           src,
+                                           interface: None,
+                                           dst: None,
+                                           src_mac: None,
+                                       });
+    }
+
+    /// Queues a request received as `ctx` describes.
+    pub fn push_request_with_context(&self, bytes: &[u8], ctx: RequestContext) {
+        self.lock().requests.push_back((bytes.to_vec(), ctx));
+    }
+
+    /// Removes and returns the packets sent so far, in order, with their destinations.
+    pub fn take_replies(&self) -> Vec<(Vec<u8>, SocketAddr)> {
+        std::mem::take(&mut self.lock().replies)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Queues> {
+        // Nothing can be left half updated, so a panic elsewhere does not matter
+        self.queues.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Transport for MockTransport {
+    fn recv_request(&self, buf: &mut [u8]) -> io::Result<(usize, RequestContext)> {
+        let (bytes, ctx) = self.lock()
+            .requests
+            .pop_front()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "No more requests"))?;
+        // Truncated like a datagram too long for the buffer
+        let n = bytes.len().min(buf.len());
+        buf[..n].copy_from_slice(&bytes[..n]);
+        Ok((n, ctx))
+    }
+
+    fn send_reply(&self, buf: &[u8], dst: SocketAddr) -> io::Result<usize> {
+        self.lock().replies.push((buf.to_vec(), dst));
+        Ok(buf.len())
+    }
+
+    /// 0.0.0.0:67, where a server usually listens.
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), SERVER_PORT))
+    }
+}
@@ -0,0 +1,60 @@
+//! Malformed packets must be rejected by `packet::parse` and `PacketRef::parse` with an error,
+//! never a panic.
+
+use dhcp4r::options::{DHCP_MESSAGE_TYPE, END, HOST_NAME, OVERLOAD};
+use dhcp4r::packet::{parse, PacketRef, ParseError};
+
+const COOKIE: [u8; 4] = [99, 130, 83, 99];
+
+/// A BOOTREQUEST header and cookie followed by `options`.
+fn request(options: &[u8]) -> Vec<u8> {
+    let mut b = vec![0; 236];
+    b[0] = 1;
+    b[1] = 1;
+    b[2] = 6;
+    b.extend_from_slice(&COOKIE);
+    b.extend_from_slice(options);
+    b
+}
+
+#[test]
+fn corpus() {
+    let mut bad_cookie = request(&[END]);
+    bad_cookie[239] = 0;
+    let mut bad_op = request(&[END]);
+    bad_op[0] = 3;
+    let mut bad_hlen = request(&[END]);
+    bad_hlen[2] = 17;
+    let mut bad_overload = request(&[OVERLOAD, 1, 3, END]);
+    bad_overload[108] = END;
+    bad_overload[44..46].clone_from_slice(&[HOST_NAME, 200]);
+
+    let corpus: Vec<(Vec<u8>, ParseError)> = vec![
+        (vec![], ParseError::TooShort),
+        (request(&[])[..239].to_vec(), ParseError::TooShort),
+        (bad_cookie, ParseError::BadMagicCookie),
+        (bad_op, ParseError::InvalidOpCode(3)),
+        (bad_hlen, ParseError::InvalidHardwareLength(17)),
+        (request(&[]), ParseError::UnterminatedOptions),
+        (request(&[DHCP_MESSAGE_TYPE, 1, 1]), ParseError::UnterminatedOptions),
+        (request(&[DHCP_MESSAGE_TYPE]), ParseError::UnterminatedOptions),
+        (request(&[DHCP_MESSAGE_TYPE, 2, 1]), ParseError::InvalidOptionLength),
+        (request(&[HOST_NAME, 255, b'a', END]), ParseError::InvalidOptionLength),
+        (bad_overload, ParseError::InvalidOptionLength),
+    ];
+    for (b, e) in corpus {
+        assert_eq!(parse(&b).err(), Some(e), "{:?}", b);
+        assert_eq!(PacketRef::parse(&b).err(), Some(e), "{:?}", b);
+    }
+}
+
+#[test]
+fn every_truncation() {
+    let b = request(&[DHCP_MESSAGE_TYPE, 1, 1, HOST_NAME, 3, b'a', b'b', b'c', END]);
+    assert!(parse(&b).is_ok());
+    assert!(PacketRef::parse(&b).is_ok());
+    for l in 0..b.len() {
+        assert!(parse(&b[..l]).is_err(), "length {}", l);
+        assert!(PacketRef::parse(&b[..l]).is_err(), "length {}", l);
+    }
+}